@@ -0,0 +1,45 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rsse::sse::parser::parse_line;
+
+// a typical llm stream, where almost every line is data
+fn llm_stream() -> Vec<String> {
+    let mut lines = Vec::new();
+    for i in 0..1000 {
+        lines.push(format!(
+            "data: {{\"id\":\"chatcmpl-{}\",\"choices\":[{{\"delta\":{{\"content\":\"token\"}}}}]}}\r\n",
+            i
+        ));
+        lines.push("\r\n".to_string());
+    }
+    lines.push("data: [DONE]\r\n".to_string());
+    lines
+}
+
+fn parse_data_lines(c: &mut Criterion) {
+    let lines = llm_stream();
+    c.bench_function("parse_line data only stream", |b| {
+        b.iter(|| {
+            for line in &lines {
+                let _ = black_box(parse_line(black_box(line)));
+            }
+        })
+    });
+}
+
+#[cfg(feature = "std")]
+fn from_line_data_lines(c: &mut Criterion) {
+    use rsse::sse::response::SseResponse;
+    let lines = llm_stream();
+    c.bench_function("SseResponse::from_line data only stream", |b| {
+        b.iter(|| {
+            for line in &lines {
+                let _ = black_box(SseResponse::from_line(black_box(line)));
+            }
+        })
+    });
+}
+#[cfg(not(feature = "std"))]
+fn from_line_data_lines(_c: &mut Criterion) {}
+
+criterion_group!(benches, parse_data_lines, from_line_data_lines);
+criterion_main!(benches);
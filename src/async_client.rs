@@ -0,0 +1,382 @@
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use tokio::io::AsyncRead;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    http::request::{Request, RequestBuilder},
+    sse::{
+        async_connector::{AsyncSseConnection, AsyncSseTlsConnector},
+        connector::SseConnectionError,
+        response::SseResponse,
+        subscriber::{
+            reconnect_delay, DoneWhen, HandleProgress, ReconnectCallback, ReconnectPolicy, Result,
+            SseHandler, SseMutHandler, SseSubscribeError, StreamContext, StreamState,
+        },
+    },
+};
+
+pub struct AsyncSseClient {
+    connector: AsyncSseTlsConnector,
+    // always Some
+    // Reason of Option, we need to take ownership of the RequestBuilder
+    req_builder: Option<RequestBuilder>,
+    last_retry_ms: Option<u32>,
+    options: StreamOptions,
+}
+// the options of the builder, applied to each send like the subscriber of the sync client
+#[derive(Debug, Default)]
+struct StreamOptions {
+    total_deadline: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    reconnect: Option<ReconnectPolicy>,
+    retry_on_status: Vec<u32>,
+    on_reconnect: Option<ReconnectCallback>,
+    done_when: Option<DoneWhen>,
+}
+impl AsyncSseClient {
+    pub(crate) fn new(connector: AsyncSseTlsConnector, req_builder: RequestBuilder) -> Self {
+        Self {
+            connector,
+            req_builder: Some(req_builder),
+            last_retry_ms: None,
+            options: StreamOptions::default(),
+        }
+    }
+    pub(crate) fn total_deadline(mut self, deadline: Option<Duration>) -> Self {
+        self.options.total_deadline = deadline;
+        self
+    }
+    pub(crate) fn idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.options.idle_timeout = idle_timeout;
+        self
+    }
+    pub(crate) fn reconnect(mut self, policy: Option<ReconnectPolicy>) -> Self {
+        self.options.reconnect = policy;
+        self
+    }
+    pub(crate) fn retry_on_status(mut self, statuses: &[u32]) -> Self {
+        self.options.retry_on_status = statuses.to_vec();
+        self
+    }
+    pub(crate) fn set_reconnect_callback(&mut self, callback: Option<ReconnectCallback>) {
+        self.options.on_reconnect = callback;
+    }
+    pub(crate) fn set_done_when(&mut self, done_when: Option<DoneWhen>) {
+        self.options.done_when = done_when;
+    }
+    pub async fn send<T, E, H: SseHandler<T, E>>(&mut self, handler: &H) -> Result<T, E> {
+        self.subscribe_with_ctx(|res, ctx| handler.handle_with_ctx(res, ctx), None)
+            .await?;
+        handler.result().map_err(SseSubscribeError::HandlerError)
+    }
+    pub async fn send_mut<T, E, H: SseMutHandler<T, E>>(
+        &mut self,
+        handler: &mut H,
+    ) -> Result<T, E> {
+        self.subscribe_with_ctx(|res, ctx| handler.handle_with_ctx(res, ctx), None)
+            .await?;
+        handler.result().map_err(SseSubscribeError::HandlerError)
+    }
+    // a hanging stream is stopped with Cancelled when the token is cancelled,
+    // and the events handled until then are kept by the handler
+    pub async fn send_mut_cancellable<T, E, H: SseMutHandler<T, E>>(
+        &mut self,
+        handler: &mut H,
+        token: &CancellationToken,
+    ) -> Result<T, E> {
+        self.subscribe_with_ctx(|res, ctx| handler.handle_with_ctx(res, ctx), Some(token))
+            .await?;
+        handler.result().map_err(SseSubscribeError::HandlerError)
+    }
+    pub async fn send_fn<E, F: Fn(SseResponse) -> HandleProgress<E>>(
+        &mut self,
+        f: F,
+    ) -> Result<(), E> {
+        self.subscribe_with_ctx(|res, _ctx| f(res), None).await
+    }
+    pub async fn send_mut_fn<E, F: FnMut(SseResponse) -> HandleProgress<E>>(
+        &mut self,
+        mut f: F,
+    ) -> Result<(), E> {
+        self.subscribe_with_ctx(|res, _ctx| f(res), None).await
+    }
+    pub fn last_retry_ms(&self) -> Option<u32> {
+        self.last_retry_ms
+    }
+    async fn subscribe_with_ctx<E>(
+        &mut self,
+        f: impl FnMut(SseResponse, &StreamContext) -> HandleProgress<E>,
+        token: Option<&CancellationToken>,
+    ) -> Result<(), E> {
+        let req = self.take_request();
+        let connector = &self.connector;
+        subscribe(
+            &self.options,
+            &mut self.last_retry_ms,
+            || connector.connect(&req),
+            f,
+            token,
+        )
+        .await
+    }
+    // build the request and reset the builder for the next request
+    fn take_request(&mut self) -> Request {
+        let req = self.req_builder.take().unwrap().build();
+        self.req_builder = Some(RequestBuilder::new(req.url()));
+        req
+    }
+    pub fn post(&mut self) -> &mut Self {
+        self.req_builder = Some(self.req_builder.take().unwrap().post());
+        self
+    }
+    pub fn bearer_auth(&mut self, token: &str) -> &mut Self {
+        self.req_builder = Some(self.req_builder.take().unwrap().bearer_auth(token));
+        self
+    }
+    pub fn host(&mut self, host: &str) -> &mut Self {
+        self.req_builder = Some(self.req_builder.take().unwrap().host(host));
+        self
+    }
+    pub fn header(&mut self, key: &str, value: &str) -> &mut Self {
+        self.req_builder = Some(self.req_builder.take().unwrap().header(key, value));
+        self
+    }
+    pub fn get(&mut self) -> &mut Self {
+        self.req_builder = Some(self.req_builder.take().unwrap().get());
+        self
+    }
+    pub fn json<S: serde::Serialize>(&mut self, json: S) -> &mut Self {
+        self.req_builder = Some(self.req_builder.take().unwrap().json(json));
+        self
+    }
+}
+
+// the same reconnect loop as the sync subscriber, with a new connection for each attempt
+async fn subscribe<S, E, Fut>(
+    options: &StreamOptions,
+    last_retry_ms: &mut Option<u32>,
+    mut connect: impl FnMut() -> Fut,
+    mut f: impl FnMut(SseResponse, &StreamContext) -> HandleProgress<E>,
+    token: Option<&CancellationToken>,
+) -> Result<(), E>
+where
+    S: AsyncRead + Unpin,
+    Fut: Future<Output = std::result::Result<AsyncSseConnection<S>, SseConnectionError>>,
+{
+    let deadline = options
+        .total_deadline
+        .map(|deadline| Instant::now() + deadline);
+    let mut ctx = StreamContext::default();
+    let mut attempt = 0;
+    loop {
+        let connected = tokio::select! {
+            biased;
+            _ = cancelled(token) => return Err(SseSubscribeError::Cancelled),
+            _ = sleep_until(deadline) => return Err(SseSubscribeError::DeadlineExceeded),
+            connection = connect() => connection,
+        };
+        let result = match connected {
+            Ok(mut connection) => {
+                let mut state =
+                    StreamState::new(&mut ctx, last_retry_ms, options.done_when.as_ref());
+                let idle_timeout = options.idle_timeout;
+                stream(
+                    &mut connection,
+                    &mut f,
+                    token,
+                    &mut state,
+                    deadline,
+                    idle_timeout,
+                )
+                .await
+            }
+            Err(e) => Err(e.into()),
+        };
+        let err = match result {
+            Err(SseSubscribeError::ConnectionError(err)) => err,
+            Err(SseSubscribeError::HttpError(res)) => SseConnectionError::HttpError(res),
+            result => return result,
+        };
+        let Some(delay) = reconnect_delay(
+            options.reconnect,
+            &options.retry_on_status,
+            *last_retry_ms,
+            attempt,
+            &err,
+        ) else {
+            return Err(err.into());
+        };
+        if deadline.is_some_and(|deadline| Instant::now() + delay >= deadline) {
+            return Err(SseSubscribeError::DeadlineExceeded);
+        }
+        attempt += 1;
+        if let Some(on_reconnect) = options.on_reconnect.as_ref() {
+            on_reconnect.call(attempt, &err, delay);
+        }
+        tokio::select! {
+            biased;
+            _ = cancelled(token) => return Err(SseSubscribeError::Cancelled),
+            _ = tokio::time::sleep(delay) => {}
+        }
+    }
+}
+async fn stream<S: AsyncRead + Unpin, E>(
+    connection: &mut AsyncSseConnection<S>,
+    f: &mut impl FnMut(SseResponse, &StreamContext) -> HandleProgress<E>,
+    token: Option<&CancellationToken>,
+    state: &mut StreamState<'_>,
+    deadline: Option<Instant>,
+    idle_timeout: Option<Duration>,
+) -> Result<(), E> {
+    loop {
+        // checked first, so that a cancel by the handler stops before the next read
+        let read = tokio::select! {
+            biased;
+            _ = cancelled(token) => return Err(SseSubscribeError::Cancelled),
+            _ = sleep_until(deadline) => return Err(SseSubscribeError::DeadlineExceeded),
+            _ = sleep_until(state.idle_deadline(idle_timeout)) => {
+                return Err(SseSubscribeError::IdleTimeout)
+            }
+            read = connection.read_until_dispatch() => read.map_err(SseSubscribeError::from)?,
+        };
+        if let Some(result) = state.handle(read, connection.bytes_read(), f) {
+            return result;
+        }
+    }
+}
+// never completes without a deadline
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+        None => std::future::pending().await,
+    }
+}
+// never completes without a token
+async fn cancelled(token: Option<&CancellationToken>) {
+    match token {
+        Some(token) => token.cancelled().await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::io::AsyncWriteExt;
+
+    use crate::sse::connector::ConnectionPhase;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn cancelされたstreamはそれまでのeventを処理してcancelledを返す() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        server
+            .write_all(
+                b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\ndata: Hello\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        // the server keeps the stream open, so it hangs without the cancel
+        let mut connection = Some(AsyncSseConnection::new(client, None));
+        let token = CancellationToken::new();
+        let mut received = Vec::new();
+
+        let result = subscribe(
+            &StreamOptions::default(),
+            &mut None,
+            || std::future::ready(Ok(connection.take().unwrap())),
+            |res, _ctx| {
+                received.push(res);
+                token.cancel();
+                HandleProgress::<()>::Progress
+            },
+            Some(&token),
+        )
+        .await;
+
+        assert!(matches!(result, Err(SseSubscribeError::Cancelled)));
+        assert_eq!(received, vec![SseResponse::Data("Hello".to_string())]);
+        drop(server);
+    }
+    #[tokio::test]
+    async fn builderのreconnectとdone_whenが適用される() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        server
+            .write_all(
+                b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\ndata: Hello\r\n\r\ndata: [DONE]\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        // the first attempt fails to connect, and the second one gets the stream
+        let mut connections = vec![
+            Ok(AsyncSseConnection::new(client, None)),
+            Err(SseConnectionError::ConnectError(
+                ConnectionPhase::Connect,
+                std::io::Error::from(std::io::ErrorKind::ConnectionRefused),
+            )),
+        ];
+        let reconnects = Arc::new(Mutex::new(Vec::new()));
+        let recorded = reconnects.clone();
+        let options = StreamOptions {
+            reconnect: Some(ReconnectPolicy {
+                max_attempts: 1,
+                delay: Duration::from_millis(10),
+            }),
+            on_reconnect: Some(ReconnectCallback::new(move |attempt, _err, _delay| {
+                recorded.lock().unwrap().push(attempt)
+            })),
+            done_when: Some(DoneWhen::new(|res| res == &SseResponse::data("[DONE]"))),
+            ..Default::default()
+        };
+        let mut received = Vec::new();
+
+        // the server keeps the stream open, so it hangs without done_when
+        let result = subscribe(
+            &options,
+            &mut None,
+            || std::future::ready(connections.pop().unwrap()),
+            |res, _ctx| {
+                received.push(res);
+                HandleProgress::<()>::Progress
+            },
+            None,
+        )
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(received, vec![SseResponse::data("Hello")]);
+        assert_eq!(*reconnects.lock().unwrap(), vec![1]);
+        drop(server);
+    }
+    #[tokio::test]
+    async fn eventが届かない場合はidle_timeoutでエラーを返す() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        server
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\n: ping\r\n\r\n")
+            .await
+            .unwrap();
+        let mut connection = Some(AsyncSseConnection::new(client, None));
+        let options = StreamOptions {
+            idle_timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+
+        let result = subscribe(
+            &options,
+            &mut None,
+            || std::future::ready(Ok(connection.take().unwrap())),
+            |_res, _ctx| HandleProgress::<()>::Progress,
+            None,
+        )
+        .await;
+
+        assert!(matches!(result, Err(SseSubscribeError::IdleTimeout)));
+        drop(server);
+    }
+}
@@ -1,9 +1,27 @@
+use std::{
+    convert::Infallible,
+    fmt::Debug,
+    io::Write,
+    sync::{mpsc::Sender, Arc, Mutex},
+    time::Duration,
+};
+
+use serde::de::DeserializeOwned;
+
 use crate::{
-    http::{request::RequestBuilder, url::Url},
+    http::{
+        header::HttpHeader,
+        request::{Request, RequestBuilder},
+        url::Url,
+    },
     sse::{
         connector::{SseConnectionError, SseConnector, SseTlsConnector, SseTlsConnectorBuilder},
         response::SseResponse,
-        subscriber::{HandleProgress, Result, SseHandler, SseMutHandler, SseSubscriber},
+        subscriber::{
+            DoneWhen, HandleProgress, ReconnectCallback, ReconnectPolicy, RequestTimeouts, Result,
+            SseHandler, SseMutHandler, SseSubscribeError, SseSubscriber, StreamContext,
+            StreamOutcome, TerminationReason,
+        },
     },
 };
 
@@ -13,31 +31,183 @@ pub struct SseClient<C: SseConnector> {
     // always Some
     // Reason of Option, we need to take ownership of the RequestBuilder
     req_builder: Option<RequestBuilder>,
+    token_provider: Option<TokenProvider>,
 }
 impl<C: SseConnector> SseClient<C> {
+    pub(crate) fn new(connector: C, url: &Url) -> Self {
+        Self {
+            subscriber: SseSubscriber::new(connector),
+            req_builder: Some(RequestBuilder::new(url)),
+            token_provider: None,
+        }
+    }
+    // for custom connectors like fakes, unix sockets or replays, which are not built by SseClientBuilder
+    pub fn with_connector(connector: C, url: Url) -> Self {
+        Self::new(connector, &url)
+    }
+    pub(crate) fn url(&self) -> &Url {
+        self.req_builder.as_ref().unwrap().url()
+    }
+    pub(crate) fn into_connector(self) -> C {
+        self.subscriber.into_connector()
+    }
+    // the connector and the builder of the next request, to swap or inspect them
+    pub fn into_parts(self) -> (C, RequestBuilder) {
+        (self.subscriber.into_connector(), self.req_builder.unwrap())
+    }
+    // the inverse of into_parts, so that the pending request is kept with the swapped connector
+    pub fn from_parts(connector: C, req_builder: RequestBuilder) -> Self {
+        Self {
+            subscriber: SseSubscriber::new(connector),
+            req_builder: Some(req_builder),
+            token_provider: None,
+        }
+    }
     pub fn send<T, E, H: SseHandler<T, E>>(&mut self, handler: &H) -> Result<T, E> {
-        let req = self.req_builder.take().unwrap().build();
-        self.req_builder = Some(RequestBuilder::new(req.url()));
+        let req = self.take_request()?;
         self.subscriber.subscribe(&req, handler)
     }
     pub fn send_mut_fn<E, F: FnMut(SseResponse) -> HandleProgress<E>>(
         &mut self,
         f: F,
     ) -> Result<(), E> {
-        let req = self.req_builder.take().unwrap().build();
-        self.req_builder = Some(RequestBuilder::new(req.url()));
+        let req = self.take_request()?;
         self.subscriber.subscribe_mut_fn(&req, f)
     }
     pub fn send_fn<E, F: Fn(SseResponse) -> HandleProgress<E>>(&mut self, f: F) -> Result<(), E> {
-        let req = self.req_builder.take().unwrap().build();
-        self.req_builder = Some(RequestBuilder::new(req.url()));
+        let req = self.take_request()?;
         self.subscriber.subscribe_fn(&req, f)
     }
     pub fn send_mut<T, E, H: SseMutHandler<T, E>>(&mut self, handler: &mut H) -> Result<T, E> {
-        let req = self.req_builder.take().unwrap().build();
-        self.req_builder = Some(RequestBuilder::new(req.url()));
+        let req = self.take_request()?;
         self.subscriber.subscribe_mut(&req, handler)
     }
+    // unlike send_mut, the partial result of the handler and why the stream ended are returned
+    pub fn send_mut_outcome<T, E, H: SseMutHandler<T, E>>(
+        &mut self,
+        handler: &mut H,
+    ) -> StreamOutcome<T, E> {
+        let mut observer = StopObserver {
+            handler,
+            stopped: false,
+        };
+        let result = self.send_mut(&mut observer);
+        let (result, reason) = match result {
+            Ok(result) if observer.stopped => (Some(result), TerminationReason::HandlerStopped),
+            Ok(result) => (Some(result), TerminationReason::Completed),
+            Err(SseSubscribeError::DeadlineExceeded | SseSubscribeError::IdleTimeout) => {
+                (observer.handler.result().ok(), TerminationReason::Timeout)
+            }
+            Err(e) => (observer.handler.result().ok(), TerminationReason::Error(e)),
+        };
+        StreamOutcome { result, reason }
+    }
+    // the handler is returned back so that its state can be inspected after the stream
+    pub fn send_owned<T, E, H: SseMutHandler<T, E>>(
+        &mut self,
+        mut handler: H,
+    ) -> (Result<T, E>, H) {
+        let result = self.send_mut(&mut handler);
+        (result, handler)
+    }
+    pub fn pipe_to<W: Write>(&mut self, sink: W) -> std::result::Result<(), SseConnectionError> {
+        let req = self.take_request()?;
+        self.subscriber.pipe_to(&req, sink)
+    }
+    // writes only the data payloads, like streaming the text of a llm response to stdout
+    pub fn data_to_writer<W: Write>(
+        &mut self,
+        sink: W,
+    ) -> std::result::Result<(), SseConnectionError> {
+        let req = self.take_request()?;
+        self.subscriber.data_to_writer(&req, sink)
+    }
+    // deserialize each data until the `[DONE]` sentinel, like streaming responses of llm apis.
+    // the end marker of done_when takes the place of the sentinel when it is configured
+    pub fn send_json_stream<T: DeserializeOwned>(
+        &mut self,
+        on_invalid: OnInvalidJson,
+    ) -> Result<Vec<T>, serde_json::Error> {
+        let mut items = Vec::new();
+        let ends_on_sentinel = !self.subscriber.has_done_when();
+        self.send_mut_fn(|res| {
+            if ends_on_sentinel && res.is_done_sentinel() {
+                return HandleProgress::Done;
+            }
+            let SseResponse::Data(data) = res else {
+                return HandleProgress::Progress;
+            };
+            match serde_json::from_str(&data) {
+                Ok(item) => {
+                    items.push(item);
+                    HandleProgress::Progress
+                }
+                Err(_) if on_invalid == OnInvalidJson::Skip => HandleProgress::Progress,
+                Err(e) => HandleProgress::Err(e),
+            }
+        })?;
+        Ok(items)
+    }
+    // the stream ends cleanly when the receiver is dropped
+    pub fn send_to_channel(
+        &mut self,
+        tx: Sender<SseResponse>,
+    ) -> std::result::Result<(), SseConnectionError> {
+        self.send_fn(|res| match tx.send(res) {
+            Ok(()) => HandleProgress::<Infallible>::Progress,
+            Err(_) => HandleProgress::Done,
+        })
+        .map_err(SseConnectionError::from)
+    }
+    pub fn on_heartbeat(&mut self, f: impl FnMut() + Send + 'static) -> &mut Self {
+        self.subscriber.on_heartbeat(f);
+        self
+    }
+    pub fn on_open(&mut self, f: impl FnMut(u32, &HttpHeader) + Send + 'static) -> &mut Self {
+        self.subscriber.on_open(f);
+        self
+    }
+    pub fn on_progress(&mut self, f: impl FnMut(usize, usize) + Send + 'static) -> &mut Self {
+        self.subscriber.on_progress(f);
+        self
+    }
+    pub fn last_retry_ms(&self) -> Option<u32> {
+        self.subscriber.last_retry_ms()
+    }
+    // probe the reused connection before sending, so that a closed one can be replaced
+    pub fn is_healthy(&mut self) -> bool {
+        self.subscriber.is_healthy()
+    }
+    // the timeouts of the builder are overridden for the next request only,
+    // which opens its own connection so that the connect timeout applies to it
+    pub fn with_timeouts(&mut self, connect: Duration, read: Duration) -> &mut Self {
+        self.subscriber
+            .request_timeouts(RequestTimeouts { connect, read });
+        self
+    }
+    // build the request and reset the builder for the next request
+    // the token of the provider is fetched for each request and each reconnect of it.
+    // when the provider fails, the builder is kept for the next request
+    fn take_request(&mut self) -> std::result::Result<Request, SseConnectionError> {
+        let req_builder = self.req_builder.take().unwrap();
+        let Some(provider) = self.token_provider.clone() else {
+            let req = req_builder.build();
+            self.req_builder = Some(RequestBuilder::new(req.url()));
+            return Ok(req);
+        };
+        let req = match provider.authorize(req_builder.clone()) {
+            Ok(authorized) => authorized.build(),
+            Err(e) => {
+                self.req_builder = Some(req_builder);
+                return Err(e);
+            }
+        };
+        self.req_builder = Some(RequestBuilder::new(req.url()));
+        self.subscriber.rebuild_request_on_reconnect(move || {
+            Ok(provider.authorize(req_builder.clone())?.build())
+        });
+        Ok(req)
+    }
     pub fn post(&mut self) -> &mut Self {
         self.req_builder = Some(self.req_builder.take().unwrap().post());
         self
@@ -46,6 +216,15 @@ impl<C: SseConnector> SseClient<C> {
         self.req_builder = Some(self.req_builder.take().unwrap().bearer_auth(token));
         self
     }
+    pub fn host(&mut self, host: &str) -> &mut Self {
+        self.req_builder = Some(self.req_builder.take().unwrap().host(host));
+        self
+    }
+    // the path is reset to the path of the url after the request
+    pub fn path(&mut self, path: &str) -> &mut Self {
+        self.req_builder = Some(self.req_builder.take().unwrap().path(path));
+        self
+    }
     pub fn header(&mut self, key: &str, value: &str) -> &mut Self {
         self.req_builder = Some(self.req_builder.take().unwrap().header(key, value));
         self
@@ -54,52 +233,317 @@ impl<C: SseConnector> SseClient<C> {
         self.req_builder = Some(self.req_builder.take().unwrap().get());
         self
     }
+    pub fn clear_body(&mut self) -> &mut Self {
+        self.req_builder = Some(self.req_builder.take().unwrap().clear_body());
+        self
+    }
     pub fn json<S: serde::Serialize>(&mut self, json: S) -> &mut Self {
         self.req_builder = Some(self.req_builder.take().unwrap().json(json));
         self
     }
+    pub fn json_streaming<S: serde::Serialize + Send + Sync + 'static>(
+        &mut self,
+        json: S,
+    ) -> &mut Self {
+        self.req_builder = Some(self.req_builder.take().unwrap().json_streaming(json));
+        self
+    }
 }
 
+// records whether the handler stopped the stream by itself
+struct StopObserver<'a, H> {
+    handler: &'a mut H,
+    stopped: bool,
+}
+impl<T, E, H: SseMutHandler<T, E>> SseMutHandler<T, E> for StopObserver<'_, H> {
+    fn handle(&mut self, res: SseResponse) -> HandleProgress<E> {
+        self.handle_with_ctx(res, &StreamContext::default())
+    }
+    fn handle_with_ctx(&mut self, res: SseResponse, ctx: &StreamContext) -> HandleProgress<E> {
+        let progress = self.handler.handle_with_ctx(res, ctx);
+        self.stopped = progress.is_done();
+        progress
+    }
+    fn result(&self) -> std::result::Result<T, E> {
+        self.handler.result()
+    }
+}
+
+type ProvideToken =
+    dyn FnMut() -> std::result::Result<String, Box<dyn std::error::Error + Send + Sync>> + Send;
+#[derive(Clone)]
+struct TokenProvider(Arc<Mutex<ProvideToken>>);
+impl TokenProvider {
+    fn authorize(
+        &self,
+        req_builder: RequestBuilder,
+    ) -> std::result::Result<RequestBuilder, SseConnectionError> {
+        let token = (self.0.lock().unwrap())().map_err(SseConnectionError::TokenProviderError)?;
+        Ok(req_builder.bearer_auth(&token))
+    }
+}
+impl Debug for TokenProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TokenProvider")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnInvalidJson {
+    Skip,
+    Abort,
+}
+
+impl SseClient<SseTlsConnector> {
+    // the simplest EventSource like client, a GET request through the proxy of HTTPS_PROXY if any
+    pub fn get_stream(url: &str) -> std::result::Result<Self, SseConnectionError> {
+        let builder = SseClientBuilder::event_source(url, proxy_from_env())?;
+        let connector = builder.connector_builder.clone().build()?;
+        Ok(builder.client(connector))
+    }
+    pub fn peer_certificates(&self) -> Option<Vec<rustls::Certificate>> {
+        self.subscriber.connector().peer_certificates()
+    }
+}
+
+fn proxy_from_env() -> Option<String> {
+    ["HTTPS_PROXY", "https_proxy"]
+        .iter()
+        .find_map(|key| std::env::var(key).ok())
+        .filter(|proxy| !proxy.is_empty())
+}
+
+#[derive(Clone)]
 pub struct SseClientBuilder {
-    url: Url,
     connector_builder: SseTlsConnectorBuilder,
     req_builder: RequestBuilder,
+    total_deadline: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    reconnect: Option<ReconnectPolicy>,
+    retry_on_status: Vec<u32>,
+    on_reconnect: Option<ReconnectCallback>,
+    done_when: Option<DoneWhen>,
+    token_provider: Option<TokenProvider>,
 }
 impl SseClientBuilder {
     pub fn new(url: impl Into<Url>) -> SseClientBuilder {
         let url = url.into();
         SseClientBuilder {
-            url: url.clone(),
             connector_builder: SseTlsConnectorBuilder::new(&url),
             req_builder: RequestBuilder::new(&url),
+            total_deadline: None,
+            idle_timeout: None,
+            reconnect: None,
+            retry_on_status: Vec::new(),
+            on_reconnect: None,
+            done_when: None,
+            token_provider: None,
+        }
+    }
+    fn event_source(
+        url: &str,
+        proxy: Option<String>,
+    ) -> std::result::Result<Self, SseConnectionError> {
+        let url: Url = url.try_into()?;
+        let builder = Self::new(&url);
+        match proxy {
+            Some(proxy) => builder.proxy(proxy.as_str()),
+            None => Ok(builder),
         }
     }
 }
 
 impl SseClientBuilder {
-    pub fn proxy(self, proxy: &Url) -> std::result::Result<SseClientBuilder, SseConnectionError> {
+    pub fn proxy<U>(self, proxy: U) -> std::result::Result<SseClientBuilder, SseConnectionError>
+    where
+        U: TryInto<Url>,
+        SseConnectionError: From<U::Error>,
+    {
+        let proxy: Url = proxy.try_into()?;
         let connector_builder = self.connector_builder.proxy(proxy);
 
         Ok(SseClientBuilder {
-            url: self.url.clone(),
             connector_builder,
-            req_builder: self.req_builder,
+            ..self
         })
     }
+    pub fn proxies(self, proxies: Vec<Url>) -> SseClientBuilder {
+        let connector_builder = self.connector_builder.proxies(proxies);
+        SseClientBuilder {
+            connector_builder,
+            ..self
+        }
+    }
+    pub fn direct_fallback(self, direct_fallback: bool) -> SseClientBuilder {
+        let connector_builder = self.connector_builder.direct_fallback(direct_fallback);
+        SseClientBuilder {
+            connector_builder,
+            ..self
+        }
+    }
     pub fn add_ca(self, ca: &str) -> std::result::Result<SseClientBuilder, SseConnectionError> {
         let connector_builder = self.connector_builder.add_ca(ca);
         Ok(SseClientBuilder {
-            url: self.url.clone(),
             connector_builder,
-            req_builder: self.req_builder,
+            ..self
         })
     }
+    pub fn pin_sha256(self, pin: &[u8; 32]) -> Self {
+        let connector_builder = self.connector_builder.pin_sha256(pin);
+        SseClientBuilder {
+            connector_builder,
+            ..self
+        }
+    }
+    pub fn lossy_utf8(self, lossy_utf8: bool) -> Self {
+        let connector_builder = self.connector_builder.lossy_utf8(lossy_utf8);
+        SseClientBuilder {
+            connector_builder,
+            ..self
+        }
+    }
+    pub fn stream_on_error_status(self, stream_on_error_status: bool) -> Self {
+        let connector_builder = self
+            .connector_builder
+            .stream_on_error_status(stream_on_error_status);
+        SseClientBuilder {
+            connector_builder,
+            ..self
+        }
+    }
+    pub fn proxy_header(self, key: &str, value: &str) -> Self {
+        let connector_builder = self.connector_builder.proxy_header(key, value);
+        SseClientBuilder {
+            connector_builder,
+            ..self
+        }
+    }
+    pub fn proxy_keep_alive(self, proxy_keep_alive: bool) -> Self {
+        let connector_builder = self.connector_builder.proxy_keep_alive(proxy_keep_alive);
+        SseClientBuilder {
+            connector_builder,
+            ..self
+        }
+    }
+    pub fn max_headers(self, max_headers: usize) -> Self {
+        let connector_builder = self.connector_builder.max_headers(max_headers);
+        SseClientBuilder {
+            connector_builder,
+            ..self
+        }
+    }
+    pub fn max_header_bytes(self, max_header_bytes: usize) -> Self {
+        let connector_builder = self.connector_builder.max_header_bytes(max_header_bytes);
+        SseClientBuilder {
+            connector_builder,
+            ..self
+        }
+    }
+    pub fn max_stream_bytes(self, max_stream_bytes: usize) -> Self {
+        let connector_builder = self.connector_builder.max_stream_bytes(max_stream_bytes);
+        SseClientBuilder {
+            connector_builder,
+            ..self
+        }
+    }
+    pub fn connect_timeout(self, timeout: Duration) -> Self {
+        let connector_builder = self.connector_builder.connect_timeout(timeout);
+        SseClientBuilder {
+            connector_builder,
+            ..self
+        }
+    }
+    pub fn read_timeout(self, timeout: Duration) -> Self {
+        let connector_builder = self.connector_builder.read_timeout(timeout);
+        SseClientBuilder {
+            connector_builder,
+            ..self
+        }
+    }
+    // deadline of the whole stream of a send, which is not extended by reconnects
+    pub fn total_deadline(mut self, deadline: Duration) -> Self {
+        self.total_deadline = Some(deadline);
+        self
+    }
+    // fail when no event is dispatched within the window, even if comments or fields arrive
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+    // reconnect up to max_attempts times when the connection is failed
+    pub fn reconnect(mut self, max_attempts: u32, delay: Duration) -> Self {
+        self.reconnect = Some(ReconnectPolicy {
+            max_attempts,
+            delay,
+        });
+        self
+    }
+    // reconnect on the error statuses too, like 502/503/504 from a flaky gateway.
+    // attempts are limited by the reconnect policy
+    pub fn retry_on_status(mut self, statuses: &[u32]) -> Self {
+        self.retry_on_status = statuses.to_vec();
+        self
+    }
+    // called with the attempt number, the error and the delay before each reconnection
+    pub fn on_reconnect(
+        mut self,
+        f: impl FnMut(u32, &SseConnectionError, Duration) + Send + 'static,
+    ) -> Self {
+        self.on_reconnect = Some(ReconnectCallback::new(f));
+        self
+    }
+    // terminate the stream on a custom end marker, like `event: end`
+    pub fn done_when(mut self, f: impl FnMut(&SseResponse) -> bool + Send + 'static) -> Self {
+        self.done_when = Some(DoneWhen::new(f));
+        self
+    }
+    pub fn validate(&self) -> std::result::Result<(), SseConnectionError> {
+        self.connector_builder.validate()
+    }
     pub fn build(self) -> SseClient<SseTlsConnector> {
+        let connector = self.connector_builder.clone().build().unwrap();
+        self.client(connector)
+    }
+    fn client<C: SseConnector>(self, connector: C) -> SseClient<C> {
+        let mut subscriber = SseSubscriber::new(connector)
+            .total_deadline(self.total_deadline)
+            .idle_timeout(self.idle_timeout)
+            .reconnect(self.reconnect)
+            .retry_on_status(&self.retry_on_status);
+        subscriber.set_reconnect_callback(self.on_reconnect);
+        subscriber.set_done_when(self.done_when);
         SseClient {
-            subscriber: SseSubscriber::new(self.connector_builder.build().unwrap()),
+            subscriber,
             req_builder: Some(self.req_builder),
+            token_provider: self.token_provider,
         }
     }
+    // the same configuration can be shared with build, so clone the builder to get both clients.
+    // the stream options are applied to the async client too, except bearer_auth_fn
+    #[cfg(feature = "tokio")]
+    pub fn build_async(
+        self,
+    ) -> std::result::Result<crate::async_client::AsyncSseClient, SseConnectionError> {
+        let mut client = crate::async_client::AsyncSseClient::new(
+            self.connector_builder.build_async()?,
+            self.req_builder,
+        )
+        .total_deadline(self.total_deadline)
+        .idle_timeout(self.idle_timeout)
+        .reconnect(self.reconnect)
+        .retry_on_status(&self.retry_on_status);
+        client.set_reconnect_callback(self.on_reconnect);
+        client.set_done_when(self.done_when);
+        Ok(client)
+    }
+    // establish the connection and complete the tls handshake before the first request
+    pub fn connect_now(
+        self,
+    ) -> std::result::Result<SseClient<SseTlsConnector>, SseConnectionError> {
+        let mut connector = self.connector_builder.clone().build()?;
+        connector.handshake()?;
+        Ok(self.client(connector))
+    }
     pub fn post(mut self) -> Self {
         let new_req_builder = self.req_builder.post();
         self.req_builder = new_req_builder;
@@ -110,11 +554,66 @@ impl SseClientBuilder {
         self.req_builder = new_req_builder;
         self
     }
+    pub fn json_streaming<S: serde::Serialize + Send + Sync + 'static>(mut self, json: S) -> Self {
+        self.req_builder = self.req_builder.json_streaming(json);
+        self
+    }
+    pub fn try_json<S: serde::Serialize>(
+        mut self,
+        json: S,
+    ) -> std::result::Result<Self, serde_json::Error> {
+        self.req_builder = self.req_builder.try_json(json)?;
+        Ok(self)
+    }
+    pub fn host(mut self, host: &str) -> Self {
+        let new_req_builder = self.req_builder.host(host);
+        self.req_builder = new_req_builder;
+        self
+    }
+    pub fn absolute_form(mut self, absolute_form: bool) -> Self {
+        let new_req_builder = self.req_builder.absolute_form(absolute_form);
+        self.req_builder = new_req_builder;
+        self
+    }
+    pub fn crlf(mut self, crlf: bool) -> Self {
+        self.req_builder = self.req_builder.crlf(crlf);
+        self
+    }
     pub fn header(mut self, key: &str, value: &str) -> Self {
         let new_req_builder = self.req_builder.header(key, value);
         self.req_builder = new_req_builder;
         self
     }
+    pub fn headers<K, V>(mut self, headers: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.req_builder = self.req_builder.headers(headers);
+        self
+    }
+    pub fn origin(mut self, origin: &str) -> Self {
+        let new_req_builder = self.req_builder.origin(origin);
+        self.req_builder = new_req_builder;
+        self
+    }
+    pub fn referer(mut self, referer: &str) -> Self {
+        let new_req_builder = self.req_builder.referer(referer);
+        self.req_builder = new_req_builder;
+        self
+    }
+    // the provider is called before each request and each reconnect of the blocking client,
+    // like refreshing a short-lived token.
+    // its error aborts the request with TokenProviderError instead of sending a stale token
+    pub fn bearer_auth_fn<E: Into<Box<dyn std::error::Error + Send + Sync>>>(
+        mut self,
+        mut f: impl FnMut() -> std::result::Result<String, E> + Send + 'static,
+    ) -> Self {
+        self.token_provider = Some(TokenProvider(Arc::new(Mutex::new(move || {
+            f().map_err(Into::into)
+        }))));
+        self
+    }
     pub fn bearer_auth(mut self, token: &str) -> Self {
         let new_req_builder = self.req_builder.bearer_auth(token);
         self.req_builder = new_req_builder;
@@ -126,17 +625,523 @@ impl SseClientBuilder {
 mod tests {
     use super::*;
     use crate::sse::{
-        connector::chatgpt::{chatgpt_key, message, GptHandler, URL},
+        connector::{
+            chatgpt::{chatgpt_key, message, GptHandler, URL},
+            fakes::{FakeSseConnector, FakeTcpConnection},
+            DynSseConnector,
+        },
         response::SseResponse,
-        subscriber::HandleProgress,
+        subscriber::{fakes::MockMutHandler, HandleProgress},
     };
 
+    #[test]
+    fn 任意のconnectorからclientを生成できる() {
+        let mut connector = FakeSseConnector::new();
+        connector.set_response("HTTP/1.1 200 OK\r\n");
+        connector.set_response("Content-Type: text/event-stream\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: Hello\r\n");
+        connector.set_response("\r\n");
+        let mut sut = SseClient::with_connector(connector, "https://www.fake".try_into().unwrap());
+        let mut handler = MockMutHandler::new();
+
+        sut.send_mut(&mut handler).unwrap();
+
+        handler.assert_received(&[SseResponse::Data("Hello".to_string())]);
+        assert_eq!(sut.url(), &"https://www.fake".try_into().unwrap());
+    }
+    #[test]
+    fn requestごとのread_timeoutはbuilderの設定より優先される() {
+        let mut connector = FakeSseConnector::new().read_timeout(Duration::from_secs(1));
+        connector.set_response("HTTP/1.1 200 OK\r\n");
+        connector.set_response("Content-Type: text/event-stream\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: Hello\r\n");
+        let mut sut = SseClient::with_connector(connector, "https://www.fake".try_into().unwrap());
+
+        sut.with_timeouts(Duration::from_secs(5), Duration::from_secs(30))
+            .send_mut(&mut MockMutHandler::new())
+            .unwrap();
+        sut.send_mut(&mut MockMutHandler::new()).unwrap();
+
+        let connector = sut.into_connector();
+        assert_eq!(
+            connector.applied_read_timeouts(),
+            &[
+                Some(Duration::from_secs(30)),
+                Some(Duration::from_secs(30)),
+                Some(Duration::from_secs(30)),
+                Some(Duration::from_secs(30)),
+                Some(Duration::from_secs(30)),
+                // the next request is back to the default of the builder
+                Some(Duration::from_secs(1)),
+            ]
+        );
+        assert_eq!(connector.connect_timeout(), None);
+    }
+    #[test]
+    fn requestごとのconnect_timeoutは最初の接続から適用される() {
+        let mut connector = FakeSseConnector::new();
+        connector.set_response("HTTP/1.1 200 OK\r\n");
+        connector.set_response("Content-Type: text/event-stream\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: Hello\r\n");
+        let mut sut = SseClient::with_connector(connector, "https://www.fake".try_into().unwrap());
+
+        sut.with_timeouts(Duration::from_secs(5), Duration::from_secs(30))
+            .send_mut(&mut MockMutHandler::new())
+            .unwrap();
+        sut.send_mut(&mut MockMutHandler::new()).unwrap();
+
+        // the next request keeps the connection of the build
+        assert_eq!(
+            sut.into_connector().reconnect_timeouts(),
+            &[Some(Duration::from_secs(5))]
+        );
+    }
+    #[test]
+    fn token_providerが失敗した場合requestは送信されずerrorを返す() {
+        let mut sut = SseClientBuilder::new(&"https://www.fake".try_into().unwrap())
+            .bearer_auth_fn(|| Err("refresh token expired"))
+            .client(FakeSseConnector::new());
+
+        let result = sut.send_mut(&mut MockMutHandler::new());
+
+        let Err(SseSubscribeError::ConnectionError(e)) = result else {
+            panic!("expected token provider error, but got {:?}", result);
+        };
+        assert_eq!(
+            e.to_string(),
+            "bearer token provider failed: refresh token expired"
+        );
+        assert!(std::error::Error::source(&e).is_some());
+        assert_eq!(sut.into_connector().connected_times(), 0);
+    }
+    #[test]
+    fn reconnectのたびにtoken_providerからtokenを取得し直す() {
+        let mut connector = FakeSseConnector::new();
+        connector.set_read_error(std::io::ErrorKind::ConnectionReset);
+        let mut issued = 0;
+        let mut sut = SseClientBuilder::new(&"https://www.fake".try_into().unwrap())
+            .bearer_auth_fn(move || {
+                issued += 1;
+                Ok::<_, &str>(format!("token-{}", issued))
+            })
+            .reconnect(1, Duration::from_millis(1))
+            .client(connector);
+
+        let result = sut.send_mut(&mut MockMutHandler::new());
+
+        assert!(result.is_err());
+        let sent = String::from_utf8_lossy(sut.into_connector().written()).to_string();
+        assert!(sent.contains("Bearer token-1\r\n"));
+        assert!(sent.contains("Bearer token-2\r\n"));
+    }
+    #[test]
+    fn into_partsで取り出したconnectorからclientを作り直せる() {
+        let mut connector = FakeSseConnector::new();
+        connector.set_response("HTTP/1.1 200 OK\r\n");
+        connector.set_response("Content-Type: text/event-stream\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: Hello\r\n");
+        let mut sut = SseClient::with_connector(connector, "https://www.fake".try_into().unwrap());
+        sut.post().json(vec![1, 2, 3]);
+
+        let (connector, req_builder) = sut.into_parts();
+        let mut sut = SseClient::from_parts(connector, req_builder);
+        let mut handler = MockMutHandler::new();
+        sut.send_mut(&mut handler).unwrap();
+
+        handler.assert_received(&[SseResponse::Data("Hello".to_string())]);
+        let connector = sut.into_connector();
+        let sent = String::from_utf8_lossy(connector.written()).to_string();
+        assert!(sent.starts_with("POST / HTTP/1.1\r\n"));
+        assert!(sent.ends_with("[1,2,3]"));
+        assert_eq!(connector.connected_times(), 1);
+    }
+    #[test]
+    fn serverに閉じられたconnectionはhealthyではない() {
+        let connector = FakeSseConnector::new();
+        let mut sut = SseClient::with_connector(connector, "https://www.fake".try_into().unwrap());
+        assert!(sut.is_healthy());
+
+        let mut connector = sut.into_connector();
+        connector.close();
+        let mut sut = SseClient::with_connector(connector, "https://www.fake".try_into().unwrap());
+
+        assert!(!sut.is_healthy());
+    }
+    #[test]
+    fn sseのストリームをそのままwriterに流し込める() {
+        let mut connector = FakeSseConnector::new();
+        connector.set_response("HTTP/1.1 200 OK\r\n");
+        connector.set_response("Content-Type: text/event-stream\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: Hello\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: World!\r\n");
+        connector.set_response("\r\n");
+        let mut sut = SseClient {
+            subscriber: SseSubscriber::new(connector),
+            req_builder: Some(RequestBuilder::new(&"https://www.fake".try_into().unwrap())),
+            token_provider: None,
+        };
+        let mut sink = Vec::new();
+
+        sut.pipe_to(&mut sink).unwrap();
+
+        assert_eq!(sink, b"data: Hello\r\n\r\ndata: World!\r\n\r\n".to_vec());
+    }
+    // counts data until `stop`, and fails on `error`
+    struct CountHandler {
+        count: usize,
+    }
+    impl SseMutHandler<usize, String> for CountHandler {
+        fn handle(&mut self, res: SseResponse) -> HandleProgress<String> {
+            match res {
+                SseResponse::Data(data) if data == "stop" => HandleProgress::Done,
+                SseResponse::Data(data) if data == "error" => HandleProgress::Err(data),
+                SseResponse::Data(_) => {
+                    self.count += 1;
+                    std::thread::sleep(Duration::from_millis(10));
+                    HandleProgress::Progress
+                }
+                _ => HandleProgress::Progress,
+            }
+        }
+        fn result(&self) -> std::result::Result<usize, String> {
+            Ok(self.count)
+        }
+    }
+    fn outcome_of(data: &[&str], deadline: Option<Duration>) -> StreamOutcome<usize, String> {
+        let mut connector = FakeSseConnector::new();
+        connector.set_response("HTTP/1.1 200 OK\r\n");
+        connector.set_response("Content-Type: text/event-stream\r\n");
+        connector.set_response("\r\n");
+        for data in data {
+            connector.set_response(&format!("data: {}\r\n", data));
+            connector.set_response("\r\n");
+        }
+        let mut sut = SseClient {
+            subscriber: SseSubscriber::new(connector).total_deadline(deadline),
+            req_builder: Some(RequestBuilder::new(&"https://www.fake".try_into().unwrap())),
+            token_provider: None,
+        };
+        sut.send_mut_outcome(&mut CountHandler { count: 0 })
+    }
+    #[test]
+    fn streamの終了理由と途中までの結果を取得できる() {
+        let completed = outcome_of(&["a", "b"], None);
+        assert_eq!(completed.result, Some(2));
+        assert!(matches!(completed.reason, TerminationReason::Completed));
+
+        let stopped = outcome_of(&["a", "stop", "b"], None);
+        assert_eq!(stopped.result, Some(1));
+        assert!(matches!(stopped.reason, TerminationReason::HandlerStopped));
+
+        let error = outcome_of(&["a", "error", "b"], None);
+        assert_eq!(error.result, Some(1));
+        assert!(matches!(
+            error.reason,
+            TerminationReason::Error(SseSubscribeError::HandlerError(_))
+        ));
+
+        let timeout = outcome_of(&["a"; 100], Some(Duration::from_millis(50)));
+        assert!(timeout.result.is_some_and(|count| count < 100));
+        assert!(matches!(timeout.reason, TerminationReason::Timeout));
+    }
+    #[derive(serde::Deserialize, Debug, PartialEq)]
+    struct Delta {
+        content: String,
+    }
+    fn json_stream_connector() -> FakeSseConnector {
+        let mut connector = FakeSseConnector::new();
+        connector.set_response("HTTP/1.1 200 OK\r\n");
+        connector.set_response("Content-Type: text/event-stream\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: {\"content\":\"Hello\"}\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: not json\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: {\"content\":\"World\"}\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: [DONE]\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: {\"content\":\"after done\"}\r\n");
+        connector
+    }
+    #[test]
+    fn done_sentinelまでのjsonをまとめて取得できる() {
+        let mut sut = SseClient {
+            subscriber: SseSubscriber::new(json_stream_connector()),
+            req_builder: Some(RequestBuilder::new(&"https://www.fake".try_into().unwrap())),
+            token_provider: None,
+        };
+
+        let result = sut.send_json_stream::<Delta>(OnInvalidJson::Skip).unwrap();
+
+        assert_eq!(
+            result,
+            vec![
+                Delta {
+                    content: "Hello".to_string()
+                },
+                Delta {
+                    content: "World".to_string()
+                },
+            ]
+        );
+    }
+    #[test]
+    fn done_whenが設定されている場合はそれまでのjsonを取得する() {
+        let mut connector = json_stream_connector();
+        connector.set_response("\r\n");
+        connector.set_response("event: end\r\n");
+        let mut subscriber = SseSubscriber::new(connector);
+        subscriber.done_when(|res| res == &SseResponse::event("end"));
+        let mut sut = SseClient {
+            subscriber,
+            req_builder: Some(RequestBuilder::new(&"https://www.fake".try_into().unwrap())),
+            token_provider: None,
+        };
+
+        let result = sut.send_json_stream::<Delta>(OnInvalidJson::Skip).unwrap();
+
+        // the sentinel is only an invalid json for the api with its own end marker
+        assert_eq!(result.len(), 3);
+        assert_eq!(result[2].content, "after done");
+    }
+    #[test]
+    fn abortの場合は不正なjsonでエラーを返す() {
+        let mut sut = SseClient {
+            subscriber: SseSubscriber::new(json_stream_connector()),
+            req_builder: Some(RequestBuilder::new(&"https://www.fake".try_into().unwrap())),
+            token_provider: None,
+        };
+
+        let result = sut.send_json_stream::<Delta>(OnInvalidJson::Abort);
+
+        assert!(matches!(
+            result,
+            Err(crate::sse::subscriber::SseSubscribeError::HandlerError(_))
+        ));
+    }
+    #[test]
+    fn eventをchannelに送信できる() {
+        let mut connector = FakeSseConnector::new();
+        connector.set_response("HTTP/1.1 200 OK\r\n");
+        connector.set_response("Content-Type: text/event-stream\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("event: greeting\r\n");
+        connector.set_response("data: Hello\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: World!\r\n");
+        connector.set_response("\r\n");
+        let mut sut = SseClient {
+            subscriber: SseSubscriber::new(connector),
+            req_builder: Some(RequestBuilder::new(&"https://www.fake".try_into().unwrap())),
+            token_provider: None,
+        };
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        sut.send_to_channel(tx).unwrap();
+
+        assert_eq!(
+            rx.iter().collect::<Vec<_>>(),
+            vec![
+                SseResponse::Event("greeting".to_string()),
+                SseResponse::Data("Hello".to_string()),
+                SseResponse::Data("World!".to_string()),
+            ]
+        );
+    }
+    #[test]
+    fn receiverがdropされた場合はstreamを正常に終了する() {
+        let mut connector = FakeSseConnector::new();
+        connector.set_response("HTTP/1.1 200 OK\r\n");
+        connector.set_response("Content-Type: text/event-stream\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: Hello\r\n");
+        connector.set_response("\r\n");
+        let mut sut = SseClient {
+            subscriber: SseSubscriber::new(connector),
+            req_builder: Some(RequestBuilder::new(&"https://www.fake".try_into().unwrap())),
+            token_provider: None,
+        };
+        let (tx, rx) = std::sync::mpsc::channel();
+        drop(rx);
+
+        assert!(sut.send_to_channel(tx).is_ok());
+    }
+    #[test]
+    fn channelへの送信中のhttp_errorはそのまま返す() {
+        let mut connector = FakeSseConnector::new();
+        connector.set_response("HTTP/1.1 500 Internal Server Error\r\n");
+        connector.set_response("Content-Length: 5\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("error");
+        let mut sut = SseClient::with_connector(connector, "https://www.fake".try_into().unwrap());
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        let result = sut.send_to_channel(tx);
+
+        let Err(SseConnectionError::HttpError(res)) = result else {
+            panic!("expected http error, but got {:?}", result);
+        };
+        assert_eq!(res.status_code(), 500);
+        assert_eq!(rx.iter().count(), 0);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn build_asyncは接続せずにclientを生成する() {
+        let sut = SseClientBuilder::new(&"https://www.fake".try_into().unwrap())
+            .proxy("http://localhost:8080")
+            .unwrap()
+            .read_timeout(Duration::from_secs(1));
+
+        assert!(sut.build_async().is_ok());
+    }
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    #[ignore = "実際の通信を行うため"]
+    async fn 同じbuilderから同期と非同期のclientを生成できる() {
+        let builder = SseClientBuilder::new(&URL.try_into().unwrap())
+            .post()
+            .json(message("Hello"))
+            .bearer_auth(&chatgpt_key());
+        let mut sync_client = builder.clone().build();
+        let mut async_client = builder.build_async().unwrap();
+        let mut sync_handler = GptHandler::new();
+        let mut async_handler = GptHandler::new();
+
+        let sync_result = sync_client.send_mut(&mut sync_handler).unwrap();
+        let async_result = async_client.send_mut(&mut async_handler).await.unwrap();
+
+        assert!(sync_result.len() > 0);
+        assert!(async_result.len() > 0);
+    }
+    #[test]
+    fn dyn_sse_connectorのclientはstructのfieldに保持できる() {
+        struct App {
+            client: SseClient<DynSseConnector>,
+        }
+        let mut socket = FakeTcpConnection::new();
+        socket.set_response("HTTP/1.1 200 OK\r\n");
+        socket.set_response("Content-Type: text/event-stream\r\n");
+        socket.set_response("\r\n");
+        socket.set_response("data: Hello\r\n");
+        let mut app = App {
+            client: SseClient {
+                subscriber: SseSubscriber::new(DynSseConnector::new(socket)),
+                req_builder: Some(RequestBuilder::new(&"https://www.fake".try_into().unwrap())),
+                token_provider: None,
+            },
+        };
+        let mut handler = MockMutHandler::new();
+
+        app.client.send_mut(&mut handler).unwrap();
+
+        handler.assert_received(&[SseResponse::Data("Hello".to_string())]);
+    }
+    #[test]
+    fn send_ownedはhandlerを返す() {
+        let mut connector = FakeSseConnector::new();
+        connector.set_response("HTTP/1.1 200 OK\r\n");
+        connector.set_response("Content-Type: text/event-stream\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: Hello\r\n");
+        connector.set_response("data: World!\r\n");
+        let mut sut = SseClient {
+            subscriber: SseSubscriber::new(connector),
+            req_builder: Some(RequestBuilder::new(&"https://www.fake".try_into().unwrap())),
+            token_provider: None,
+        };
+
+        let (result, handler) = sut.send_owned(MockMutHandler::new());
+
+        assert!(result.is_ok());
+        handler.assert_received(&[
+            SseResponse::Data("Hello".to_string()),
+            SseResponse::Data("World!".to_string()),
+        ]);
+    }
+    #[test]
+    fn connect_nowは接続できない場合にエラーを返す() {
+        let sut = SseClientBuilder::new(&"https://host.invalid".try_into().unwrap());
+
+        let Err(SseConnectionError::DnsError(_)) = sut.connect_now() else {
+            panic!("expected DnsError");
+        };
+    }
+    #[test]
+    fn get_streamはevent_streamのgetリクエストを送る() {
+        let sut = SseClientBuilder::event_source("https://www.fake/stream", None).unwrap();
+
+        assert_eq!(
+            sut.req_builder.build().bytes(),
+            b"GET /stream HTTP/1.1\r\nHost: www.fake\r\nAccept: text/event-stream\r\nConnection: close\r\n\r\n"
+        );
+        assert!(SseClientBuilder::event_source(
+            "https://www.fake",
+            Some("http://localhost:8080".to_string())
+        )
+        .unwrap()
+        .validate()
+        .is_ok());
+        let Err(SseConnectionError::InvalidUrl(_)) = SseClient::get_stream("not a url") else {
+            panic!("expected InvalidUrl");
+        };
+    }
+    #[test]
+    fn 正しい設定はvalidateに成功する() {
+        let sut = SseClientBuilder::new(&"https://www.fake".try_into().unwrap())
+            .proxy("http://localhost:8080")
+            .unwrap();
+
+        assert!(sut.validate().is_ok());
+    }
+    #[test]
+    fn 存在しないca_fileはvalidateに失敗する() {
+        let sut = SseClientBuilder::new(&"https://www.fake".try_into().unwrap())
+            .add_ca("not_exist_ca.pem")
+            .unwrap();
+
+        let Err(SseConnectionError::CAFileIOError(_)) = sut.validate() else {
+            panic!("expected CAFileIOError");
+        };
+    }
+    #[test]
+    fn httpのurlはvalidateに失敗する() {
+        let sut = SseClientBuilder::new(&"http://www.fake".try_into().unwrap());
+
+        let Err(SseConnectionError::InvalidUrl(_)) = sut.validate() else {
+            panic!("expected InvalidUrl error");
+        };
+    }
+    #[test]
+    fn 不正なurlはvalidateに失敗する() {
+        let sut = SseClientBuilder::new(&"https://".try_into().unwrap());
+
+        let Err(SseConnectionError::InvalidUrl(_)) = sut.validate() else {
+            panic!("expected InvalidUrl error");
+        };
+    }
+    #[test]
+    fn 不正なproxyのurlはinvalid_urlのエラーになる() {
+        let result =
+            SseClientBuilder::new(&"https://www.fake".try_into().unwrap()).proxy("localhost:8080");
+
+        let Err(SseConnectionError::InvalidUrl(_)) = result else {
+            panic!("expected InvalidUrl error");
+        };
+    }
     #[test]
     #[ignore = "dockerによるproxyが必要のため"]
     fn proxyに対して通信可能() {
         let mut gpt_handler = GptHandler::new();
         let mut sut = SseClientBuilder::new(&URL.try_into().unwrap())
-            .proxy(&"http://localhost:8080".try_into().unwrap())
+            .proxy("http://localhost:8080")
             .unwrap()
             .post()
             .json(message("Hello"))
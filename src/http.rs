@@ -1,6 +1,6 @@
 pub(crate) mod body;
-pub(crate) mod header;
+pub mod header;
 pub(crate) mod request;
-pub(crate) mod response;
+pub mod response;
 pub(crate) mod status_line;
 pub mod url;
@@ -1,27 +1,66 @@
-use std::{collections::HashMap, fmt::Display};
+use std::fmt::Display;
 
-#[derive(Debug, PartialEq, Clone)]
+// headers are kept in the received order, so that the serialized response is stable
+#[derive(Debug, PartialEq, Clone, Default)]
 pub struct HttpHeader {
-    headers: HashMap<String, String>,
+    headers: Vec<(String, String)>,
 }
 
 impl HttpHeader {
     pub fn new() -> Self {
         HttpHeader {
-            headers: HashMap::new(),
+            headers: Vec::new(),
         }
     }
     pub fn get(&self, key: &str) -> Option<&str> {
-        self.headers.get(key).map(|v| v.as_str())
+        self.headers
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
     }
+    pub(crate) fn get_ignore_case(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get_ignore_case(key).is_some()
+    }
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let index = self
+            .headers
+            .iter()
+            .position(|(k, _)| k.eq_ignore_ascii_case(key))?;
+        Some(self.headers.remove(index).1)
+    }
+    // media type parameters like charset are ignored
+    pub fn is_event_stream(&self) -> bool {
+        self.get_ignore_case("Content-Type")
+            .and_then(|value| value.split(';').next())
+            .map(|media_type| media_type.trim().eq_ignore_ascii_case("text/event-stream"))
+            .unwrap_or(false)
+    }
+    // the value of an existing key is replaced in place, and a new key is appended
     pub fn insert(&mut self, key: &str, value: &str) {
-        self.headers.insert(key.to_string(), value.to_string());
+        match self.headers.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value.to_string(),
+            None => self.headers.push((key.to_string(), value.to_string())),
+        }
+    }
+    // obs-fold: a continuation line is appended to the value of the previous header
+    pub fn append_folded(&mut self, key: &str, continuation: &str) {
+        if let Some((_, value)) = self.headers.iter_mut().rev().find(|(k, _)| k == key) {
+            value.push(' ');
+            value.push_str(continuation.trim());
+        }
     }
     pub fn concat(&mut self, other: Self) {
-        self.headers.extend(other.headers);
+        for (key, value) in other.headers {
+            self.insert(&key, &value);
+        }
     }
     pub fn from_line(line: &str) -> Result<Self, HttpHeaderError> {
-        let mut headers = HashMap::new();
         let mut iter = line.splitn(2, ":");
         let key = iter.next().ok_or(HttpHeaderError::InvalidFormat(format!(
             "Invalid format: {}",
@@ -31,8 +70,9 @@ impl HttpHeader {
             "Invalid format: {}",
             line,
         )))?;
-        headers.insert(key.trim().to_string(), value.trim().to_string());
-        Ok(HttpHeader { headers })
+        Ok(HttpHeader {
+            headers: vec![(key.trim().to_string(), value.trim().to_string())],
+        })
     }
     pub fn to_string(&self) -> String {
         self.headers.iter().fold(String::new(), |acc, (k, v)| {
@@ -41,6 +81,40 @@ impl HttpHeader {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitInfo {
+    remaining: Option<u64>,
+    reset: Option<u64>,
+    retry_after: Option<u64>,
+}
+impl RateLimitInfo {
+    pub fn from_headers(headers: &HttpHeader) -> Option<Self> {
+        let parse = |key: &str| {
+            headers
+                .get_ignore_case(key)
+                .and_then(|v| v.trim().parse::<u64>().ok())
+        };
+        let info = Self {
+            remaining: parse("X-RateLimit-Remaining"),
+            reset: parse("X-RateLimit-Reset"),
+            retry_after: parse("Retry-After"),
+        };
+        if info.remaining.is_none() && info.reset.is_none() && info.retry_after.is_none() {
+            return None;
+        }
+        Some(info)
+    }
+    pub fn remaining(&self) -> Option<u64> {
+        self.remaining
+    }
+    pub fn reset(&self) -> Option<u64> {
+        self.reset
+    }
+    pub fn retry_after(&self) -> Option<u64> {
+        self.retry_after
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum HttpHeaderError {
     InvalidFormat(String),
@@ -66,6 +140,37 @@ mod header_tests {
         assert_eq!(sut.to_string(), format!("{}{}", header, "\r\n"));
     }
     #[test]
+    fn headerは大文字小文字を区別せずに削除できる() {
+        let mut sut = HttpHeader::new();
+        sut.insert("Connection", "keep-alive");
+
+        let removed = sut.remove("connection");
+
+        assert_eq!(removed, Some("keep-alive".to_string()));
+        assert!(!sut.contains_key("Connection"));
+    }
+    #[test]
+    fn 存在しないheaderの削除はnoneを返す() {
+        let mut sut = HttpHeader::new();
+        sut.insert("Content-Type", "text/event-stream");
+
+        let removed = sut.remove("Connection");
+
+        assert_eq!(removed, None);
+        assert!(sut.contains_key("content-type"));
+    }
+    #[test]
+    fn content_typeのパラメータを無視してevent_streamか判定できる() {
+        let mut sut = HttpHeader::new();
+        sut.insert("content-type", "text/event-stream; charset=utf-8");
+
+        assert!(sut.is_event_stream());
+
+        sut.insert("content-type", "application/json");
+
+        assert!(!sut.is_event_stream());
+    }
+    #[test]
     fn 不正な文字列の場合はエラー() {
         let status_line = "HTTP/1.1 200 OK\n\r";
         let sut = HttpHeader::from_line(status_line);
@@ -88,4 +193,24 @@ mod header_tests {
 
         assert_eq!(header.get("Content-Type").unwrap(), "text/html");
     }
+    #[test]
+    fn rate_limitのheaderを数値として取得可能() {
+        let mut header = HttpHeader::new();
+        header.insert("x-ratelimit-remaining", "42");
+        header.insert("X-RateLimit-Reset", "1700000000");
+        header.insert("Retry-After", "30");
+
+        let sut = RateLimitInfo::from_headers(&header).unwrap();
+
+        assert_eq!(sut.remaining(), Some(42));
+        assert_eq!(sut.reset(), Some(1700000000));
+        assert_eq!(sut.retry_after(), Some(30));
+    }
+    #[test]
+    #[allow(non_snake_case)]
+    fn rate_limitのheaderが無い場合はNoneを返す() {
+        let header = HttpHeader::from_line("Content-Type: text/html").unwrap();
+
+        assert_eq!(RateLimitInfo::from_headers(&header), None);
+    }
 }
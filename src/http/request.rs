@@ -1,10 +1,75 @@
-use std::collections::BTreeMap;
+use std::{
+    collections::BTreeMap,
+    fmt::{Debug, Display},
+    io::Write,
+    sync::Arc,
+};
 
 use super::url::Url;
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct Request {
     value: String,
     url: Url,
+    streaming_body: Option<StreamingBody>,
+}
+type WriteBody = dyn Fn(&mut dyn Write) -> std::io::Result<()> + Send + Sync;
+// body written after the head of the request, without building it in memory
+#[derive(Clone)]
+pub(crate) struct StreamingBody(Arc<WriteBody>);
+impl PartialEq for StreamingBody {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+impl Debug for StreamingBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("StreamingBody")
+    }
+}
+// credentials in the request line are redacted, so requests can be logged safely
+impl Debug for Request {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = self
+            .value
+            .split_inclusive('\n')
+            .map(|line| match line.split_once(':') {
+                Some((key, value)) if is_credential(key) => {
+                    let eol = &value[value.trim_end().len()..];
+                    format!("{}: {}{}", key, redact(value.trim()), eol)
+                }
+                _ => line.to_string(),
+            })
+            .collect::<String>();
+        f.debug_struct("Request")
+            .field("value", &value)
+            .field("url", &self.url)
+            .field("streaming_body", &self.streaming_body)
+            .finish()
+    }
+}
+fn is_body_header(key: &str) -> bool {
+    ["Content-Length", "Content-Type", "Transfer-Encoding"]
+        .iter()
+        .any(|header| key.eq_ignore_ascii_case(header))
+}
+fn is_credential(key: &str) -> bool {
+    key.eq_ignore_ascii_case("Authorization") || key.eq_ignore_ascii_case("Proxy-Authorization")
+}
+// keep the scheme like `Bearer` to tell which credential is used
+fn redact(value: &str) -> String {
+    match value.split_once(' ') {
+        Some((scheme, _)) => format!("{} ***", scheme),
+        None => "***".to_string(),
+    }
+}
+fn redact_headers(headers: &BTreeMap<String, String>) -> BTreeMap<&String, String> {
+    headers
+        .iter()
+        .map(|(key, value)| match is_credential(key) {
+            true => (key, redact(value)),
+            false => (key, value.clone()),
+        })
+        .collect()
 }
 impl Request {
     pub fn bytes(&self) -> &[u8] {
@@ -13,27 +78,118 @@ impl Request {
     pub fn url(&self) -> &Url {
         &self.url
     }
+    // the head is written at once, and a streaming body is written in chunks after it
+    pub fn write_to<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        writer.write_all(self.bytes())?;
+        match &self.streaming_body {
+            Some(StreamingBody(write_body)) => write_body(&mut writer),
+            None => writer.flush(),
+        }
+    }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+const CHUNK_SIZE: usize = 8 * 1024;
+// writes the body with the chunked transfer coding, buffering at most CHUNK_SIZE bytes
+pub struct ChunkedWriter<W: Write> {
+    writer: W,
+    buf: Vec<u8>,
+}
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            buf: Vec::with_capacity(CHUNK_SIZE),
+        }
+    }
+    // write the rest of the body and the last chunk
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.write_chunk()?;
+        self.writer.write_all(b"0\r\n\r\n")?;
+        self.writer.flush()
+    }
+    fn write_chunk(&mut self) -> std::io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let mut chunk = format!("{:x}\r\n", self.buf.len()).into_bytes();
+        chunk.append(&mut self.buf);
+        chunk.extend_from_slice(b"\r\n");
+        self.writer.write_all(&chunk)
+    }
+}
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let size = buf.len().min(CHUNK_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&buf[..size]);
+        if self.buf.len() == CHUNK_SIZE {
+            self.write_chunk()?;
+        }
+        Ok(size)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.write_chunk()?;
+        self.writer.flush()
+    }
+}
+
+#[derive(PartialEq, Clone)]
 pub struct RequestBuilder {
     url: Url,
     method: HttpMethod,
+    host: Option<String>,
+    absolute_form: bool,
     headers: BTreeMap<String, String>,
+    // headers only for the CONNECT request, so that origin credentials are not sent to proxies
+    proxy_headers: BTreeMap<String, String>,
     body: String,
+    // request target path overriding the path of the url
+    path: Option<String>,
+    streaming_body: Option<StreamingBody>,
+    // error while building, which is reported by try_build
+    error: Option<BuildError>,
+    crlf: bool,
+}
+impl Debug for RequestBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RequestBuilder")
+            .field("url", &self.url)
+            .field("method", &self.method)
+            .field("host", &self.host)
+            .field("absolute_form", &self.absolute_form)
+            .field("headers", &redact_headers(&self.headers))
+            .field("proxy_headers", &redact_headers(&self.proxy_headers))
+            .field("body", &self.body)
+            .field("path", &self.path)
+            .field("streaming_body", &self.streaming_body)
+            .field("error", &self.error)
+            .field("crlf", &self.crlf)
+            .finish()
+    }
 }
 impl RequestBuilder {
     pub fn new(url: impl Into<Url>) -> Self {
         Self {
             url: url.into(),
             method: HttpMethod::Get,
+            host: None,
+            absolute_form: false,
             headers: BTreeMap::new(),
+            proxy_headers: BTreeMap::new(),
             body: String::new(),
+            path: None,
+            streaming_body: None,
+            error: None,
+            crlf: true,
         }
     }
-    pub fn get(mut self) -> Self {
-        self.method = HttpMethod::Get;
-        self
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+    // a GET has no body, so the body left by a previous POST is dropped with its headers
+    pub fn get(self) -> Self {
+        let mut new = self.clear_body();
+        new.method = HttpMethod::Get;
+        new
     }
     pub fn post(mut self) -> Self {
         self.method = HttpMethod::Post;
@@ -43,15 +199,61 @@ impl RequestBuilder {
         Self {
             url: self.url.clone(),
             method: HttpMethod::Connect,
-            headers: self.headers.clone(),
+            host: None,
+            absolute_form: false,
+            headers: self.proxy_headers.clone(),
+            proxy_headers: BTreeMap::new(),
             body: String::new(),
+            path: None,
+            streaming_body: None,
+            error: None,
+            crlf: true,
         }
         .build()
     }
+    // override only the path of the request target, the connection target is not changed
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_string());
+        self
+    }
+    pub fn host(mut self, host: &str) -> Self {
+        self.host = Some(host.to_string());
+        self
+    }
+    // lines of the request end with `\n` instead of `\r\n` when false.
+    // it is not standard, but some minimal servers and tools accept only `\n`
+    pub fn crlf(mut self, crlf: bool) -> Self {
+        self.crlf = crlf;
+        self
+    }
+    // absolute-form request target like `GET http://host/path HTTP/1.1` for forward proxies
+    pub fn absolute_form(mut self, absolute_form: bool) -> Self {
+        self.absolute_form = absolute_form;
+        self
+    }
     pub fn header(mut self, key: &str, value: &str) -> Self {
         self.headers.insert(key.to_string(), value.to_string());
         self
     }
+    // header of the CONNECT request, which is not sent to the origin
+    pub fn proxy_header(mut self, key: &str, value: &str) -> Self {
+        self.proxy_headers
+            .insert(key.to_string(), value.to_string());
+        self
+    }
+    // convenient when the headers come from a config map
+    pub fn headers<K, V>(mut self, headers: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.headers.extend(
+            headers
+                .into_iter()
+                .map(|(key, value)| (key.into(), value.into())),
+        );
+        self
+    }
     fn header_string(&self) -> String {
         self.headers
             .iter()
@@ -59,16 +261,65 @@ impl RequestBuilder {
                 acc.push_str(key);
                 acc.push_str(": ");
                 acc.push_str(value);
-                acc.push_str("\r\n");
+                acc.push_str(self.eol());
                 acc
             })
     }
+    fn eol(&self) -> &'static str {
+        if self.crlf {
+            "\r\n"
+        } else {
+            "\n"
+        }
+    }
+    // serialization error is deferred to try_build
     pub fn json<T: serde::Serialize>(self, json: T) -> Self {
+        match serde_json::to_string(&json) {
+            Ok(body) => self.json_body(body),
+            Err(e) => Self {
+                error: Some(BuildError::Json(e.to_string())),
+                ..self
+            },
+        }
+    }
+    // for large bodies, the json is serialized into the socket with the chunked transfer coding
+    // instead of being built as a string
+    pub fn json_streaming<T: serde::Serialize + Send + Sync + 'static>(self, json: T) -> Self {
+        let mut new = self
+            .header("Content-Type", "application/json")
+            .header("Transfer-Encoding", "chunked");
+        new.headers.remove("Content-Length");
+        new.body = String::new();
+        new.streaming_body = Some(StreamingBody(Arc::new(move |writer| {
+            let mut chunked = ChunkedWriter::new(writer);
+            serde_json::to_writer(&mut chunked, &json)?;
+            chunked.finish()
+        })));
+        new
+    }
+    // the serialization error of the body is dropped too
+    pub fn clear_body(mut self) -> Self {
+        self.body = String::new();
+        self.streaming_body = None;
+        self.error = None;
+        self.headers.retain(|key, _| !is_body_header(key));
+        self
+    }
+    pub fn try_json<T: serde::Serialize>(self, json: T) -> Result<Self, serde_json::Error> {
+        let body = serde_json::to_string(&json)?;
+        Ok(self.json_body(body))
+    }
+    fn json_body(self, body: String) -> Self {
         let mut new = self.header("Content-Type", "application/json");
-        new.body = serde_json::to_string(&json).unwrap();
+        new.body = body;
         let len = new.body.len();
-        let new = new.header("Content-Length", len.to_string().as_str());
-        new
+        new.header("Content-Length", len.to_string().as_str())
+    }
+    pub fn origin(self, origin: &str) -> Self {
+        self.header("Origin", origin)
+    }
+    pub fn referer(self, referer: &str) -> Self {
+        self.header("Referer", referer)
     }
     pub fn bearer_auth(mut self, token: &str) -> Self {
         self.headers
@@ -76,52 +327,121 @@ impl RequestBuilder {
         self
     }
     pub fn build(self) -> Request {
-        Request {
+        self.try_build().unwrap()
+    }
+    pub fn try_build(mut self) -> Result<Request, BuildError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        self.sync_content_length();
+        Ok(Request {
             value: self.to_request(),
             url: self.url.clone(),
+            streaming_body: self.streaming_body,
+        })
+    }
+    // the length is recomputed from the final body, so a stale or wrong value is never sent.
+    // a streaming body is chunked, so it has no length
+    fn sync_content_length(&mut self) {
+        let had_length = self.remove_content_length();
+        if self.streaming_body.is_none() && (had_length || !self.body.is_empty()) {
+            self.headers
+                .insert("Content-Length".to_string(), self.body.len().to_string());
         }
     }
+    fn remove_content_length(&mut self) -> bool {
+        let before = self.headers.len();
+        self.headers
+            .retain(|key, _| !key.eq_ignore_ascii_case("Content-Length"));
+        before != self.headers.len()
+    }
+    fn host_header(&self) -> &str {
+        self.host.as_deref().unwrap_or(self.url.host())
+    }
+    // default accept header, which is omitted when the accept header is set explicitly
+    fn accept_header(&self) -> String {
+        if self
+            .headers
+            .keys()
+            .any(|key| key.eq_ignore_ascii_case("Accept"))
+        {
+            return String::new();
+        }
+        format!("Accept: text/event-stream{}", self.eol())
+    }
+    fn request_target(&self) -> String {
+        let url = match self.path.as_deref() {
+            Some(path) => self.url.with_path(path),
+            None => self.url.clone(),
+        };
+        if self.absolute_form {
+            return url.to_string();
+        }
+        url.path().to_string()
+    }
     fn to_request(&self) -> String {
+        let eol = self.eol();
         let mut request = String::new();
         request.push_str(self.method.to_str());
         request.push_str(" ");
         match self.method {
             HttpMethod::Get => {
-                request.push_str(self.url.path());
-                request.push_str(" HTTP/1.1\r\n");
+                request.push_str(&self.request_target());
+                request.push_str(" HTTP/1.1");
+                request.push_str(eol);
                 request.push_str("Host: ");
-                request.push_str(self.url.host());
-                request.push_str("\r\n");
-                request.push_str("Connection: close\r\n");
-                request.push_str("\r\n");
+                request.push_str(self.host_header());
+                request.push_str(eol);
+                request.push_str(&self.accept_header());
+                request.push_str("Connection: close");
+                request.push_str(eol);
+                request.push_str(self.header_string().as_str());
+                request.push_str(eol);
             }
             HttpMethod::Post => {
-                request.push_str(self.url.path());
-                request.push_str(" HTTP/1.1\r\n");
+                request.push_str(&self.request_target());
+                request.push_str(" HTTP/1.1");
+                request.push_str(eol);
                 request.push_str("Host: ");
-                request.push_str(self.url.host());
-                request.push_str("\r\n");
-                request.push_str("Accept: text/event-stream\r\n");
-                request.push_str("Connection: keep-alive\r\n");
+                request.push_str(self.host_header());
+                request.push_str(eol);
+                request.push_str(&self.accept_header());
+                request.push_str("Connection: keep-alive");
+                request.push_str(eol);
                 request.push_str(self.header_string().as_str());
-                request.push_str("\r\n");
+                request.push_str(eol);
                 request.push_str(self.body.as_str());
             }
             HttpMethod::Connect => {
                 request.push_str(self.url.host());
                 request.push_str(&format!(":{}", self.url.port()));
-                request.push_str(" HTTP/1.1\r\n");
+                request.push_str(" HTTP/1.1");
+                request.push_str(eol);
                 request.push_str("Host: ");
                 request.push_str(self.url.host());
                 request.push_str(&format!(":{}", self.url.port()));
-                request.push_str("\r\n");
-                request.push_str("\r\n");
+                request.push_str(eol);
+                request.push_str(self.header_string().as_str());
+                request.push_str(eol);
             }
         }
         request
     }
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub enum BuildError {
+    Json(String),
+}
+impl Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::Json(message) => write!(f, "Failed to serialize json body: {}", message),
+        }
+    }
+}
+impl std::error::Error for BuildError {}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 enum HttpMethod {
     Get,
@@ -146,6 +466,112 @@ mod tests {
 
     use super::*;
     #[test]
+    fn 大きなjsonはbody全体を保持せずにchunkで書き込める() {
+        #[derive(Default)]
+        struct RecordingWriter {
+            written: Vec<u8>,
+            max_write: usize,
+        }
+        impl Write for RecordingWriter {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.max_write = self.max_write.max(buf.len());
+                self.written.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let url = Url::from_str("https://localhost/test").unwrap();
+        let json = vec!["a".repeat(100); 1000];
+        let request = RequestBuilder::new(url)
+            .post()
+            .json_streaming(json.clone())
+            .build();
+        let mut writer = RecordingWriter::default();
+
+        request.write_to(&mut writer).unwrap();
+
+        let written = String::from_utf8(writer.written).unwrap();
+        let (head, body) = written.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("Transfer-Encoding: chunked"));
+        assert!(!head.contains("Content-Length"));
+        let response = crate::http::response::HttpResponse::parse(&format!(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{}",
+            body
+        ))
+        .unwrap();
+        assert_eq!(response.body_str(), serde_json::to_string(&json).unwrap());
+        assert!(body.len() > CHUNK_SIZE * 10);
+        assert!(writer.max_write <= CHUNK_SIZE + 16);
+    }
+    #[test]
+    fn postからgetに切り替えるとbodyとbodyのheaderを取り除く() {
+        let url = Url::from_str("https://localhost/test").unwrap();
+        let post = RequestBuilder::new(url)
+            .post()
+            .bearer_auth("token")
+            .json(vec![1, 2, 3]);
+
+        let get = post.clone().get().to_request();
+        let streaming = post.json_streaming(vec![1, 2, 3]).get().build();
+
+        assert_eq!(
+            get,
+            "GET /test HTTP/1.1\r\nHost: localhost\r\nAccept: text/event-stream\r\nConnection: close\r\nAuthorization: Bearer token\r\n\r\n"
+        );
+        let mut written = Vec::new();
+        streaming.write_to(&mut written).unwrap();
+        assert_eq!(written, get.as_bytes());
+    }
+    #[test]
+    fn clear_bodyでpostのbodyを取り除ける() {
+        let url = Url::from_str("https://localhost/test").unwrap();
+        let request = RequestBuilder::new(url)
+            .post()
+            .header("content-length", "7")
+            .json(vec![1, 2, 3])
+            .clear_body()
+            .to_request();
+        assert_eq!(
+            request,
+            "POST /test HTTP/1.1\r\nHost: localhost\r\nAccept: text/event-stream\r\nConnection: keep-alive\r\n\r\n"
+        )
+    }
+    #[test]
+    fn crlfを無効にするとlfのみの改行でrequestを生成する() {
+        let url = Url::from_str("https://localhost/test").unwrap();
+        let sut = RequestBuilder::new(url)
+            .post()
+            .crlf(false)
+            .bearer_auth("token")
+            .json(vec![1, 2, 3]);
+
+        let debug = format!("{:?}", sut.clone().build());
+        let request = sut.to_request();
+
+        assert_eq!(
+            request,
+            "POST /test HTTP/1.1\nHost: localhost\nAccept: text/event-stream\nConnection: keep-alive\nAuthorization: Bearer token\nContent-Length: 7\nContent-Type: application/json\n\n[1,2,3]"
+        );
+        assert!(debug.contains("Authorization: Bearer ***\\n"), "{}", debug);
+    }
+    #[test]
+    fn 誤ったcontent_lengthはbuild時にbodyの長さに補正される() {
+        let url = Url::from_str("https://localhost/test").unwrap();
+        let sut = RequestBuilder::new(url)
+            .post()
+            .json(vec![1, 2, 3])
+            .header("content-length", "100");
+
+        let request = sut.build();
+
+        assert_eq!(
+            String::from_utf8_lossy(request.bytes()),
+            "POST /test HTTP/1.1\r\nHost: localhost\r\nAccept: text/event-stream\r\nConnection: keep-alive\r\nContent-Length: 7\r\nContent-Type: application/json\r\n\r\n[1,2,3]"
+        );
+    }
+    #[test]
     fn bodyにjsonを追加できる() {
         let url = Url::from_str("https://localhost/test").unwrap();
         let request = RequestBuilder::new(url)
@@ -158,6 +584,68 @@ mod tests {
         )
     }
     #[test]
+    fn jsonへのserializeに失敗する場合はtry_jsonはエラーを返す() {
+        struct Unserializable;
+        impl serde::Serialize for Unserializable {
+            fn serialize<S: serde::Serializer>(&self, _serializer: S) -> Result<S::Ok, S::Error> {
+                Err(serde::ser::Error::custom("unserializable"))
+            }
+        }
+        let url = Url::from_str("https://localhost/test").unwrap();
+
+        let result = RequestBuilder::new(url).post().try_json(Unserializable);
+
+        assert!(result.is_err());
+    }
+    #[test]
+    fn jsonのserializeの失敗はtry_buildでエラーとして返す() {
+        struct Unserializable;
+        impl serde::Serialize for Unserializable {
+            fn serialize<S: serde::Serializer>(&self, _serializer: S) -> Result<S::Ok, S::Error> {
+                Err(serde::ser::Error::custom("unserializable"))
+            }
+        }
+        let url = Url::from_str("https://localhost/test").unwrap();
+
+        let result = RequestBuilder::new(url)
+            .post()
+            .json(Unserializable)
+            .bearer_auth("token")
+            .try_build();
+
+        assert_eq!(
+            result.unwrap_err(),
+            BuildError::Json("unserializable".to_string())
+        );
+    }
+    #[test]
+    fn 複数のheaderをまとめて追加できる() {
+        let url = Url::from_str("https://localhost/test").unwrap();
+        let headers = std::collections::HashMap::from([
+            ("X-Api-Key".to_string(), "key".to_string()),
+            ("X-Request-Id".to_string(), "1".to_string()),
+        ]);
+
+        let request = RequestBuilder::new(url).get().headers(headers).to_request();
+
+        assert!(request.contains("X-Api-Key: key\r\n"));
+        assert!(request.contains("X-Request-Id: 1\r\n"));
+    }
+    #[test]
+    fn request_targetのpathだけを上書きできる() {
+        let url = Url::from_str("https://localhost/v1/chat").unwrap();
+
+        let request = RequestBuilder::new(url)
+            .get()
+            .path("/v1/completions")
+            .build();
+
+        assert!(String::from_utf8_lossy(request.bytes())
+            .starts_with("GET /v1/completions HTTP/1.1\r\nHost: localhost\r\n"));
+        assert_eq!(request.url().path(), "/v1/chat");
+        assert_eq!(request.url().host(), "localhost");
+    }
+    #[test]
     fn bearer_authを追加できる() {
         let url = Url::from_str("https://localhost/test").unwrap();
         let request = RequestBuilder::new(url)
@@ -170,6 +658,118 @@ mod tests {
         )
     }
     #[test]
+    fn debug出力では認証情報を伏せる() {
+        let url = Url::from_str("https://localhost/test").unwrap();
+        let sut = RequestBuilder::new(url)
+            .post()
+            .bearer_auth("secret-token")
+            .header("Proxy-Authorization", "Basic c2VjcmV0");
+
+        let builder_debug = format!("{:?}", sut);
+        let request_debug = format!("{:?}", sut.build());
+
+        for debug in [builder_debug, request_debug] {
+            assert!(!debug.contains("secret-token"), "{}", debug);
+            assert!(!debug.contains("c2VjcmV0"), "{}", debug);
+            assert!(debug.contains("Bearer ***"), "{}", debug);
+            assert!(debug.contains("Basic ***"), "{}", debug);
+        }
+    }
+    #[test]
+    fn hostヘッダーをurlとは別に指定できる() {
+        let url = Url::from_str("https://127.0.0.1/test").unwrap();
+        let request = RequestBuilder::new(url).get().host("example.com").build();
+
+        assert_eq!(request.url().host(), "127.0.0.1");
+        assert_eq!(
+            String::from_utf8_lossy(request.bytes()),
+            "GET /test HTTP/1.1\r\nHost: example.com\r\nAccept: text/event-stream\r\nConnection: close\r\n\r\n"
+        )
+    }
+    #[test]
+    fn absolute_formのリクエストラインを生成できる() {
+        let url = Url::from_str("http://localhost/test").unwrap();
+        let request = RequestBuilder::new(url).get().absolute_form(true).build();
+
+        assert_eq!(
+            String::from_utf8_lossy(request.bytes()),
+            "GET http://localhost/test HTTP/1.1\r\nHost: localhost\r\nAccept: text/event-stream\r\nConnection: close\r\n\r\n"
+        )
+    }
+    #[test]
+    fn getはデフォルトでevent_streamをacceptする() {
+        let url = Url::from_str("https://localhost/test").unwrap();
+        let request = RequestBuilder::new(url).get().to_request();
+        assert_eq!(
+            request,
+            "GET /test HTTP/1.1\r\nHost: localhost\r\nAccept: text/event-stream\r\nConnection: close\r\n\r\n"
+        )
+    }
+    #[test]
+    fn acceptヘッダーは上書きできる() {
+        let url = Url::from_str("https://localhost/test").unwrap();
+        let request = RequestBuilder::new(url)
+            .get()
+            .header("accept", "*/*")
+            .to_request();
+        assert_eq!(
+            request,
+            "GET /test HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\naccept: */*\r\n\r\n"
+        )
+    }
+    #[test]
+    fn connectリクエストにheaderを追加できる() {
+        let url = Url::from_str("https://www.fake/test").unwrap();
+        let request = RequestBuilder::new(url)
+            .proxy_header("Proxy-Authorization", "Basic dXNlcjpwYXNz")
+            .connect_request();
+        assert_eq!(
+            String::from_utf8_lossy(request.bytes()),
+            "CONNECT www.fake:443 HTTP/1.1\r\nHost: www.fake:443\r\nProxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n"
+        )
+    }
+    #[test]
+    fn originのheaderはconnectリクエストに含めない() {
+        let url = Url::from_str("https://www.fake/test").unwrap();
+        let builder = RequestBuilder::new(url)
+            .bearer_auth("secret")
+            .proxy_header("Proxy-Authorization", "Basic dXNlcjpwYXNz");
+
+        let connect =
+            String::from_utf8_lossy(builder.clone().connect_request().bytes()).to_string();
+        let origin = String::from_utf8_lossy(builder.build().bytes()).to_string();
+
+        assert!(!connect.contains("Authorization: Bearer"));
+        assert!(!connect.contains("secret"));
+        assert!(origin.contains("Authorization: Bearer secret\r\n"));
+        assert!(!origin.contains("Proxy-Authorization"));
+    }
+    #[test]
+    fn originとrefererを追加できる() {
+        let url = Url::from_str("https://localhost/test").unwrap();
+        let request = RequestBuilder::new(url)
+            .get()
+            .origin("https://example.com")
+            .referer("https://example.com/page")
+            .to_request();
+        assert_eq!(
+            request,
+            "GET /test HTTP/1.1\r\nHost: localhost\r\nAccept: text/event-stream\r\nConnection: close\r\nOrigin: https://example.com\r\nReferer: https://example.com/page\r\n\r\n"
+        )
+    }
+    #[test]
+    fn getでもheaderを追加できる() {
+        let url = Url::from_str("https://localhost/test").unwrap();
+        let request = RequestBuilder::new(url)
+            .get()
+            .header("Last-Event-ID", "2")
+            .to_request();
+        assert_eq!(
+            request,
+            "GET /test HTTP/1.1\r\nHost: localhost\r\nAccept: text/event-stream\r\nConnection: close\r\nLast-Event-ID: 2\r\n\r\n"
+        )
+    }
+    #[test]
     fn headerを追加できる() {
         let url = Url::from_str("https://localhost/test").unwrap();
         let request = RequestBuilder::new(url)
@@ -1,6 +1,10 @@
 use std::fmt::Display;
 
-use super::{body::HttpBody, header::HttpHeader, status_line::HttpStatusLine};
+use super::{
+    body::HttpBody,
+    header::{HttpHeader, HttpHeaderError, RateLimitInfo},
+    status_line::{HttpStatusLine, HttpStatusLineError},
+};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct HttpResponse {
@@ -10,15 +14,7 @@ pub struct HttpResponse {
 }
 impl Display for HttpResponse {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}{}{}{}{}",
-            self.status_line.to_string(),
-            self.header.to_string(),
-            "\r\n",
-            self.body.to_str(),
-            "\r\n"
-        )
+        write!(f, "{}", HttpResponse::to_string(self))
     }
 }
 
@@ -36,29 +32,132 @@ impl HttpResponse {
     pub fn get_header(&self, key: &str) -> Option<&str> {
         self.header.get(key)
     }
+    pub fn header(&self) -> &HttpHeader {
+        &self.header
+    }
+    pub fn rate_limit(&self) -> Option<RateLimitInfo> {
+        RateLimitInfo::from_headers(&self.header)
+    }
     pub fn body_str(&self) -> &str {
         self.body.to_str()
     }
+    pub fn content_length(&self) -> Option<usize> {
+        self.header
+            .get_ignore_case("Content-Length")
+            .and_then(|value| value.trim().parse().ok())
+    }
     pub fn is_error(&self) -> bool {
         self.status_code() >= 400
     }
+    // the body is framed as a single chunk when the response was chunked
     pub fn to_string(&self) -> String {
         format!(
-            "{}{}{}{}{}",
+            "{}{}{}{}",
             self.status_line.to_string(),
             self.header.to_string(),
             "\r\n",
-            self.body.to_str(),
-            "\r\n"
+            self.framed_body()
         )
     }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+    pub fn parse(response: &str) -> Result<Self, HttpResponseError> {
+        let (head, body) = response.split_once("\r\n\r\n").unwrap_or((response, ""));
+        let mut lines = head.split("\r\n");
+        let status_line = HttpStatusLine::from_str(lines.next().unwrap_or_default())?;
+        let mut header = HttpHeader::new();
+        for line in lines {
+            header.concat(HttpHeader::from_line(line)?);
+        }
+        let body = if Self::is_chunked(&header) {
+            Self::decode_chunked(body)?
+        } else {
+            HttpBody::from_line(body)
+        };
+        let response = Self::new(status_line, header, body);
+        // a truncated or padded body is rejected, so that a broken record is not replayed
+        let actual = response.body_str().len();
+        match response.content_length() {
+            Some(expected) if expected != actual && !Self::is_chunked(&response.header) => {
+                Err(HttpResponseError::ContentLengthMismatch { expected, actual })
+            }
+            _ => Ok(response),
+        }
+    }
+    fn is_chunked(header: &HttpHeader) -> bool {
+        header
+            .get_ignore_case("Transfer-Encoding")
+            .is_some_and(|value| value.contains("chunked"))
+    }
+    fn framed_body(&self) -> String {
+        let body = self.body.to_str();
+        if !Self::is_chunked(&self.header) {
+            return body.to_string();
+        }
+        if body.is_empty() {
+            return "0\r\n\r\n".to_string();
+        }
+        format!("{:x}\r\n{}\r\n0\r\n\r\n", body.len(), body)
+    }
+    fn decode_chunked(mut rest: &str) -> Result<HttpBody, HttpResponseError> {
+        let mut body = HttpBody::new();
+        loop {
+            let invalid = || HttpResponseError::InvalidChunk(rest.to_string());
+            let (size, after) = rest.split_once("\r\n").ok_or_else(invalid)?;
+            let size = size.split(';').next().unwrap_or_default().trim();
+            let size = usize::from_str_radix(size, 16).map_err(|_e| invalid())?;
+            if size == 0 {
+                return Ok(body);
+            }
+            let chunk = after.get(..size).ok_or_else(invalid)?;
+            body.concat(HttpBody::from_line(chunk));
+            rest = after[size..].strip_prefix("\r\n").ok_or_else(invalid)?;
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum HttpResponseError {
+    InvalidStatusLine(HttpStatusLineError),
+    InvalidHeader(HttpHeaderError),
+    InvalidChunk(String),
+    ContentLengthMismatch { expected: usize, actual: usize },
+}
+impl Display for HttpResponseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidStatusLine(e) => write!(f, "Invalid status line: {}", e),
+            Self::InvalidHeader(e) => write!(f, "Invalid header: {}", e),
+            Self::InvalidChunk(rest) => write!(f, "Invalid chunk: {}", rest),
+            Self::ContentLengthMismatch { expected, actual } => write!(
+                f,
+                "Content-Length mismatch: expected {} bytes, but got {} bytes",
+                expected, actual
+            ),
+        }
+    }
+}
+impl std::error::Error for HttpResponseError {}
+impl From<HttpStatusLineError> for HttpResponseError {
+    fn from(e: HttpStatusLineError) -> Self {
+        Self::InvalidStatusLine(e)
+    }
+}
+impl From<HttpHeaderError> for HttpResponseError {
+    fn from(e: HttpHeaderError) -> Self {
+        Self::InvalidHeader(e)
+    }
 }
 
 #[cfg(test)]
 
 mod tests {
     use crate::http::{
-        body::HttpBody, header::HttpHeader, response::HttpResponse, status_line::HttpStatusLine,
+        body::HttpBody,
+        header::HttpHeader,
+        response::{HttpResponse, HttpResponseError},
+        status_line::HttpStatusLine,
     };
 
     #[test]
@@ -123,13 +222,78 @@ mod tests {
         assert_eq!(
             sut.to_string(),
             format!(
-                "{}{}{}{}{}",
+                "{}{}{}{}",
                 "HTTP/1.1 200 OK\r\n",
                 "Content-Type: text/event-stream\r\n",
                 "\r\n",
                 "Hello, World!",
-                "\r\n"
             )
         );
     }
+    #[test]
+    fn chunkedのresponseはchunkとして文字列に変換される() {
+        let status_line = HttpStatusLine::from_str("HTTP/1.1 500 Internal Server Error").unwrap();
+        let header = HttpHeader::from_line("Transfer-Encoding: chunked").unwrap();
+        let body = HttpBody::from_line("Hello, World!");
+
+        let sut = HttpResponse::new(status_line, header, body);
+
+        assert_eq!(
+            sut.to_bytes(),
+            b"HTTP/1.1 500 Internal Server Error\r\nTransfer-Encoding: chunked\r\n\r\nd\r\nHello, World!\r\n0\r\n\r\n"
+        );
+    }
+    #[test]
+    fn 文字列に変換して再度parseすると元のresponseに戻る() {
+        let raw = concat!(
+            "HTTP/1.1 429 Too Many Requests\r\n",
+            "Content-Type: application/json\r\n",
+            "Transfer-Encoding: chunked\r\n",
+            "\r\n",
+            "5\r\n{\"err\r\n",
+            "5\r\nor\":1\r\n",
+            "1\r\n}\r\n",
+            "0\r\n\r\n"
+        );
+        let parsed = HttpResponse::parse(raw).unwrap();
+        assert_eq!(parsed.body_str(), "{\"error\":1}");
+
+        let sut = HttpResponse::parse(&parsed.to_string()).unwrap();
+
+        assert_eq!(sut, parsed);
+        assert_eq!(sut.to_bytes(), parsed.to_bytes());
+    }
+    #[test]
+    fn content_lengthのresponseも往復できる() {
+        let raw = "HTTP/1.1 404 Not Found\r\nContent-Length: 9\r\n\r\nnot found";
+
+        let sut = HttpResponse::parse(raw).unwrap();
+
+        assert_eq!(sut.to_string(), raw);
+    }
+    #[test]
+    fn content_lengthを取得できる() {
+        let raw = "HTTP/1.1 500 Internal Server Error\r\ncontent-length: 5\r\n\r\nerror";
+
+        let sut = HttpResponse::parse(raw).unwrap();
+
+        assert_eq!(sut.content_length(), Some(5));
+        assert_eq!(sut.body_str().len(), 5);
+        let no_length = HttpResponse::parse("HTTP/1.1 200 OK\r\n\r\n").unwrap();
+        assert_eq!(no_length.content_length(), None);
+    }
+    #[test]
+    fn bodyの長さがcontent_lengthと異なる場合はerrorになる() {
+        let raw = "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 10\r\n\r\nerror";
+
+        let result = HttpResponse::parse(raw);
+
+        assert_eq!(
+            result,
+            Err(HttpResponseError::ContentLengthMismatch {
+                expected: 10,
+                actual: 5
+            })
+        );
+    }
 }
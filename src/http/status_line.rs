@@ -30,9 +30,10 @@ impl HttpStatusLine {
             status_code,
         }
     }
+    // tokens may be separated by any whitespace, and the reason phrase may be omitted
     pub fn from_str(line: &str) -> Result<Self, HttpStatusLineError> {
-        let mut split_line = line.split(" ");
-        let (Some(version),Some(status_num),Some(_status_message)) = (split_line.next(),split_line.next(),split_line.next()) else {
+        let mut split_line = line.split_whitespace();
+        let (Some(version),Some(status_num)) = (split_line.next(),split_line.next()) else {
             return Err(HttpStatusLineError::InvalidFormat(line.to_string()));
         };
         let Some(version) = HttpVersion::from_str(version) else {
@@ -51,6 +52,9 @@ impl HttpStatusLine {
             self.status_code.to_str()
         )
     }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
     pub fn is_error(&self) -> bool {
         self.status_code.is_error()
     }
@@ -63,19 +67,26 @@ impl HttpStatusLine {
 }
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HttpVersion {
+    V1_0,
     V1_1,
 }
 impl HttpVersion {
     pub fn to_str(&self) -> &'static str {
         match self {
+            HttpVersion::V1_0 => "HTTP/1.0",
             HttpVersion::V1_1 => "HTTP/1.1",
         }
     }
+    // some proxies send the version in lowercase, and some old servers answer with HTTP/1.0
     pub fn from_str(version: &str) -> Option<Self> {
-        match version {
-            "HTTP/1.1" => Some(HttpVersion::V1_1),
-            _ => None,
+        let version = version.trim();
+        if version.eq_ignore_ascii_case("HTTP/1.1") {
+            return Some(HttpVersion::V1_1);
+        }
+        if version.eq_ignore_ascii_case("HTTP/1.0") {
+            return Some(HttpVersion::V1_0);
         }
+        None
     }
 }
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -392,6 +403,14 @@ mod tests {
         assert_eq!(sut.to_string(), "HTTP/1.1 200 OK\r\n");
     }
     #[test]
+    fn http_1_0のstatus_lineから構造体を生成可能() {
+        let sut = HttpStatusLine::from_str("HTTP/1.0 200 OK\r\n").unwrap();
+
+        assert_eq!(sut.version(), HttpVersion::V1_0);
+        assert_eq!(sut.status_code(), HttpStatusCode::OK);
+        assert_eq!(sut.to_string(), "HTTP/1.0 200 OK\r\n");
+    }
+    #[test]
     fn 不正な文字列の場合はエラー() {
         let invalid = "HTTP/1.1 200OK";
 
@@ -404,6 +423,26 @@ mod tests {
         );
     }
     #[test]
+    fn 小文字のhttp_versionも受け付ける() {
+        let sut = HttpStatusLine::from_str("http/1.1 200 OK\r\n").unwrap();
+
+        assert_eq!(sut.version(), HttpVersion::V1_1);
+        assert_eq!(sut.status_code(), HttpStatusCode::OK);
+    }
+    #[test]
+    fn 先頭の空白は無視する() {
+        let sut = HttpStatusLine::from_str(" HTTP/1.1 404 Not Found\r\n").unwrap();
+
+        assert_eq!(sut.version(), HttpVersion::V1_1);
+        assert_eq!(sut.status_code(), HttpStatusCode::NotFound);
+    }
+    #[test]
+    fn tabで区切られたstatus_lineも受け付ける() {
+        let sut = HttpStatusLine::from_str("HTTP/1.1\t503\tService Unavailable").unwrap();
+
+        assert_eq!(sut.status_code(), HttpStatusCode::ServiceUnavailable);
+    }
+    #[test]
     fn 存在しないhttp_versionの文字列の場合はエラー() {
         let invalid = "HTTP/1.2 200 OK";
 
@@ -429,4 +468,12 @@ mod tests {
 
         assert!(sut.is_error());
     }
+    #[test]
+    fn byte列に変換可能() {
+        let sut = HttpStatusLine::from_str("HTTP/1.1 404 Not Found").unwrap();
+
+        assert_eq!(sut.to_bytes(), b"HTTP/1.1 404 Not Found\r\n");
+        // borrowed, so the status line is still usable
+        assert!(sut.is_error());
+    }
 }
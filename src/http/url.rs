@@ -2,7 +2,9 @@ use std::fmt::Display;
 
 use thiserror::Error;
 
-#[derive(Debug, Clone, PartialEq)]
+// the default port is always filled in and the scheme is lowercased while parsing,
+// so the derived equality and hash are normalized
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Url {
     scheme: Schema,
     host: String,
@@ -77,6 +79,10 @@ impl Url {
     pub fn scheme(&self) -> &str {
         self.scheme.to_str()
     }
+    // whether the url must be connected with tls
+    pub fn is_secure(&self) -> bool {
+        self.scheme == Schema::Https
+    }
     pub fn port(&self) -> u16 {
         self.port
     }
@@ -86,6 +92,18 @@ impl Url {
     pub fn path(&self) -> &str {
         self.path.as_str()
     }
+    // same scheme, host and port with another path
+    pub fn with_path(&self, path: &str) -> Url {
+        let path = if path.starts_with('/') {
+            path.to_string()
+        } else {
+            format!("/{}", path)
+        };
+        Self {
+            path,
+            ..self.clone()
+        }
+    }
 }
 impl TryInto<Url> for &str {
     type Error = UrlError;
@@ -99,7 +117,7 @@ impl Into<Url> for &Url {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum Schema {
     Http,
     Https,
@@ -107,7 +125,7 @@ enum Schema {
 
 impl Schema {
     fn from_str(s: &str) -> Result<Self> {
-        match s {
+        match s.to_ascii_lowercase().as_str() {
             "http" => Ok(Schema::Http),
             "https" => Ok(Schema::Https),
             _ => Err(UrlError::InvalidSchema(s.to_string())),
@@ -176,11 +194,36 @@ mod tests {
         assert_eq!(url.scheme(), "http");
     }
     #[test]
+    fn httpsのurlはsecureである() {
+        let url = Url::from_str("https://localhost/test").unwrap();
+        assert!(url.is_secure());
+        let url = Url::from_str("http://localhost/test").unwrap();
+        assert!(!url.is_secure());
+    }
+    #[test]
     fn url構造体はaddr_strを返すことができる() {
         let url = Url::from_str("https://localhost/test").unwrap();
         assert_eq!(url.to_addr_str(), "localhost:443");
     }
     #[test]
+    fn schemeの大文字小文字とdefault_portは同じurlとして扱う() {
+        use std::collections::HashSet;
+        let url = Url::from_str("https://localhost").unwrap();
+        let same = Url::from_str("HTTPS://localhost:443").unwrap();
+        assert_eq!(url, same);
+        assert_eq!(same.scheme(), "https");
+        let set: HashSet<Url> = [url, same].into_iter().collect();
+        assert_eq!(set.len(), 1);
+    }
+    #[test]
+    fn portが異なるurlは等しくない() {
+        let url = Url::from_str("https://localhost").unwrap();
+        let other = Url::from_str("https://localhost:8443").unwrap();
+        assert_ne!(url, other);
+        let http = Url::from_str("http://localhost:443").unwrap();
+        assert_ne!(url, http);
+    }
+    #[test]
     fn url構造体はportを返すことができる() {
         let url = Url::from_str("https://localhost/test").unwrap();
         assert_eq!(url.port(), 443);
@@ -1,3 +1,8 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+#[cfg(feature = "tokio")]
+pub mod async_client;
+#[cfg(feature = "std")]
 pub mod client;
+#[cfg(feature = "std")]
 pub mod http;
 pub mod sse;
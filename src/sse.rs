@@ -1,4 +1,21 @@
+#[cfg(feature = "tokio")]
+pub mod async_connector;
+#[cfg(feature = "std")]
 pub mod connector;
+#[cfg(feature = "std")]
+pub mod event;
+#[cfg(feature = "std")]
+pub mod handler;
+pub mod parser;
+#[cfg(feature = "std")]
+pub mod pool;
+#[cfg(feature = "std")]
+pub mod reader;
+#[cfg(feature = "std")]
 pub mod response;
+#[cfg(feature = "std")]
 pub(crate) mod server;
+#[cfg(feature = "std")]
 pub mod subscriber;
+#[cfg(all(feature = "std", any(test, feature = "testing")))]
+pub mod testing;
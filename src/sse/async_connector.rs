@@ -0,0 +1,361 @@
+use std::{io::Write, net::SocketAddr, sync::Arc, time::Duration};
+
+use rustls::ClientConfig;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::mpsc::Sender,
+};
+use tokio_rustls::{client::TlsStream, TlsConnector};
+
+use crate::http::{
+    body::HttpBody, header::HttpHeader, request::Request, response::HttpResponse,
+    status_line::HttpStatusLine, url::Url,
+};
+
+use super::{
+    connector::{
+        dns_error, is_transient_dns_error, tunnel_request, ConnectedSseResponse, ConnectionPhase,
+        InvalidDnsNameError, ProxyConnectionError, ProxyConnectionErrorType, Result,
+        SseConnectionError, StreamRead, DNS_RETRIES, DNS_RETRY_DELAY, DNS_TIMEOUT,
+    },
+    response::SseResponse,
+};
+
+pub struct AsyncSseTlsConnector {
+    url: Url,
+    connector: TlsConnector,
+    proxy_urls: Vec<Url>,
+    direct_fallback: bool,
+    proxy_keep_alive: bool,
+    proxy_headers: Vec<(String, String)>,
+    read_timeout: Option<Duration>,
+}
+
+impl AsyncSseTlsConnector {
+    pub(crate) fn new(
+        url: Url,
+        config: Arc<ClientConfig>,
+        proxy_urls: Vec<Url>,
+        direct_fallback: bool,
+        proxy_keep_alive: bool,
+        proxy_headers: Vec<(String, String)>,
+        read_timeout: Option<Duration>,
+    ) -> Self {
+        Self {
+            url,
+            connector: TlsConnector::from(config),
+            proxy_urls,
+            direct_fallback,
+            proxy_keep_alive,
+            proxy_headers,
+            read_timeout,
+        }
+    }
+    pub async fn connect(&self, req: &Request) -> Result<AsyncSseConnection> {
+        let server_name = rustls::ServerName::try_from(self.url.host())
+            .map_err(|_e| SseConnectionError::DnsError(InvalidDnsNameError::new(&self.url)))?;
+        let tcp_stream = self.tcp_stream().await?;
+        let mut stream = self
+            .connector
+            .connect(server_name, tcp_stream)
+            .await
+            .map_err(|e| SseConnectionError::ConnectError(ConnectionPhase::Handshake, e))?;
+        write_request(req, &mut stream)
+            .await
+            .map_err(|e| SseConnectionError::ConnectError(ConnectionPhase::Write, e))?;
+        Ok(AsyncSseConnection::new(stream, self.read_timeout))
+    }
+    async fn tcp_stream(&self) -> Result<TcpStream> {
+        if self.proxy_urls.is_empty() {
+            return Self::direct(&self.url).await;
+        }
+        let mut last_error = None;
+        for proxy_url in &self.proxy_urls {
+            match Self::proxy(
+                &self.url,
+                proxy_url,
+                self.proxy_keep_alive,
+                &self.proxy_headers,
+            )
+            .await
+            {
+                Ok(tcp_stream) => return Ok(tcp_stream),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        match last_error {
+            Some(_) if self.direct_fallback => Self::direct(&self.url).await,
+            Some(e) => Err(e),
+            None => unreachable!("proxy_urls is not empty"),
+        }
+    }
+    async fn direct(url: &Url) -> Result<TcpStream> {
+        connect_tcp(url).await
+    }
+    async fn proxy(
+        url: &Url,
+        proxy_url: &Url,
+        keep_alive: bool,
+        headers: &[(String, String)],
+    ) -> Result<TcpStream> {
+        let mut tcp_stream = connect_tcp(proxy_url).await?;
+        let req = tunnel_request(url, keep_alive, headers);
+        tcp_stream
+            .write_all(req.bytes())
+            .await
+            .map_err(|e| SseConnectionError::ConnectError(ConnectionPhase::Write, e))?;
+
+        let mut buf = vec![0; 4096];
+        while let Ok(size) = tcp_stream.read(&mut buf).await {
+            if size == 0 {
+                break;
+            }
+            let proxy_response = String::from_utf8_lossy(&buf[..size]);
+            if proxy_response.contains("Established") {
+                return Ok(tcp_stream);
+            }
+        }
+        Err(SseConnectionError::ProxyConnectionError(
+            ProxyConnectionError::new(
+                proxy_url,
+                url,
+                ProxyConnectionErrorType::InvalidRequestError("Invalid Error".to_string()),
+            ),
+        ))
+    }
+}
+
+// the writer of a streaming body is blocking, so it runs on a blocking thread
+// and each chunk is written to the stream as soon as it is produced
+async fn write_request<W: AsyncWrite + Unpin>(
+    req: &Request,
+    stream: &mut W,
+) -> std::io::Result<()> {
+    let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+    let req = req.clone();
+    let writer = tokio::task::spawn_blocking(move || req.write_to(ChannelWriter(tx)));
+    while let Some(chunk) = rx.recv().await {
+        // the receiver is dropped on error, so the blocking writer stops with BrokenPipe
+        stream.write_all(&chunk).await?;
+    }
+    writer.await.map_err(std::io::Error::other)??;
+    stream.flush().await
+}
+struct ChannelWriter(Sender<Vec<u8>>);
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.blocking_send(buf.to_vec()).map_err(|_e| {
+            std::io::Error::new(std::io::ErrorKind::BrokenPipe, "the stream is closed")
+        })?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+// resolved the same way as the sync connector, so that dns failures are told apart from connect errors
+async fn connect_tcp(url: &Url) -> Result<TcpStream> {
+    let addrs = resolve(url).await?;
+    TcpStream::connect(addrs.as_slice())
+        .await
+        .map_err(|e| SseConnectionError::ConnectError(ConnectionPhase::Connect, e))
+}
+async fn resolve(url: &Url) -> Result<Vec<SocketAddr>> {
+    let mut attempt = 0;
+    let mut pending = Box::pin(lookup(url.to_addr_str()));
+    loop {
+        match tokio::time::timeout(DNS_TIMEOUT, &mut pending).await {
+            Ok(Ok(addrs)) if !addrs.is_empty() => return Ok(addrs),
+            Ok(Ok(_)) => return Err(dns_error(url, "no address found".to_string())),
+            Ok(Err(e)) if attempt >= DNS_RETRIES || !is_transient_dns_error(&e) => {
+                return Err(dns_error(url, e.to_string()))
+            }
+            Ok(Err(_)) => {
+                tokio::time::sleep(DNS_RETRY_DELAY).await;
+                pending = Box::pin(lookup(url.to_addr_str()));
+            }
+            // the timed out lookup is dropped, and a fresh one is started
+            Err(_) if attempt < DNS_RETRIES => pending = Box::pin(lookup(url.to_addr_str())),
+            Err(_) => return Err(dns_error(url, "lookup timed out".to_string())),
+        }
+        attempt += 1;
+    }
+}
+async fn lookup(addr: String) -> std::io::Result<Vec<SocketAddr>> {
+    Ok(tokio::net::lookup_host(addr).await?.collect())
+}
+
+// generic over the stream, so that the reading can be tested without tls
+pub struct AsyncSseConnection<S = TlsStream<TcpStream>> {
+    reader: BufReader<S>,
+    read_timeout: Option<Duration>,
+    bytes_read: usize,
+    pending_fields: bool,
+}
+impl<S: AsyncRead + Unpin> AsyncSseConnection<S> {
+    pub(crate) fn new(stream: S, read_timeout: Option<Duration>) -> Self {
+        Self {
+            reader: BufReader::new(stream),
+            read_timeout,
+            bytes_read: 0,
+            pending_fields: false,
+        }
+    }
+    pub fn bytes_read(&self) -> usize {
+        self.bytes_read
+    }
+    pub async fn read(&mut self) -> Result<ConnectedSseResponse> {
+        loop {
+            if let StreamRead::Response(res) = self.read_until_dispatch().await? {
+                return Ok(res);
+            }
+        }
+    }
+    // stops also at the blank line that dispatches the pending fields as one event
+    pub(crate) async fn read_until_dispatch(&mut self) -> Result<StreamRead> {
+        while let Some(line) = self
+            .read_line()
+            .await
+            .map_err(SseConnectionError::from_read_error)?
+        {
+            if let Ok(http_status) = HttpStatusLine::from_str(&line) {
+                if !http_status.is_error() {
+                    continue;
+                };
+                return Err(self.http_error(http_status).await);
+            };
+            if line.trim().is_empty() && std::mem::take(&mut self.pending_fields) {
+                return Ok(StreamRead::Dispatched);
+            }
+            // sse_response is look like header, so check sse_response first
+            if let Ok(sse_response) = SseResponse::from_line(line.as_str()) {
+                if sse_response.is_comment() || sse_response.is_other() {
+                    continue;
+                }
+                self.pending_fields = true;
+                return Ok(StreamRead::Response(ConnectedSseResponse::Progress {
+                    response: sse_response,
+                    raw: line,
+                }));
+            };
+        }
+        Ok(StreamRead::Response(ConnectedSseResponse::Done))
+    }
+    // discard the rest of the current event until the blank line
+    pub async fn skip_event(&mut self) -> Result<()> {
+        while let Some(line) = self
+            .read_line()
+            .await
+            .map_err(SseConnectionError::from_read_error)?
+        {
+            if line.trim().is_empty() {
+                self.pending_fields = false;
+                break;
+            }
+        }
+        Ok(())
+    }
+    async fn http_error(&mut self, http_status: HttpStatusLine) -> SseConnectionError {
+        let mut header = HttpHeader::new();
+        let mut body = HttpBody::new();
+        while let Ok(Some(line)) = self.read_line().await {
+            if let Ok(add_header) = HttpHeader::from_line(line.as_str()) {
+                header.concat(add_header);
+                continue;
+            };
+            body.concat(HttpBody::from_line(line.as_str()))
+        }
+        SseConnectionError::HttpError(HttpResponse::new(http_status, header, body))
+    }
+    async fn read_line(&mut self) -> std::result::Result<Option<String>, std::io::Error> {
+        let mut line = String::new();
+        let read = self.reader.read_line(&mut line);
+        let size = match self.read_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, read).await.map_err(|_e| {
+                std::io::Error::new(std::io::ErrorKind::TimedOut, "read timed out")
+            })??,
+            None => read.await?,
+        };
+        if size == 0 {
+            return Ok(None);
+        }
+        self.bytes_read += size;
+        Ok(Some(line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use crate::http::request::RequestBuilder;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn streaming_bodyはbufferせずにchunkのまま書き込む() {
+        #[derive(Default)]
+        struct RecordingWriter {
+            written: Vec<u8>,
+            writes: usize,
+            max_write: usize,
+        }
+        impl AsyncWrite for RecordingWriter {
+            fn poll_write(
+                mut self: Pin<&mut Self>,
+                _cx: &mut Context<'_>,
+                buf: &[u8],
+            ) -> Poll<std::io::Result<usize>> {
+                self.writes += 1;
+                self.max_write = self.max_write.max(buf.len());
+                self.written.extend_from_slice(buf);
+                Poll::Ready(Ok(buf.len()))
+            }
+            fn poll_flush(
+                self: Pin<&mut Self>,
+                _cx: &mut Context<'_>,
+            ) -> Poll<std::io::Result<()>> {
+                Poll::Ready(Ok(()))
+            }
+            fn poll_shutdown(
+                self: Pin<&mut Self>,
+                _cx: &mut Context<'_>,
+            ) -> Poll<std::io::Result<()>> {
+                Poll::Ready(Ok(()))
+            }
+        }
+        let url = Url::from_str("https://localhost/test").unwrap();
+        let json = vec!["a".repeat(100); 1000];
+        let req = RequestBuilder::new(&url)
+            .post()
+            .json_streaming(json.clone())
+            .build();
+        let mut writer = RecordingWriter::default();
+
+        write_request(&req, &mut writer).await.unwrap();
+
+        let written = String::from_utf8(writer.written).unwrap();
+        let (head, body) = written.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("Transfer-Encoding: chunked"));
+        assert!(body.ends_with("0\r\n\r\n"));
+        assert!(body.len() > 8 * 1024 * 10);
+        assert!(writer.writes > 10);
+        assert!(writer.max_write <= 8 * 1024 + 16);
+    }
+
+    #[tokio::test]
+    async fn 名前解決できないhostはasyncでもdns_errorになる() {
+        let url = Url::from_str("http://rsse-test.invalid").unwrap();
+
+        let result = connect_tcp(&url).await;
+
+        let Err(SseConnectionError::DnsError(err)) = result else {
+            panic!("expected DnsError");
+        };
+        assert!(err.to_string().starts_with("failed to resolve"));
+    }
+}
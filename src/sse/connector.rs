@@ -1,15 +1,21 @@
 use std::{
     cell::RefCell,
+    convert::Infallible,
     fmt::{Debug, Display, Formatter},
     fs::File,
     io::{BufRead, BufReader, BufWriter, Read, Write},
-    net::TcpStream,
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
     path::Path,
-    sync::Arc,
+    sync::{
+        mpsc::{Receiver, RecvTimeoutError},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
 use rustls::{Certificate, ClientConfig};
 use rustls_pemfile::{read_one, Item};
+use serde::de::DeserializeOwned;
 use thiserror::Error;
 
 use crate::http::{
@@ -17,17 +23,28 @@ use crate::http::{
     header::HttpHeader,
     request::{Request, RequestBuilder},
     response::HttpResponse,
-    status_line::HttpStatusLine,
-    url::Url,
+    status_line::{HttpStatusLine, HttpStatusLineError},
+    url::{Url, UrlError},
 };
 
 use super::response::SseResponse;
 pub type Result<T> = std::result::Result<T, SseConnectionError>;
 
+#[derive(Clone)]
 pub(crate) struct SseTlsConnectorBuilder {
     url: Url,
     ca_paths: Vec<String>,
-    proxy_url: Option<Url>,
+    proxy_urls: Vec<Url>,
+    direct_fallback: bool,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    pin_sha256: Option<[u8; 32]>,
+    lossy_utf8: bool,
+    max_stream_bytes: Option<usize>,
+    header_limit: HeaderLimit,
+    proxy_keep_alive: bool,
+    proxy_headers: Vec<(String, String)>,
+    stream_on_error_status: bool,
 }
 
 impl SseTlsConnectorBuilder {
@@ -35,10 +52,86 @@ impl SseTlsConnectorBuilder {
         Self {
             url: url.into(),
             ca_paths: Vec::new(),
-            proxy_url: None,
+            proxy_urls: Vec::new(),
+            direct_fallback: false,
+            connect_timeout: None,
+            read_timeout: None,
+            pin_sha256: None,
+            lossy_utf8: false,
+            max_stream_bytes: None,
+            header_limit: HeaderLimit::default(),
+            proxy_keep_alive: false,
+            proxy_headers: Vec::new(),
+            stream_on_error_status: false,
         }
     }
 
+    // read events of an error response when its content type is text/event-stream
+    pub fn stream_on_error_status(mut self, stream_on_error_status: bool) -> Self {
+        self.stream_on_error_status = stream_on_error_status;
+        self
+    }
+
+    // send `Proxy-Connection: keep-alive` on CONNECT for proxies which close idle tunnels
+    pub fn proxy_keep_alive(mut self, proxy_keep_alive: bool) -> Self {
+        self.proxy_keep_alive = proxy_keep_alive;
+        self
+    }
+
+    // header only sent to proxies on CONNECT, like Proxy-Authorization
+    pub fn proxy_header(mut self, key: &str, value: &str) -> Self {
+        self.proxy_headers
+            .push((key.to_string(), value.to_string()));
+        self
+    }
+
+    // fail the stream when the server sends more than the limit in total
+    pub fn max_stream_bytes(mut self, max_stream_bytes: usize) -> Self {
+        self.max_stream_bytes = Some(max_stream_bytes);
+        self
+    }
+
+    // fail the stream when the response has more header lines than the limit
+    pub fn max_headers(mut self, max_headers: usize) -> Self {
+        self.header_limit.max_headers = max_headers;
+        self
+    }
+
+    // fail the stream when the headers of the response are larger than the limit in total
+    pub fn max_header_bytes(mut self, max_header_bytes: usize) -> Self {
+        self.header_limit.max_header_bytes = max_header_bytes;
+        self
+    }
+
+    // replace invalid utf-8 sequences instead of failing the stream
+    pub fn lossy_utf8(mut self, lossy_utf8: bool) -> Self {
+        self.lossy_utf8 = lossy_utf8;
+        self
+    }
+
+    // reject the server when the sha256 of the leaf certificate does not match
+    pub fn pin_sha256(mut self, pin: &[u8; 32]) -> Self {
+        self.pin_sha256 = Some(*pin);
+        self
+    }
+
+    // bound the tcp connect to the server or the proxy
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    // the timeout overridden by set_connect_timeout, for a reconnect
+    fn override_connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout.or(self.connect_timeout);
+        self
+    }
+
     pub fn add_ca(mut self, ca_path: impl AsRef<Path>) -> Self {
         self.ca_paths
             .push(ca_path.as_ref().to_str().unwrap().to_string());
@@ -46,11 +139,24 @@ impl SseTlsConnectorBuilder {
     }
 
     pub fn proxy(mut self, proxy_url: impl Into<Url>) -> Self {
-        self.proxy_url = Some(proxy_url.into());
+        self.proxy_urls = vec![proxy_url.into()];
+        self
+    }
+
+    // proxies are tried in order, and the first one which establishes the tunnel is used
+    pub fn proxies(mut self, proxy_urls: Vec<Url>) -> Self {
+        self.proxy_urls = proxy_urls;
+        self
+    }
+
+    // connect directly when all proxies are failed
+    pub fn direct_fallback(mut self, direct_fallback: bool) -> Self {
+        self.direct_fallback = direct_fallback;
         self
     }
 
     pub fn build(self) -> Result<SseTlsConnector> {
+        let builder = self.clone();
         // set ca
         let mut ca = RootCertStore::new();
         self.ca_paths
@@ -58,29 +164,384 @@ impl SseTlsConnectorBuilder {
             .try_for_each(|path| ca.add_ca(path))
             .map_err(|e| SseConnectionError::CAFileIOError(e))?;
 
-        // set proxy
-        if let Some(proxy_url) = self.proxy_url.as_ref() {
-            let client_connection = ClientConnection::proxy_connection(&self.url, proxy_url, ca)?;
-            return Ok(SseTlsConnector::new(client_connection));
+        let mut client_connection = self.client_connection(ca)?;
+        client_connection
+            .tcp_stream
+            .set_read_timeout(self.read_timeout)
+            .map_err(|e| SseConnectionError::ConnectError(ConnectionPhase::Connect, e))?;
+        if let Some(pin) = self.pin_sha256.as_ref() {
+            // peer certificates are available only after the handshake
+            client_connection.complete_handshake()?;
+            verify_pin(client_connection.client.peer_certificates(), pin)?;
+        }
+        let mut connector = SseTlsConnector::new(client_connection, self.lossy_utf8);
+        connector.builder = Some(builder);
+        connector.conn.read_timeout = self.read_timeout;
+        connector.conn.max_stream_bytes = self.max_stream_bytes;
+        connector.conn.header_limit = self.header_limit;
+        connector
+            .conn
+            .set_stream_on_error_status(self.stream_on_error_status);
+        Ok(connector)
+    }
+
+    // async connectors open the socket on each request, so building does not connect
+    #[cfg(feature = "tokio")]
+    pub fn build_async(self) -> Result<super::async_connector::AsyncSseTlsConnector> {
+        let mut ca = RootCertStore::new();
+        self.ca_paths
+            .iter()
+            .try_for_each(|path| ca.add_ca(path))
+            .map_err(SseConnectionError::CAFileIOError)?;
+        Ok(super::async_connector::AsyncSseTlsConnector::new(
+            self.url,
+            ca.client_config(),
+            self.proxy_urls,
+            self.direct_fallback,
+            self.proxy_keep_alive,
+            self.proxy_headers,
+            self.read_timeout,
+        ))
+    }
+
+    // check the configuration without opening any socket
+    pub fn validate(&self) -> Result<()> {
+        Self::validate_url(&self.url)?;
+        if !self.url.is_secure() {
+            return Err(SseConnectionError::InvalidUrl(format!(
+                "tls connector requires https: {}",
+                self.url
+            )));
+        }
+        rustls::ServerName::try_from(self.url.host())
+            .map_err(|_e| SseConnectionError::DnsError(InvalidDnsNameError::new(&self.url)))?;
+        self.proxy_urls.iter().try_for_each(Self::validate_url)?;
+        let mut ca = RootCertStore::new();
+        self.ca_paths
+            .iter()
+            .try_for_each(|path| ca.add_ca(path))
+            .map_err(SseConnectionError::CAFileIOError)
+    }
+
+    fn validate_url(url: &Url) -> Result<()> {
+        if url.host().is_empty() {
+            return Err(SseConnectionError::InvalidUrl(format!(
+                "host is empty: {}",
+                url
+            )));
+        }
+        Ok(())
+    }
+
+    fn client_connection(&self, ca: RootCertStore) -> Result<ClientConnection> {
+        if self.proxy_urls.is_empty() {
+            return ClientConnection::default(&self.url, ca, self.connect_timeout);
+        }
+        let mut last_error = None;
+        for proxy_url in &self.proxy_urls {
+            match ClientConnection::proxy_connection(
+                &self.url,
+                proxy_url,
+                ca.clone(),
+                self.proxy_keep_alive,
+                &self.proxy_headers,
+                self.connect_timeout,
+            ) {
+                Ok(client_connection) => return Ok(client_connection),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        match last_error {
+            Some(_) if self.direct_fallback => {
+                ClientConnection::default(&self.url, ca, self.connect_timeout)
+            }
+            Some(e) => Err(e),
+            None => unreachable!("proxy_urls is not empty"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SseTcpConnectorBuilder {
+    url: Url,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    stream_on_error_status: bool,
+}
+
+impl SseTcpConnectorBuilder {
+    pub fn new(url: impl Into<Url>) -> Self {
+        Self {
+            url: url.into(),
+            connect_timeout: None,
+            read_timeout: None,
+            stream_on_error_status: false,
+        }
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    // read events of an error response when its content type is text/event-stream
+    pub fn stream_on_error_status(mut self, stream_on_error_status: bool) -> Self {
+        self.stream_on_error_status = stream_on_error_status;
+        self
+    }
+
+    fn override_connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout.or(self.connect_timeout);
+        self
+    }
+
+    pub fn build(self) -> Result<SseTcpConnector> {
+        let tcp_stream = connect_tcp(&self.url, self.connect_timeout)?;
+        tcp_stream
+            .set_read_timeout(self.read_timeout)
+            .map_err(|e| SseConnectionError::ConnectError(ConnectionPhase::Connect, e))?;
+        let socket = TcpSocket::new(tcp_stream)
+            .map_err(|e| SseConnectionError::ConnectError(ConnectionPhase::Connect, e))?;
+        let mut conn = SseConnection::new(socket);
+        conn.read_timeout = self.read_timeout;
+        conn.set_stream_on_error_status(self.stream_on_error_status);
+        Ok(SseTcpConnector {
+            conn,
+            builder: self,
+            connect_timeout: None,
+        })
+    }
+}
+
+// plain http connector, mainly for local sse servers
+pub struct SseTcpConnector {
+    conn: SseConnection<TcpSocket>,
+    builder: SseTcpConnectorBuilder,
+    // overrides the timeout of the builder on reconnect
+    connect_timeout: Option<Duration>,
+}
+
+// the url of requests is only used for the request line and the host header
+#[cfg(unix)]
+#[derive(Clone)]
+pub struct SseUnixConnectorBuilder {
+    path: std::path::PathBuf,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    stream_on_error_status: bool,
+}
+
+#[cfg(unix)]
+impl SseUnixConnectorBuilder {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            connect_timeout: None,
+            read_timeout: None,
+            stream_on_error_status: false,
         }
+    }
+
+    // a connect to a listener with a full backlog blocks, so it can be bounded as well
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    pub fn stream_on_error_status(mut self, stream_on_error_status: bool) -> Self {
+        self.stream_on_error_status = stream_on_error_status;
+        self
+    }
+
+    fn override_connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout.or(self.connect_timeout);
+        self
+    }
 
-        let client_connection = ClientConnection::default(&self.url, ca)?;
-        Ok(SseTlsConnector::new(client_connection))
+    pub fn build(self) -> Result<SseUnixConnector> {
+        let stream = connect_unix(&self.path, self.connect_timeout)
+            .map_err(|e| SseConnectionError::ConnectError(ConnectionPhase::Connect, e))?;
+        stream
+            .set_read_timeout(self.read_timeout)
+            .map_err(|e| SseConnectionError::ConnectError(ConnectionPhase::Connect, e))?;
+        let socket = UnixSocket::new(stream)
+            .map_err(|e| SseConnectionError::ConnectError(ConnectionPhase::Connect, e))?;
+        let mut conn = SseConnection::new(socket);
+        conn.read_timeout = self.read_timeout;
+        conn.set_stream_on_error_status(self.stream_on_error_status);
+        Ok(SseUnixConnector {
+            conn,
+            builder: self,
+            connect_timeout: None,
+        })
     }
 }
 
+// connector over a unix domain socket, for local services which do not listen on tcp
+#[cfg(unix)]
+pub struct SseUnixConnector {
+    conn: SseConnection<UnixSocket>,
+    builder: SseUnixConnectorBuilder,
+    connect_timeout: Option<Duration>,
+}
+
 pub struct SseTlsConnector {
     conn: SseConnection<TlsSocket<StreamOwned>>,
+    // to open a new socket on reconnect
+    builder: Option<SseTlsConnectorBuilder>,
+    connect_timeout: Option<Duration>,
 }
 
 impl SseTlsConnector {
-    fn new(client_connection: ClientConnection) -> Self {
+    fn new(client_connection: ClientConnection, lossy_utf8: bool) -> Self {
         let stream = StreamOwned::new(client_connection);
-        let socket = TlsSocket::new(stream);
+        let mut conn = SseConnection::new(TlsSocket::new(stream));
+        conn.lossy_utf8 = lossy_utf8;
         Self {
-            conn: SseConnection::new(socket),
+            conn,
+            builder: None,
+            connect_timeout: None,
+        }
+    }
+    pub fn handshake(&mut self) -> Result<()> {
+        self.conn.conn.reader.get_ref().complete_handshake()
+    }
+    // None until the handshake is completed
+    pub fn peer_certificates(&self) -> Option<Vec<Certificate>> {
+        self.conn.conn.reader.get_ref().peer_certificates()
+    }
+}
+
+// the established tunnel is kept by the connector and reused for subsequent requests
+pub(crate) fn tunnel_request(url: &Url, keep_alive: bool, headers: &[(String, String)]) -> Request {
+    let req = headers
+        .iter()
+        .fold(RequestBuilder::new(url), |req, (key, value)| {
+            req.proxy_header(key, value)
+        });
+    if keep_alive {
+        return req
+            .proxy_header("Proxy-Connection", "keep-alive")
+            .connect_request();
+    }
+    req.connect_request()
+}
+pub(crate) const DNS_TIMEOUT: Duration = Duration::from_secs(5);
+pub(crate) const DNS_RETRIES: u32 = 2;
+pub(crate) const DNS_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+// resolve the host before connecting, so that dns failures are told apart from connect errors
+fn connect_tcp(url: &Url, timeout: Option<Duration>) -> Result<TcpStream> {
+    let addrs = resolve(url)?;
+    let Some(timeout) = timeout else {
+        return TcpStream::connect(addrs.as_slice())
+            .map_err(|e| SseConnectionError::ConnectError(ConnectionPhase::Connect, e));
+    };
+    // each address is tried in order like connect, but bounded by the timeout
+    let mut last_error = None;
+    for addr in &addrs {
+        match TcpStream::connect_timeout(addr, timeout) {
+            Ok(tcp_stream) => return Ok(tcp_stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(SseConnectionError::ConnectError(
+        ConnectionPhase::Connect,
+        last_error.expect("resolve returns at least one address"),
+    ))
+}
+// std has no timeout for the connect of a unix domain socket,
+// so the connect runs on its own thread like the dns lookup
+#[cfg(unix)]
+fn connect_unix(
+    path: &Path,
+    timeout: Option<Duration>,
+) -> std::io::Result<std::os::unix::net::UnixStream> {
+    let Some(timeout) = timeout else {
+        return std::os::unix::net::UnixStream::connect(path);
+    };
+    let path = path.to_path_buf();
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(std::os::unix::net::UnixStream::connect(path));
+    });
+    rx.recv_timeout(timeout).unwrap_or_else(|_e| {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            "connect timed out",
+        ))
+    })
+}
+// lookups are retried shortly for transient failures of the resolver,
+// and each lookup runs on its own thread because std has no timeout for it
+fn resolve(url: &Url) -> Result<Vec<SocketAddr>> {
+    let mut attempt = 0;
+    let mut lookup = spawn_lookup(url);
+    loop {
+        match lookup.recv_timeout(DNS_TIMEOUT) {
+            Ok(Ok(addrs)) if !addrs.is_empty() => return Ok(addrs),
+            Ok(Ok(_)) => return Err(dns_error(url, "no address found".to_string())),
+            Ok(Err(e)) if attempt >= DNS_RETRIES || !is_transient_dns_error(&e) => {
+                return Err(dns_error(url, e.to_string()))
+            }
+            Ok(Err(_)) => {
+                std::thread::sleep(DNS_RETRY_DELAY);
+                lookup = spawn_lookup(url);
+            }
+            // the timed out lookup is left behind, and a fresh one is started on another thread
+            Err(RecvTimeoutError::Timeout) if attempt < DNS_RETRIES => lookup = spawn_lookup(url),
+            Err(RecvTimeoutError::Timeout) => {
+                return Err(dns_error(url, "lookup timed out".to_string()))
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(dns_error(url, "lookup failed".to_string()))
+            }
         }
+        attempt += 1;
+    }
+}
+fn spawn_lookup(url: &Url) -> Receiver<std::io::Result<Vec<SocketAddr>>> {
+    let addr = url.to_addr_str();
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(addr.to_socket_addrs().map(Iterator::collect::<Vec<_>>));
+    });
+    rx
+}
+// a definitive answer of the resolver, like an unknown name, is not retried.
+// std reports EAI_AGAIN of getaddrinfo only by its message, without a kind or an os error code
+pub(crate) fn is_transient_dns_error(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::TimedOut
+            | std::io::ErrorKind::Interrupted
+            | std::io::ErrorKind::WouldBlock
+    ) || e
+        .to_string()
+        .contains("Temporary failure in name resolution")
+}
+pub(crate) fn dns_error(url: &Url, reason: String) -> SseConnectionError {
+    SseConnectionError::DnsError(InvalidDnsNameError::new(url).reason(reason))
+}
+fn verify_pin(certs: Option<&[Certificate]>, pin: &[u8; 32]) -> Result<()> {
+    let Some(leaf) = certs.and_then(|certs| certs.first()) else {
+        return Err(SseConnectionError::CertificatePinMismatch);
+    };
+    let digest = ring::digest::digest(&ring::digest::SHA256, &leaf.0);
+    if digest.as_ref() != pin {
+        return Err(SseConnectionError::CertificatePinMismatch);
     }
+    Ok(())
 }
 
 struct ClientConnection {
@@ -91,15 +552,29 @@ impl ClientConnection {
     fn new(client: rustls::ClientConnection, tcp_stream: TcpStream) -> Self {
         Self { client, tcp_stream }
     }
-    fn proxy_connection(url: &Url, proxy_url: &Url, certs: RootCertStore) -> Result<Self> {
+    fn complete_handshake(&mut self) -> Result<()> {
+        while self.client.is_handshaking() {
+            self.client
+                .complete_io(&mut self.tcp_stream)
+                .map_err(|e| SseConnectionError::ConnectError(ConnectionPhase::Handshake, e))?;
+        }
+        Ok(())
+    }
+    fn proxy_connection(
+        url: &Url,
+        proxy_url: &Url,
+        certs: RootCertStore,
+        keep_alive: bool,
+        headers: &[(String, String)],
+        connect_timeout: Option<Duration>,
+    ) -> Result<Self> {
         let client = Self::client(url, certs)?;
 
-        let mut tcp_stream = TcpStream::connect(proxy_url.to_addr_str())
-            .map_err(|e| SseConnectionError::ConnectError(e))?;
-        let req = RequestBuilder::new(url).connect_request();
+        let mut tcp_stream = connect_tcp(proxy_url, connect_timeout)?;
+        let req = tunnel_request(url, keep_alive, headers);
         tcp_stream
             .write_all(req.bytes())
-            .map_err(|e| SseConnectionError::ConnectError(e))?;
+            .map_err(|e| SseConnectionError::ConnectError(ConnectionPhase::Write, e))?;
 
         let mut buf = vec![0; 4096];
 
@@ -119,9 +594,8 @@ impl ClientConnection {
         ))
         .map_err(|e| SseConnectionError::ProxyConnectionError(e))
     }
-    fn default(url: &Url, certs: RootCertStore) -> Result<Self> {
-        let tcp_stream = TcpStream::connect(url.to_addr_str())
-            .map_err(|e| SseConnectionError::ConnectError(e))?;
+    fn default(url: &Url, certs: RootCertStore, connect_timeout: Option<Duration>) -> Result<Self> {
+        let tcp_stream = connect_tcp(url, connect_timeout)?;
         let client = Self::client(url, certs)?;
         Ok(Self::new(client, tcp_stream))
     }
@@ -130,15 +604,12 @@ impl ClientConnection {
             .host()
             .try_into()
             .map_err(|_e| SseConnectionError::DnsError(InvalidDnsNameError::new(url)))?;
-        let config = ClientConfig::builder()
-            .with_safe_defaults()
-            .with_root_certificates(certs.root_store)
-            .with_no_client_auth();
-        let client = rustls::ClientConnection::new(Arc::new(config), ip).unwrap();
+        let client = rustls::ClientConnection::new(certs.client_config(), ip).unwrap();
         Ok(client)
     }
 }
 
+#[derive(Clone)]
 struct RootCertStore {
     root_store: rustls::RootCertStore,
 }
@@ -167,31 +638,271 @@ impl RootCertStore {
         self.root_store.add(&cert).unwrap();
         Ok(())
     }
+    fn client_config(self) -> Arc<ClientConfig> {
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(self.root_store)
+            .with_no_client_auth();
+        Arc::new(config)
+    }
 }
 
 impl SseConnector for SseTlsConnector {
     type Socket = TlsSocket<StreamOwned>;
     fn connect(&mut self, req: &Request) -> Result<&mut SseConnection<Self::Socket>> {
         self.conn
-            .write(req.bytes())
-            .map_err(|e| SseConnectionError::ConnectError(e))?;
+            .write_request(req)
+            .map_err(|e| SseConnectionError::ConnectError(ConnectionPhase::Write, e))?;
+        Ok(&mut self.conn)
+    }
+    fn set_connect_timeout(&mut self, timeout: Option<Duration>) {
+        self.connect_timeout = timeout;
+    }
+    fn reconnect(&mut self) -> Result<()> {
+        let Some(builder) = self.builder.clone() else {
+            return Ok(());
+        };
+        let builder = builder.override_connect_timeout(self.connect_timeout);
+        self.conn.reopen(builder.build()?.conn.conn);
+        Ok(())
+    }
+    fn is_healthy(&mut self) -> bool {
+        self.conn.is_healthy()
+    }
+    fn is_drained(&self) -> bool {
+        self.conn.is_drained()
+    }
+}
+
+impl SseConnector for SseTcpConnector {
+    type Socket = TcpSocket;
+    fn connect(&mut self, req: &Request) -> Result<&mut SseConnection<Self::Socket>> {
+        self.conn
+            .write_request(req)
+            .map_err(|e| SseConnectionError::ConnectError(ConnectionPhase::Write, e))?;
+        Ok(&mut self.conn)
+    }
+    fn set_connect_timeout(&mut self, timeout: Option<Duration>) {
+        self.connect_timeout = timeout;
+    }
+    fn reconnect(&mut self) -> Result<()> {
+        let builder = self
+            .builder
+            .clone()
+            .override_connect_timeout(self.connect_timeout);
+        self.conn.reopen(builder.build()?.conn.conn);
+        Ok(())
+    }
+    fn is_healthy(&mut self) -> bool {
+        self.conn.is_healthy()
+    }
+    fn is_drained(&self) -> bool {
+        self.conn.is_drained()
+    }
+}
+
+#[cfg(unix)]
+impl SseConnector for SseUnixConnector {
+    type Socket = UnixSocket;
+    fn connect(&mut self, req: &Request) -> Result<&mut SseConnection<Self::Socket>> {
+        self.conn
+            .write_request(req)
+            .map_err(|e| SseConnectionError::ConnectError(ConnectionPhase::Write, e))?;
+        Ok(&mut self.conn)
+    }
+    fn set_connect_timeout(&mut self, timeout: Option<Duration>) {
+        self.connect_timeout = timeout;
+    }
+    fn reconnect(&mut self) -> Result<()> {
+        let builder = self
+            .builder
+            .clone()
+            .override_connect_timeout(self.connect_timeout);
+        self.conn.reopen(builder.build()?.conn.conn);
+        Ok(())
+    }
+    fn is_healthy(&mut self) -> bool {
+        self.conn.is_healthy()
+    }
+    fn is_drained(&self) -> bool {
+        self.conn.is_drained()
+    }
+}
+
+// called with the connect timeout overriding the one of the build
+type Reopen = dyn FnMut(Option<Duration>) -> Result<Box<dyn Socket>>;
+// connector with a boxed socket, to choose the connector at runtime
+pub struct DynSseConnector {
+    conn: SseConnection<Box<dyn Socket>>,
+    // None for a socket given by the user, which cannot be opened again
+    reopen: Option<Box<Reopen>>,
+    connect_timeout: Option<Duration>,
+}
+impl DynSseConnector {
+    pub fn new(socket: impl Socket + 'static) -> Self {
+        Self {
+            conn: SseConnection::new(Box::new(socket)),
+            reopen: None,
+            connect_timeout: None,
+        }
+    }
+    // tls for an https url and plain tcp for an http one, with the defaults of each builder
+    pub fn for_url(url: &Url) -> Result<Self> {
+        if url.is_secure() {
+            return Ok(SseTlsConnectorBuilder::new(url).build()?.into());
+        }
+        Ok(SseTcpConnectorBuilder::new(url).build()?.into())
+    }
+    // the settings given at build time are kept, like the read timeout restored after overrides
+    fn with_connection(conn: SseConnection<impl Socket + 'static>) -> Self {
+        Self {
+            conn: conn.into_boxed(),
+            reopen: None,
+            connect_timeout: None,
+        }
+    }
+    fn reopen_with(
+        mut self,
+        reopen: impl FnMut(Option<Duration>) -> Result<Box<dyn Socket>> + 'static,
+    ) -> Self {
+        self.reopen = Some(Box::new(reopen));
+        self
+    }
+}
+impl From<SseTlsConnector> for DynSseConnector {
+    fn from(connector: SseTlsConnector) -> Self {
+        let dyn_connector = Self::with_connection(connector.conn);
+        match connector.builder {
+            Some(builder) => dyn_connector.reopen_with(move |timeout| {
+                let builder = builder.clone().override_connect_timeout(timeout);
+                Ok(Box::new(builder.build()?.conn.conn) as Box<dyn Socket>)
+            }),
+            None => dyn_connector,
+        }
+    }
+}
+impl From<SseTcpConnector> for DynSseConnector {
+    fn from(connector: SseTcpConnector) -> Self {
+        let builder = connector.builder;
+        Self::with_connection(connector.conn).reopen_with(move |timeout| {
+            let builder = builder.clone().override_connect_timeout(timeout);
+            Ok(Box::new(builder.build()?.conn.conn) as Box<dyn Socket>)
+        })
+    }
+}
+#[cfg(unix)]
+impl From<SseUnixConnector> for DynSseConnector {
+    fn from(connector: SseUnixConnector) -> Self {
+        let builder = connector.builder;
+        Self::with_connection(connector.conn).reopen_with(move |timeout| {
+            let builder = builder.clone().override_connect_timeout(timeout);
+            Ok(Box::new(builder.build()?.conn.conn) as Box<dyn Socket>)
+        })
+    }
+}
+impl SseConnector for DynSseConnector {
+    type Socket = Box<dyn Socket>;
+    fn connect(&mut self, req: &Request) -> Result<&mut SseConnection<Self::Socket>> {
+        self.conn
+            .write_request(req)
+            .map_err(|e| SseConnectionError::ConnectError(ConnectionPhase::Write, e))?;
         Ok(&mut self.conn)
     }
+    fn set_connect_timeout(&mut self, timeout: Option<Duration>) {
+        self.connect_timeout = timeout;
+    }
+    fn reconnect(&mut self) -> Result<()> {
+        if let Some(reopen) = self.reopen.as_mut() {
+            self.conn.reopen(reopen(self.connect_timeout)?);
+        }
+        Ok(())
+    }
+    fn is_healthy(&mut self) -> bool {
+        self.conn.is_healthy()
+    }
+    fn is_drained(&self) -> bool {
+        self.conn.is_drained()
+    }
 }
 
 pub trait SseConnector {
     type Socket: Socket;
     fn connect(&mut self, req: &Request) -> Result<&mut SseConnection<Self::Socket>>;
+    // bound the connection of the following reconnects, None restores the timeout of the build.
+    // the builtin connectors connect at build time, and apply it when the socket is opened again,
+    // which the subscriber does for a request with its own connect timeout
+    fn set_connect_timeout(&mut self, _timeout: Option<Duration>) {}
+    // whether the reused connection can take the next request, without sending anything
+    fn is_healthy(&mut self) -> bool {
+        true
+    }
+    // open a new socket in place of a dropped one, before the request is sent again.
+    // connectors which cannot open a socket by themselves keep the current one
+    fn reconnect(&mut self) -> Result<()> {
+        Ok(())
+    }
+    // whether the last response is read to its end, so that no stale event is left on the connection
+    fn is_drained(&self) -> bool {
+        false
+    }
 }
 
 pub trait Socket {
     fn read_line(&mut self) -> std::result::Result<Option<String>, std::io::Error>;
+    // a line as read from the wire, up to limit bytes, so that the chunked coding is counted
+    // in bytes. sockets which only read whole lines ignore the limit
+    fn read_line_bytes(
+        &mut self,
+        _limit: usize,
+    ) -> std::result::Result<Option<Vec<u8>>, std::io::Error> {
+        Ok(self.read_line()?.map(String::into_bytes))
+    }
     fn write_all(&mut self, buf: &[u8]) -> std::result::Result<(), std::io::Error>;
+    fn set_read_timeout(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> std::result::Result<(), std::io::Error>;
+    // false when the peer is known to have closed the connection, checked without blocking.
+    // sockets which cannot peek are assumed to be open
+    fn is_open(&mut self) -> bool {
+        true
+    }
+}
+
+impl Socket for Box<dyn Socket> {
+    fn read_line(&mut self) -> std::result::Result<Option<String>, std::io::Error> {
+        self.as_mut().read_line()
+    }
+    fn read_line_bytes(
+        &mut self,
+        limit: usize,
+    ) -> std::result::Result<Option<Vec<u8>>, std::io::Error> {
+        self.as_mut().read_line_bytes(limit)
+    }
+    fn write_all(&mut self, buf: &[u8]) -> std::result::Result<(), std::io::Error> {
+        self.as_mut().write_all(buf)
+    }
+    fn set_read_timeout(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> std::result::Result<(), std::io::Error> {
+        self.as_mut().set_read_timeout(timeout)
+    }
+    fn is_open(&mut self) -> bool {
+        self.as_mut().is_open()
+    }
 }
 
 pub trait Stream: std::io::Write + std::io::Read + Sized {
     fn reader(&self) -> BufReader<Self>;
     fn writer(&self) -> BufWriter<Self>;
+    fn set_read_timeout(
+        &self,
+        timeout: Option<Duration>,
+    ) -> std::result::Result<(), std::io::Error>;
+    fn is_open(&self) -> bool {
+        true
+    }
 }
 
 #[derive(Debug)]
@@ -209,6 +920,26 @@ impl StreamOwned {
         }
     }
 }
+impl StreamOwned {
+    fn complete_handshake(&self) -> Result<()> {
+        let mut stream = self.client.borrow_mut();
+        let stream = &mut *stream;
+        while stream.conn.is_handshaking() {
+            stream
+                .conn
+                .complete_io(&mut stream.sock)
+                .map_err(|e| SseConnectionError::ConnectError(ConnectionPhase::Handshake, e))?;
+        }
+        Ok(())
+    }
+    fn peer_certificates(&self) -> Option<Vec<Certificate>> {
+        self.client
+            .borrow()
+            .conn
+            .peer_certificates()
+            .map(|certs| certs.to_vec())
+    }
+}
 impl Stream for StreamOwned {
     fn reader(&self) -> BufReader<Self> {
         let client = Arc::clone(&self.client);
@@ -218,6 +949,15 @@ impl Stream for StreamOwned {
         let client = Arc::clone(&self.client);
         BufWriter::new(Self { client })
     }
+    fn set_read_timeout(
+        &self,
+        timeout: Option<Duration>,
+    ) -> std::result::Result<(), std::io::Error> {
+        self.client.borrow().sock.set_read_timeout(timeout)
+    }
+    fn is_open(&self) -> bool {
+        peek_open(&self.client.borrow().sock)
+    }
 }
 impl std::io::Read for StreamOwned {
     fn read(&mut self, buf: &mut [u8]) -> std::result::Result<usize, std::io::Error> {
@@ -246,88 +986,743 @@ impl<S: Stream + Debug> TlsSocket<S> {
         }
     }
 }
+fn read_line_from(
+    reader: &mut impl BufRead,
+) -> std::result::Result<Option<String>, std::io::Error> {
+    let mut buf = String::new();
+    let size = reader.read_line(&mut buf)?;
+    Ok((size > 0).then_some(buf))
+}
+fn read_line_bytes_from(
+    reader: &mut impl BufRead,
+    limit: usize,
+) -> std::result::Result<Option<Vec<u8>>, std::io::Error> {
+    let mut buf = Vec::new();
+    let size = reader.take(limit as u64).read_until(b'\n', &mut buf)?;
+    Ok((size > 0).then_some(buf))
+}
 impl<S: Stream + Debug> Socket for TlsSocket<S> {
     fn read_line(&mut self) -> std::result::Result<Option<String>, std::io::Error> {
-        let mut buf = String::new();
-        let size = self.reader.read_line(&mut buf)?;
-        if size == 0 {
-            Ok(None)
-        } else {
-            Ok(Some(buf))
-        }
+        read_line_from(&mut self.reader)
     }
+    fn read_line_bytes(
+        &mut self,
+        limit: usize,
+    ) -> std::result::Result<Option<Vec<u8>>, std::io::Error> {
+        read_line_bytes_from(&mut self.reader, limit)
+    }
+    // the request is already built in memory, so it is passed to the stream at once
+    // instead of being split into the chunks of the buffer, which fragments tls records
     fn write_all(&mut self, buf: &[u8]) -> std::result::Result<(), std::io::Error> {
-        self.writer.write_all(buf)?;
         self.writer.flush()?;
-        Ok(())
+        let stream = self.writer.get_mut();
+        stream.write_all(buf)?;
+        stream.flush()
+    }
+    fn set_read_timeout(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> std::result::Result<(), std::io::Error> {
+        self.reader.get_ref().set_read_timeout(timeout)
+    }
+    fn is_open(&mut self) -> bool {
+        self.reader.get_ref().is_open()
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct SseConnection<S: Socket> {
-    conn: S,
+#[derive(Debug)]
+pub struct TcpSocket {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
 }
-impl<S: Socket> SseConnection<S> {
-    fn new(conn: S) -> Self {
-        Self { conn }
+impl TcpSocket {
+    fn new(stream: TcpStream) -> std::result::Result<Self, std::io::Error> {
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+        })
     }
-    pub fn write(&mut self, buf: &[u8]) -> std::result::Result<(), std::io::Error> {
-        self.conn.write_all(buf)
+}
+impl Socket for TcpSocket {
+    fn read_line(&mut self) -> std::result::Result<Option<String>, std::io::Error> {
+        read_line_from(&mut self.reader)
     }
-    pub fn read(&mut self) -> Result<ConnectedSseResponse> {
-        while let Some(line) = self
-            .conn
-            .read_line()
-            .map_err(|e| SseConnectionError::ConnectionError(e))?
-        {
-            if let Ok(http_status) = HttpStatusLine::from_str(&line) {
-                if !http_status.is_error() {
-                    continue;
-                };
-                return Err(self.http_error(http_status));
-            };
+    fn read_line_bytes(
+        &mut self,
+        limit: usize,
+    ) -> std::result::Result<Option<Vec<u8>>, std::io::Error> {
+        read_line_bytes_from(&mut self.reader, limit)
+    }
+    fn write_all(&mut self, buf: &[u8]) -> std::result::Result<(), std::io::Error> {
+        self.writer.write_all(buf)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+    fn set_read_timeout(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> std::result::Result<(), std::io::Error> {
+        self.reader.get_ref().set_read_timeout(timeout)
+    }
+    fn is_open(&mut self) -> bool {
+        peek_open(self.reader.get_ref())
+    }
+}
+// a socket closed by the peer is readable with 0 bytes, while an open one would block.
+// unread bytes, like the rest of the previous response, do not make the socket unhealthy
+fn peek_open(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let open = match stream.peek(&mut [0; 1]) {
+        Ok(size) => size > 0,
+        Err(e) => e.kind() == std::io::ErrorKind::WouldBlock,
+    };
+    stream.set_nonblocking(false).is_ok() && open
+}
+
+// each write is passed to the socket as is, so the head of a request stays in a single write
+struct SocketWriter<'a, S: Socket>(&'a mut S);
+impl<S: Socket> Write for SocketWriter<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> std::result::Result<usize, std::io::Error> {
+        self.0.write_all(buf)?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::result::Result<(), std::io::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct UnixSocket {
+    reader: BufReader<std::os::unix::net::UnixStream>,
+    writer: BufWriter<std::os::unix::net::UnixStream>,
+}
+#[cfg(unix)]
+impl UnixSocket {
+    fn new(stream: std::os::unix::net::UnixStream) -> std::result::Result<Self, std::io::Error> {
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+        })
+    }
+}
+#[cfg(unix)]
+impl Socket for UnixSocket {
+    fn read_line(&mut self) -> std::result::Result<Option<String>, std::io::Error> {
+        read_line_from(&mut self.reader)
+    }
+    fn read_line_bytes(
+        &mut self,
+        limit: usize,
+    ) -> std::result::Result<Option<Vec<u8>>, std::io::Error> {
+        read_line_bytes_from(&mut self.reader, limit)
+    }
+    fn write_all(&mut self, buf: &[u8]) -> std::result::Result<(), std::io::Error> {
+        self.writer.write_all(buf)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+    fn set_read_timeout(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> std::result::Result<(), std::io::Error> {
+        self.reader.get_ref().set_read_timeout(timeout)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SseConnection<S: Socket> {
+    conn: S,
+    // read timeout given at build time, restored on the next request after it is changed
+    read_timeout: Option<Duration>,
+    read_timeout_changed: bool,
+    // the first line of a response must be a status line or an event
+    first_line: bool,
+    // the body of the current response is read to its end
+    drained: bool,
+    bytes_read: usize,
+    header: HttpHeader,
+    in_header: bool,
+    last_header_key: Option<String>,
+    chunked: bool,
+    chunk_remaining: usize,
+    max_stream_bytes: Option<usize>,
+    header_limit: HeaderLimit,
+    // header lines and bytes of the current response
+    header_lines: usize,
+    header_bytes: usize,
+    // fields are read since the last blank line
+    pending_fields: bool,
+    heartbeats: usize,
+    stream_on_error_status: bool,
+    // invalid utf-8 is replaced after the chunked coding is removed, so that chunks are counted in bytes
+    lossy_utf8: bool,
+    status: Option<HttpStatusLine>,
+    // number of responses whose headers are completely read
+    opened: usize,
+    // lines without any event after this instant are failed
+    idle_deadline: Option<Instant>,
+}
+impl<S: Socket> SseConnection<S> {
+    fn new(conn: S) -> Self {
+        Self {
+            conn,
+            read_timeout: None,
+            read_timeout_changed: false,
+            first_line: true,
+            drained: false,
+            bytes_read: 0,
+            header: HttpHeader::new(),
+            // a stream without http preamble, like an already connected body, starts with events
+            in_header: false,
+            last_header_key: None,
+            chunked: false,
+            chunk_remaining: 0,
+            max_stream_bytes: None,
+            header_limit: HeaderLimit::default(),
+            header_lines: 0,
+            header_bytes: 0,
+            pending_fields: false,
+            heartbeats: 0,
+            stream_on_error_status: false,
+            lossy_utf8: false,
+            status: None,
+            opened: 0,
+            idle_deadline: None,
+        }
+    }
+    pub(crate) fn set_idle_deadline(&mut self, idle_deadline: Option<Instant>) {
+        self.idle_deadline = idle_deadline;
+    }
+    pub(crate) fn set_stream_on_error_status(&mut self, stream_on_error_status: bool) {
+        self.stream_on_error_status = stream_on_error_status;
+    }
+    // status code of the current response, None for a stream without http preamble
+    pub fn status_code(&self) -> Option<u32> {
+        self.status.map(|status| status.status_code().num())
+    }
+    pub fn opened(&self) -> usize {
+        self.opened
+    }
+    // headers of the current response
+    pub fn header(&self) -> &HttpHeader {
+        &self.header
+    }
+    // writing a request starts a new response stream
+    pub fn write(&mut self, buf: &[u8]) -> std::result::Result<(), std::io::Error> {
+        self.bytes_read = 0;
+        self.first_line = true;
+        self.drained = false;
+        self.restore_read_timeout()?;
+        self.conn.write_all(buf)
+    }
+    // unlike write, a streaming body of the request is written too
+    pub fn write_request(&mut self, req: &Request) -> std::result::Result<(), std::io::Error> {
+        self.bytes_read = 0;
+        self.first_line = true;
+        self.drained = false;
+        self.restore_read_timeout()?;
+        req.write_to(SocketWriter(&mut self.conn))
+    }
+    // the new socket starts without the state of the response read on the dropped one,
+    // and the settings given at build time are kept
+    pub(crate) fn reopen(&mut self, conn: S) {
+        let mut reopened = Self::new(conn);
+        reopened.read_timeout = self.read_timeout;
+        reopened.max_stream_bytes = self.max_stream_bytes;
+        reopened.header_limit = self.header_limit;
+        reopened.stream_on_error_status = self.stream_on_error_status;
+        reopened.lossy_utf8 = self.lossy_utf8;
+        *self = reopened;
+    }
+    fn into_boxed(self) -> SseConnection<Box<dyn Socket>>
+    where
+        S: 'static,
+    {
+        let mut boxed = SseConnection::new(Box::new(self.conn) as Box<dyn Socket>);
+        boxed.read_timeout = self.read_timeout;
+        boxed.max_stream_bytes = self.max_stream_bytes;
+        boxed.header_limit = self.header_limit;
+        boxed.stream_on_error_status = self.stream_on_error_status;
+        boxed.lossy_utf8 = self.lossy_utf8;
+        boxed
+    }
+    // a half-open connection, like a keep-alive closed by the server, fails the next request
+    pub fn is_healthy(&mut self) -> bool {
+        self.conn.is_open()
+    }
+    // false when a handler stopped the stream before its end, because the rest is still unread
+    pub fn is_drained(&self) -> bool {
+        self.drained
+    }
+    // the read timeout given at build time
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout
+    }
+    // change the read timeout of the established connection, applied from the next read
+    // until the next request
+    pub fn read_timeout_for(&mut self, timeout: Duration) -> Result<()> {
+        self.read_timeout_changed = true;
+        self.conn
+            .set_read_timeout(Some(timeout))
+            .map_err(SseConnectionError::ConnectionError)
+    }
+    fn restore_read_timeout(&mut self) -> std::result::Result<(), std::io::Error> {
+        if !self.read_timeout_changed {
+            return Ok(());
+        }
+        self.read_timeout_changed = false;
+        self.conn.set_read_timeout(self.read_timeout)
+    }
+    // bytes read from the current response stream
+    pub fn bytes_read(&self) -> usize {
+        self.bytes_read
+    }
+    // number of blank lines without any pending field, which are only liveness signals
+    pub fn heartbeats(&self) -> usize {
+        self.heartbeats
+    }
+    pub fn read(&mut self) -> Result<ConnectedSseResponse> {
+        self.read_with_heartbeat(|| {})
+    }
+    // the callback is called on each heartbeat while waiting for the next event,
+    // so that a stream of only heartbeats is observed before it ends
+    pub fn read_with_heartbeat(
+        &mut self,
+        mut on_heartbeat: impl FnMut(),
+    ) -> Result<ConnectedSseResponse> {
+        loop {
+            if let StreamRead::Response(res) = self.read_until_dispatch(&mut on_heartbeat)? {
+                return Ok(res);
+            }
+        }
+    }
+    // stops also at the blank line that dispatches the pending fields as one event,
+    // so that the subscriber counts events instead of fields
+    pub(crate) fn read_until_dispatch(
+        &mut self,
+        mut on_heartbeat: impl FnMut(),
+    ) -> Result<StreamRead> {
+        while let Some(line) = self.next_body_line()? {
+            // comments and heartbeats keep the socket busy, so the idle time is checked per line
+            if self
+                .idle_deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                return Err(SseConnectionError::IdleTimeout);
+            }
+            if line.trim().is_empty() {
+                if std::mem::take(&mut self.pending_fields) {
+                    return Ok(StreamRead::Dispatched);
+                }
+                self.heartbeats += 1;
+                on_heartbeat();
+                continue;
+            }
             // sse_response is look like header, so check sse_response first
             if let Ok(sse_response) = SseResponse::from_line(line.as_str()) {
-                return Ok(ConnectedSseResponse::Progress(sse_response));
+                // comments are only keep-alive signals, and unknown fields are ignored by the spec,
+                // so they are not passed to handlers
+                if sse_response.is_comment() || sse_response.is_other() {
+                    continue;
+                }
+                self.pending_fields = true;
+                return Ok(StreamRead::Response(ConnectedSseResponse::Progress {
+                    response: sse_response,
+                    raw: line,
+                }));
             };
             if let Ok(_header) = HttpHeader::from_line(line.as_str()) {
                 continue;
             };
         }
-        Ok(ConnectedSseResponse::Done)
+        Ok(StreamRead::Response(ConnectedSseResponse::Done))
+    }
+    // for a finite stream, like a replayed response in tests
+    pub fn read_all(&mut self) -> Result<Vec<SseResponse>> {
+        let mut responses = Vec::new();
+        while let ConnectedSseResponse::Progress { response, .. } = self.read()? {
+            responses.push(response);
+        }
+        Ok(responses)
+    }
+    // write only the data payloads. data lines of the same event are joined with a newline,
+    // and events are concatenated without separator
+    pub fn data_to_writer<W: Write>(&mut self, mut sink: W) -> Result<()> {
+        let mut in_event = false;
+        while let Some(line) = self.next_body_line()? {
+            if line.trim().is_empty() {
+                in_event = false;
+                continue;
+            }
+            let Ok(SseResponse::Data(data)) = SseResponse::from_line(line.as_str()) else {
+                continue;
+            };
+            let separator = if in_event { "\n" } else { "" };
+            in_event = true;
+            sink.write_all(separator.as_bytes())
+                .and_then(|_| sink.write_all(data.as_bytes()))
+                .and_then(|_| sink.flush())
+                .map_err(SseConnectionError::SinkError)?;
+        }
+        Ok(())
+    }
+    // discard the rest of the current event until the blank line
+    pub fn skip_event(&mut self) -> Result<()> {
+        while let Some(line) = self
+            .next_line()
+            .map_err(SseConnectionError::from_read_error)?
+        {
+            self.check_stream_size()?;
+            if line.trim().is_empty() {
+                self.pending_fields = false;
+                break;
+            }
+        }
+        Ok(())
+    }
+    pub fn pipe_to<W: Write>(&mut self, mut sink: W) -> Result<()> {
+        let mut is_body = false;
+        while let Some(line) = self
+            .read_line()
+            .map_err(SseConnectionError::from_read_error)?
+        {
+            self.check_stream_size()?;
+            if !is_body {
+                if let Ok(http_status) = HttpStatusLine::from_str(&line) {
+                    if http_status.is_error() {
+                        self.start_response(http_status);
+                        return Err(self.http_error(http_status));
+                    };
+                    continue;
+                };
+                // blank line is the end of http header
+                is_body = line.trim().is_empty();
+                continue;
+            }
+            sink.write_all(line.as_bytes())
+                .and_then(|_| sink.flush())
+                .map_err(SseConnectionError::SinkError)?;
+        }
+        Ok(())
     }
+    // the rest of the headers and the body are read like the ones of a stream,
+    // so that a chunked body is decoded
     fn http_error(&mut self, http_status: HttpStatusLine) -> SseConnectionError {
-        let mut header = HttpHeader::new();
+        match self.read_http_error(http_status) {
+            Ok(res) => SseConnectionError::HttpError(res),
+            Err(e) => e,
+        }
+    }
+    fn read_http_error(&mut self, http_status: HttpStatusLine) -> Result<HttpResponse> {
+        while self.in_header {
+            let Some(line) = self
+                .next_line()
+                .map_err(SseConnectionError::from_read_error)?
+            else {
+                break;
+            };
+            self.read_header(&line)?;
+        }
+        // a keep-alive connection is not closed after the body, so it is read only up to its length
+        let mut remaining = match self.chunked {
+            true => None,
+            false => self
+                .header
+                .get_ignore_case("Content-Length")
+                .and_then(|length| length.trim().parse::<usize>().ok()),
+        };
         let mut body = HttpBody::new();
-        while let Some(line) = self.conn.read_line().map_or(None, |r| r) {
-            if let Ok(add_header) = HttpHeader::from_line(line.as_str()) {
-                header.concat(add_header);
+        loop {
+            let line = match remaining {
+                Some(0) => break,
+                Some(length) => {
+                    let line = self
+                        .read_line_bytes(length)
+                        .map_err(SseConnectionError::from_read_error)?;
+                    remaining = Some(length - line.as_ref().map_or(length, Vec::len));
+                    line.map(|line| self.decode(line))
+                        .transpose()
+                        .map_err(SseConnectionError::from_read_error)?
+                }
+                None => self
+                    .next_line()
+                    .map_err(SseConnectionError::from_read_error)?,
+            };
+            let Some(line) = line else {
+                break;
+            };
+            body.concat(HttpBody::from_line(line.as_str()));
+        }
+        Ok(HttpResponse::new(http_status, self.header.clone(), body))
+    }
+    // a status line starts a new response on the connection
+    fn start_response(&mut self, http_status: HttpStatusLine) {
+        self.header = HttpHeader::new();
+        self.in_header = true;
+        self.last_header_key = None;
+        self.chunked = false;
+        self.chunk_remaining = 0;
+        self.header_lines = 0;
+        self.header_bytes = 0;
+        self.status = Some(http_status);
+    }
+    fn read_header(&mut self, line: &str) -> Result<()> {
+        if !line.trim().is_empty() {
+            self.header_lines += 1;
+            self.header_bytes += line.len();
+            self.header_limit
+                .check(self.header_lines, self.header_bytes)?;
+        }
+        self.read_header_line(line);
+        Ok(())
+    }
+    // status lines and headers are consumed here, so only the lines of the body are returned
+    fn next_body_line(&mut self) -> Result<Option<String>> {
+        while let Some(line) = self
+            .next_line()
+            .map_err(SseConnectionError::from_read_error)?
+        {
+            self.check_stream_size()?;
+            // blank lines left by the previous response on a reused connection are not the first line
+            let first_line = self.first_line && !line.trim().is_empty();
+            self.first_line &= line.trim().is_empty();
+            let http_status = match HttpStatusLine::from_str(&line) {
+                // a stream without http preamble starts with an event, so anything else is malformed
+                Err(e) if first_line && SseResponse::from_line(&line).is_err() => {
+                    return Err(SseConnectionError::InvalidStatusLine(e));
+                }
+                http_status => http_status,
+            };
+            if let Ok(http_status) = http_status {
+                self.start_response(http_status);
+                if http_status.is_error() && !self.stream_on_error_status {
+                    return Err(self.http_error(http_status));
+                };
+                continue;
+            };
+            if self.in_header {
+                self.read_header(&line)?;
+                if !self.in_header {
+                    self.open()?;
+                }
                 continue;
+            }
+            return Ok(Some(line));
+        }
+        self.drained = true;
+        Ok(None)
+    }
+    // an error response is streamed only when it is an event stream
+    fn open(&mut self) -> Result<()> {
+        self.opened += 1;
+        match self.status {
+            Some(status) if status.is_error() && !self.header.is_event_stream() => {
+                Err(self.http_error(status))
+            }
+            _ => Ok(()),
+        }
+    }
+    // header lines are accumulated until the blank line
+    fn read_header_line(&mut self, line: &str) {
+        if line.trim().is_empty() {
+            self.in_header = false;
+            self.chunked = self
+                .header
+                .get_ignore_case("Transfer-Encoding")
+                .is_some_and(|value| value.contains("chunked"));
+            return;
+        }
+        if line.starts_with([' ', '\t']) {
+            if let Some(key) = self.last_header_key.as_ref() {
+                self.header.append_folded(key, line);
+            }
+            return;
+        }
+        if let Ok(header) = HttpHeader::from_line(line) {
+            self.last_header_key = line.split_once(':').map(|(key, _)| key.trim().to_string());
+            self.header.concat(header);
+        }
+    }
+    fn next_line(&mut self) -> std::result::Result<Option<String>, std::io::Error> {
+        if self.chunked && !self.in_header {
+            return self.read_chunked_line();
+        }
+        self.read_line()
+    }
+    // a line of the chunked body may be split across chunks, so join the pieces.
+    // the chunk sizes are counted in the bytes on the wire, and the line is decoded at the end
+    fn read_chunked_line(&mut self) -> std::result::Result<Option<String>, std::io::Error> {
+        let mut line = Vec::new();
+        loop {
+            if self.chunk_remaining == 0 {
+                let Some(size_line) = self.read_line()? else {
+                    return Ok(None);
+                };
+                let size = size_line.split(';').next().unwrap_or_default().trim();
+                let size = usize::from_str_radix(size, 16).map_err(|_e| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("invalid chunk size: {}", size_line),
+                    )
+                })?;
+                if size == 0 {
+                    self.read_trailers()?;
+                    return Ok(None);
+                }
+                self.chunk_remaining = size;
+            }
+            let Some(mut piece) = self.read_line_bytes(self.chunk_remaining)? else {
+                return Ok(None);
             };
-            let add_body = HttpBody::from_line(line.as_str());
-            body.concat(add_body)
+            if piece.len() <= self.chunk_remaining {
+                self.chunk_remaining -= piece.len();
+                line.append(&mut piece);
+                if self.chunk_remaining == 0 {
+                    // crlf at the end of the chunk
+                    self.read_line_bytes(usize::MAX)?;
+                }
+            } else {
+                // a socket ignoring the limit reads the crlf at the end of the chunk with the piece
+                piece.truncate(self.chunk_remaining);
+                line.append(&mut piece);
+                self.chunk_remaining = 0;
+            }
+            if line.ends_with(b"\n") {
+                return self.decode(line).map(Some);
+            }
+        }
+    }
+    // trailer headers after the last chunk are not events, so discard them
+    fn read_trailers(&mut self) -> std::result::Result<(), std::io::Error> {
+        while let Some(line) = self.read_line()? {
+            if line.trim().is_empty() {
+                break;
+            }
+        }
+        self.chunked = false;
+        Ok(())
+    }
+    fn check_stream_size(&self) -> Result<()> {
+        match self.max_stream_bytes {
+            Some(max) if self.bytes_read > max => Err(SseConnectionError::StreamTooLarge(max)),
+            _ => Ok(()),
+        }
+    }
+    fn read_line(&mut self) -> std::result::Result<Option<String>, std::io::Error> {
+        self.read_line_bytes(usize::MAX)?
+            .map(|line| self.decode(line))
+            .transpose()
+    }
+    fn read_line_bytes(
+        &mut self,
+        limit: usize,
+    ) -> std::result::Result<Option<Vec<u8>>, std::io::Error> {
+        let line = self.conn.read_line_bytes(limit)?;
+        if let Some(line) = line.as_ref() {
+            self.bytes_read += line.len();
+        }
+        Ok(line)
+    }
+    fn decode(&self, line: Vec<u8>) -> std::result::Result<String, std::io::Error> {
+        match String::from_utf8(line) {
+            Ok(line) => Ok(line),
+            Err(e) if self.lossy_utf8 => Ok(String::from_utf8_lossy(e.as_bytes()).into_owned()),
+            Err(_e) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "stream did not contain valid UTF-8",
+            )),
+        }
+    }
+}
+const DEFAULT_MAX_HEADERS: usize = 100;
+const DEFAULT_MAX_HEADER_BYTES: usize = 64 * 1024;
+
+// headers are accumulated in memory, so a server sending endless headers is rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct HeaderLimit {
+    max_headers: usize,
+    max_header_bytes: usize,
+}
+impl Default for HeaderLimit {
+    fn default() -> Self {
+        Self {
+            max_headers: DEFAULT_MAX_HEADERS,
+            max_header_bytes: DEFAULT_MAX_HEADER_BYTES,
+        }
+    }
+}
+impl HeaderLimit {
+    fn check(&self, lines: usize, bytes: usize) -> Result<()> {
+        if lines > self.max_headers || bytes > self.max_header_bytes {
+            return Err(SseConnectionError::HeadersTooLarge {
+                max_headers: self.max_headers,
+                max_header_bytes: self.max_header_bytes,
+            });
         }
-        SseConnectionError::HttpError(HttpResponse::new(http_status, header, body))
+        Ok(())
     }
 }
+// what a read of the stream stopped at
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum StreamRead {
+    Response(ConnectedSseResponse),
+    // a blank line ended the pending fields
+    Dispatched,
+}
 #[derive(Debug, PartialEq, Clone)]
 pub enum ConnectedSseResponse {
-    Progress(SseResponse),
+    // the raw line is kept as it was on the wire, to debug a misparse
+    Progress { response: SseResponse, raw: String },
     Done,
 }
+impl ConnectedSseResponse {
+    pub fn response(&self) -> Option<&SseResponse> {
+        match self {
+            Self::Progress { response, .. } => Some(response),
+            Self::Done => None,
+        }
+    }
+    pub fn raw(&self) -> Option<&str> {
+        match self {
+            Self::Progress { raw, .. } => Some(raw),
+            Self::Done => None,
+        }
+    }
+    pub fn into_response(self) -> Option<SseResponse> {
+        match self {
+            Self::Progress { response, .. } => Some(response),
+            Self::Done => None,
+        }
+    }
+}
 
 #[derive(Debug, Error)]
 pub struct InvalidDnsNameError {
     name: Url,
+    // why the resolution failed, None when the name itself is invalid
+    reason: Option<String>,
 }
 impl InvalidDnsNameError {
     pub fn new(name: impl Into<Url>) -> Self {
-        Self { name: name.into() }
+        Self {
+            name: name.into(),
+            reason: None,
+        }
+    }
+    fn reason(mut self, reason: String) -> Self {
+        self.reason = Some(reason);
+        self
     }
 }
 impl Display for InvalidDnsNameError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "invalid dns name {}", self.name)
+        match &self.reason {
+            Some(reason) => write!(f, "failed to resolve {}: {}", self.name, reason),
+            None => write!(f, "invalid dns name {}", self.name),
+        }
     }
 }
 
@@ -377,12 +1772,107 @@ pub enum SseConnectionError {
     CAFileIOError(std::io::Error),
     #[error("http error {0:?}")]
     HttpError(HttpResponse),
-    #[error("connect io error {0:?}")]
-    ConnectError(std::io::Error),
+    #[error("{0} io error {1:?}")]
+    ConnectError(ConnectionPhase, std::io::Error),
     #[error("connection io error {0:?}")]
     ConnectionError(std::io::Error),
+    #[error("connection reset by peer {0:?}")]
+    ConnectionReset(std::io::Error),
+    #[error("connection closed unexpectedly {0:?}")]
+    UnexpectedEof(std::io::Error),
     #[error("dns error {0:?}")]
     DnsError(InvalidDnsNameError),
+    #[error("sink io error {0:?}")]
+    SinkError(std::io::Error),
+    #[error("certificate pin mismatch")]
+    CertificatePinMismatch,
+    #[error("bearer token provider failed: {0}")]
+    TokenProviderError(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("stream exceeded {0} bytes")]
+    StreamTooLarge(usize),
+    #[error("response headers exceeded {max_headers} lines or {max_header_bytes} bytes")]
+    HeadersTooLarge {
+        max_headers: usize,
+        max_header_bytes: usize,
+    },
+    #[error("no event arrived within the idle timeout")]
+    IdleTimeout,
+    #[error("the stream exceeded the total deadline")]
+    DeadlineExceeded,
+    #[error("invalid status line {0}")]
+    InvalidStatusLine(HttpStatusLineError),
+}
+// where a connection failed, to tell the setup of a connection from the streaming of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionPhase {
+    Dns,
+    Connect,
+    Handshake,
+    Read,
+    Write,
+}
+impl Display for ConnectionPhase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let phase = match self {
+            Self::Dns => "dns",
+            Self::Connect => "connect",
+            Self::Handshake => "handshake",
+            Self::Read => "read",
+            Self::Write => "write",
+        };
+        f.write_str(phase)
+    }
+}
+impl SseConnectionError {
+    // None for errors which are not of the connection, like an http error or an invalid url
+    pub fn phase(&self) -> Option<ConnectionPhase> {
+        match self {
+            Self::DnsError(_) => Some(ConnectionPhase::Dns),
+            Self::ConnectError(phase, _) => Some(*phase),
+            Self::ConnectionError(_) | Self::ConnectionReset(_) | Self::UnexpectedEof(_) => {
+                Some(ConnectionPhase::Read)
+            }
+            _ => None,
+        }
+    }
+    // the peer closing the connection is classified, so that it can be retried
+    pub(crate) fn from_read_error(err: std::io::Error) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::BrokenPipe => {
+                Self::ConnectionReset(err)
+            }
+            std::io::ErrorKind::UnexpectedEof => Self::UnexpectedEof(err),
+            _ => Self::ConnectionError(err),
+        }
+    }
+    // errors of the transport, which may succeed by connecting again
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::ConnectError(..)
+                | Self::ConnectionError(_)
+                | Self::ConnectionReset(_)
+                | Self::UnexpectedEof(_)
+        )
+    }
+    // deserialize the body of an http error, like `{"error":{"message":...}}`
+    pub fn json_error<T: DeserializeOwned>(&self) -> Option<T> {
+        match self {
+            Self::HttpError(res) => serde_json::from_str(res.body_str()).ok(),
+            _ => None,
+        }
+    }
+}
+impl From<UrlError> for SseConnectionError {
+    fn from(err: UrlError) -> Self {
+        Self::InvalidUrl(err.to_string())
+    }
+}
+// for conversions which never fail, like `&Url` into `Url`
+impl From<Infallible> for SseConnectionError {
+    fn from(err: Infallible) -> Self {
+        match err {}
+    }
 }
 
 #[cfg(test)]
@@ -391,9 +1881,13 @@ mod tests {
     use crate::{
         http::{body::HttpBody, request::RequestBuilder, response::HttpResponse},
         sse::connector::{
-            chatgpt::{chatgpt_key, evaluate_chatgpt_response, message, ChatGptRes, URL},
+            chatgpt::{
+                chatgpt_key, evaluate_chatgpt_response, evaluate_chatgpt_sse_response, message,
+                ChatGptRes, URL,
+            },
             fakes::FakeTcpConnection,
         },
+        sse::testing::assert_stream_eq,
     };
 
     use super::*;
@@ -439,6 +1933,49 @@ mod tests {
         one_request(&mut tls_connector, "thanks");
     }
     #[test]
+    fn chatgptの終了判定はdataが完全に一致する場合のみ() {
+        let done = SseResponse::from_line("data: [DONE]\r\n").unwrap();
+        let empty = SseResponse::from_line("data: \r\n").unwrap();
+
+        assert!(matches!(
+            evaluate_chatgpt_sse_response(&done),
+            ChatGptRes::Done
+        ));
+        assert!(matches!(
+            evaluate_chatgpt_sse_response(&empty),
+            ChatGptRes::Data(_)
+        ));
+    }
+    #[test]
+    #[ignore = "実際の通信を行うため"]
+    fn handshake後はpeerの証明書を取得できる() {
+        let req = RequestBuilder::new(&URL.try_into().unwrap()).get().build();
+        let mut tls_connector = SseTlsConnectorBuilder::new(req.url()).build().unwrap();
+        assert!(tls_connector.peer_certificates().is_none());
+
+        tls_connector.connect(&req).unwrap();
+
+        let certs = tls_connector.peer_certificates().unwrap();
+        assert!(!certs.is_empty());
+    }
+    #[test]
+    fn leaf証明書のsha256がpinと一致するか検証できる() {
+        let leaf = Certificate(vec![1, 2, 3]);
+        let other = Certificate(vec![4, 5, 6]);
+        let digest = ring::digest::digest(&ring::digest::SHA256, &leaf.0);
+        let pin: [u8; 32] = digest.as_ref().try_into().unwrap();
+
+        assert!(verify_pin(Some(&[leaf.clone(), other.clone()]), &pin).is_ok());
+        assert!(matches!(
+            verify_pin(Some(&[other, leaf]), &pin),
+            Err(SseConnectionError::CertificatePinMismatch)
+        ));
+        assert!(matches!(
+            verify_pin(None, &pin),
+            Err(SseConnectionError::CertificatePinMismatch)
+        ));
+    }
+    #[test]
     #[ignore = "実際の通信を行うため"]
     fn chatgptにtlsで通信する() {
         let req = RequestBuilder::new(&URL.try_into().unwrap())
@@ -473,9 +2010,44 @@ mod tests {
         assert!(flag);
     }
     #[test]
+    fn 接続できないproxyの次のproxyで接続する() {
+        let dead_proxy = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let dead_proxy_url: Url = format!("http://{}", dead_proxy.local_addr().unwrap())
+            .as_str()
+            .try_into()
+            .unwrap();
+        drop(dead_proxy);
+        let proxy = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_url: Url = format!("http://{}", proxy.local_addr().unwrap())
+            .as_str()
+            .try_into()
+            .unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let (mut stream, _) = proxy.accept().unwrap();
+            let mut buf = vec![0; 4096];
+            let size = stream.read(&mut buf).unwrap();
+            tx.send(String::from_utf8_lossy(&buf[..size]).to_string())
+                .unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+                .unwrap();
+        });
+
+        let result = SseTlsConnectorBuilder::new(&"https://www.fake".try_into().unwrap())
+            .proxies(vec![dead_proxy_url, proxy_url])
+            .build();
+
+        assert!(result.is_ok());
+        assert!(rx
+            .recv()
+            .unwrap()
+            .starts_with("CONNECT www.fake:443 HTTP/1.1\r\n"));
+    }
+    #[test]
     fn tls_socketは書き込みもできる() {
         let url: Url = "https://www.google.com".try_into().unwrap();
-        let client = ClientConnection::default(&url, RootCertStore::new()).unwrap();
+        let client = ClientConnection::default(&url, RootCertStore::new(), None).unwrap();
         let stream = StreamOwned::new(client);
         let mut socket = TlsSocket::new(stream);
         socket
@@ -499,55 +2071,796 @@ mod tests {
 
         let result = sut.read().unwrap();
         assert_eq!(
-            result,
-            ConnectedSseResponse::Progress(SseResponse::Data("Hello, World!".to_string()))
+            result.into_response(),
+            Some(SseResponse::Data("Hello, World!".to_string()))
         );
 
         let result = sut.read().unwrap();
         assert_eq!(
-            result,
-            ConnectedSseResponse::Progress(SseResponse::Data("Good Bye World".to_string()))
+            result.into_response(),
+            Some(SseResponse::Data("Good Bye World".to_string()))
         );
 
         let done = sut.read().unwrap();
         assert_eq!(done, ConnectedSseResponse::Done);
     }
     #[test]
-    fn http_errorの場合はhttp_responseをそのままerrorに包んで返す() {
+    fn read_allはstreamの全てのeventを順番に返す() {
         let mut fake = FakeTcpConnection::new();
-        fake.set_response("HTTP/1.1 404 Not Found\n\n");
-        fake.set_response("Content-Type: text/event-stream\n\n");
-
+        fake.set_response("HTTP/1.1 200 OK\r\n");
+        fake.set_response("Content-Type: text/event-stream\r\n");
+        fake.set_response("\r\n");
+        fake.set_response("event: greeting\r\n");
+        fake.set_response("data: Hello\r\n");
+        fake.set_response("\r\n");
+        fake.set_response(": keep-alive\r\n");
+        fake.set_response("id: 1\r\n");
+        fake.set_response("data: World\r\n");
+        fake.set_response("\r\n");
         let mut sut = SseConnection::new(fake);
-        let Err(SseConnectionError::HttpError(result)) = sut.read() else {
-            panic!("expected Err, but got Ok");
-        };
 
-        assert_eq!(
-            result,
-            HttpResponse::new(
-                HttpStatusLine::from_str("HTTP/1.1 404 Not Found").unwrap(),
-                HttpHeader::from_line("Content-Type: text/event-stream").unwrap(),
-                HttpBody::from_line("")
-            )
+        let responses = sut.read_all().unwrap();
+
+        assert_stream_eq(
+            &responses,
+            &[
+                SseResponse::event("greeting"),
+                SseResponse::data("Hello"),
+                SseResponse::id("1"),
+                SseResponse::data("World"),
+            ],
         );
     }
-}
-#[cfg(test)]
-pub(crate) mod fakes {
-    use super::{Socket, SseConnection, SseConnectionError};
+    #[test]
+    fn proxy_keep_aliveの場合はconnect_requestにheaderを含める() {
+        let url = Url::from_str("https://www.fake").unwrap();
 
-    pub struct FakeSseConnector {
-        connected_times: usize,
-        pub connection: SseConnection<FakeTcpConnection>,
+        let sut = tunnel_request(&url, true, &[]);
+
+        let request = String::from_utf8_lossy(sut.bytes()).to_string();
+        assert!(request.starts_with("CONNECT "));
+        assert!(request.contains("Proxy-Connection: keep-alive\r\n"));
+        let sut = tunnel_request(&url, false, &[]);
+        assert!(!String::from_utf8_lossy(sut.bytes()).contains("Proxy-Connection"));
     }
-    impl FakeSseConnector {
+    #[cfg(unix)]
+    #[test]
+    fn unix_domain_socketのserverからeventを受信できる() {
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("rsse-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+                line.clear();
+            }
+            let mut stream = stream;
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\ndata: Hello\r\n\r\n")
+                .unwrap();
+            request_line
+        });
+        let mut sut = SseUnixConnectorBuilder::new(&path).build().unwrap();
+        let req = RequestBuilder::new(&"http://localhost/events".try_into().unwrap())
+            .get()
+            .build();
+
+        let conn = sut.connect(&req).unwrap();
+
+        assert_eq!(
+            conn.read().unwrap().into_response(),
+            Some(SseResponse::Data("Hello".to_string()))
+        );
+        assert_eq!(conn.read().unwrap(), ConnectedSseResponse::Done);
+        assert_eq!(server.join().unwrap(), "GET /events HTTP/1.1\r\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+    #[test]
+    fn 名前解決できないhostはdns_errorになる() {
+        let url = Url::from_str("http://rsse-test.invalid").unwrap();
+
+        let result = SseTcpConnectorBuilder::new(&url).build();
+
+        let Err(SseConnectionError::DnsError(err)) = result else {
+            panic!("expected DnsError");
+        };
+        assert!(err.to_string().starts_with("failed to resolve"));
+    }
+    #[test]
+    fn 一時的なdnsのerrorだけをretryする() {
+        use std::io::{Error, ErrorKind};
+
+        assert!(is_transient_dns_error(&Error::from(ErrorKind::TimedOut)));
+        assert!(is_transient_dns_error(&Error::from(ErrorKind::Interrupted)));
+        assert!(is_transient_dns_error(&Error::new(
+            ErrorKind::Other,
+            "failed to lookup address information: Temporary failure in name resolution"
+        )));
+        assert!(!is_transient_dns_error(&Error::new(
+            ErrorKind::Other,
+            "failed to lookup address information: Name or service not known"
+        )));
+    }
+    #[test]
+    fn peerが閉じたtcp_connectionはhealthyではない() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url: Url = format!("http://{}", listener.local_addr().unwrap())
+            .as_str()
+            .try_into()
+            .unwrap();
+        let mut sut = SseTcpConnectorBuilder::new(&url).build().unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        assert!(sut.is_healthy());
+
+        drop(stream);
+        std::thread::sleep(Duration::from_millis(50));
+
+        assert!(!sut.is_healthy());
+    }
+    #[test]
+    fn httpのurlにはtlsを使わずに接続する() {
+        use std::io::{BufRead, BufReader};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url: Url = format!("http://{}", listener.local_addr().unwrap())
+            .as_str()
+            .try_into()
+            .unwrap();
+        let mut sut = DynSseConnector::for_url(&url).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        sut.connect(&RequestBuilder::new(&url).get().build())
+            .unwrap();
+
+        // a tls client would send a handshake instead of the request line
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        assert_eq!(line, "GET / HTTP/1.1\r\n");
+    }
+    #[test]
+    fn connectに失敗したerrorはconnectのphaseを持つ() {
+        // the port is released, so that nothing listens on it
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url: Url = format!("http://{}", listener.local_addr().unwrap())
+            .as_str()
+            .try_into()
+            .unwrap();
+        drop(listener);
+
+        let Err(err) = SseTcpConnectorBuilder::new(&url).build() else {
+            panic!("expected ConnectError");
+        };
+
+        assert!(matches!(
+            err,
+            SseConnectionError::ConnectError(ConnectionPhase::Connect, _)
+        ));
+        assert_eq!(err.phase(), Some(ConnectionPhase::Connect));
+    }
+    #[test]
+    fn connect_timeoutを超えるconnectはconnect_errorになる() {
+        // an address of TEST-NET-1, which is never routed
+        let url = Url::from_str("http://192.0.2.1:80").unwrap();
+        let start = Instant::now();
+
+        let Err(err) = SseTcpConnectorBuilder::new(&url)
+            .connect_timeout(Duration::from_millis(100))
+            .build()
+        else {
+            panic!("expected ConnectError");
+        };
+
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(err.phase(), Some(ConnectionPhase::Connect));
+    }
+    #[test]
+    fn stream途中のread_errorはreadのphaseを持つ() {
+        let mut fake = FakeTcpConnection::new();
+        fake.set_response("HTTP/1.1 200 OK\r\n");
+        fake.set_response("Content-Type: text/event-stream\r\n");
+        fake.set_response("\r\n");
+        fake.set_response("data: Hello\r\n");
+        fake.set_read_error(std::io::ErrorKind::ConnectionReset);
+        let mut sut = SseConnection::new(fake);
+
+        sut.read().unwrap();
+        let err = sut.read().unwrap_err();
+
+        assert_eq!(err.phase(), Some(ConnectionPhase::Read));
+        assert_eq!(
+            SseConnectionError::DnsError(InvalidDnsNameError::new(
+                &Url::from_str("http://rsse-test.invalid").unwrap()
+            ))
+            .phase(),
+            Some(ConnectionPhase::Dns)
+        );
+        assert_eq!(SseConnectionError::IdleTimeout.phase(), None);
+    }
+    #[test]
+    fn proxy_headerはconnect_requestにだけ含める() {
+        let url = Url::from_str("https://www.fake").unwrap();
+        let headers = [(
+            "Proxy-Authorization".to_string(),
+            "Basic dXNlcjpwYXNz".to_string(),
+        )];
+
+        let sut = tunnel_request(&url, false, &headers);
+
+        let request = String::from_utf8_lossy(sut.bytes()).to_string();
+        assert!(request.contains("Proxy-Authorization: Basic dXNlcjpwYXNz\r\n"));
+        assert!(!request.contains("Authorization: Bearer"));
+    }
+    #[test]
+    fn dataの値だけをwriterに書き込む() {
+        let mut fake = FakeTcpConnection::new();
+        fake.set_response("HTTP/1.1 200 OK\r\n");
+        fake.set_response("Content-Type: text/event-stream\r\n");
+        fake.set_response("\r\n");
+        fake.set_response("event: delta\r\n");
+        fake.set_response("data: Hello\r\n");
+        fake.set_response("\r\n");
+        fake.set_response(": ping\r\n");
+        fake.set_response("data: , World\r\n");
+        fake.set_response("data: second line\r\n");
+        fake.set_response("\r\n");
+        fake.set_response("data: !\r\n");
+        fake.set_response("\r\n");
+        let mut sut = SseConnection::new(fake);
+        let mut sink = Vec::new();
+
+        sut.data_to_writer(&mut sink).unwrap();
+
+        assert_eq!(
+            String::from_utf8(sink).unwrap(),
+            "Hello, World\nsecond line!"
+        );
+    }
+    #[test]
+    fn status_lineがないstreamは最初の行からeventとして読み込む() {
+        let mut fake = FakeTcpConnection::new();
+        fake.set_response("data: Hello\n");
+        fake.set_response("\n");
+        fake.set_response("event: greeting\n");
+        fake.set_response("data: World\n");
+
+        let mut sut = SseConnection::new(fake);
+
+        assert_eq!(
+            sut.read().unwrap().into_response(),
+            Some(SseResponse::Data("Hello".to_string()))
+        );
+        assert_eq!(
+            sut.read().unwrap().into_response(),
+            Some(SseResponse::Event("greeting".to_string()))
+        );
+        assert_eq!(
+            sut.read().unwrap().into_response(),
+            Some(SseResponse::Data("World".to_string()))
+        );
+        assert_eq!(sut.read().unwrap(), ConnectedSseResponse::Done);
+    }
+    #[test]
+    fn max_stream_bytesを超えた場合はerrorを返す() {
+        let mut fake = FakeTcpConnection::new();
+        fake.set_response("HTTP/1.1 200 OK\n");
+        fake.set_response("Content-Type: text/event-stream\n");
+        fake.set_response("\n");
+        fake.set_response("data: Hello, World!\n");
+        fake.set_response("data: Good Bye World\n");
+
+        let mut sut = SseConnection::new(fake);
+        sut.max_stream_bytes = Some(70);
+
+        let result = sut.read().unwrap();
+        assert_eq!(
+            result.into_response(),
+            Some(SseResponse::Data("Hello, World!".to_string()))
+        );
+        let Err(SseConnectionError::StreamTooLarge(max)) = sut.read() else {
+            panic!("expected StreamTooLarge");
+        };
+        assert_eq!(max, 70);
+    }
+    #[test]
+    fn headerが多すぎる場合はerrorを返す() {
+        let mut fake = FakeTcpConnection::new();
+        fake.set_response("HTTP/1.1 200 OK\r\n");
+        for i in 0..=DEFAULT_MAX_HEADERS {
+            fake.set_response(&format!("X-Header-{}: value\r\n", i));
+        }
+        fake.set_response("\r\n");
+        fake.set_response("data: Hello\r\n");
+        let mut sut = SseConnection::new(fake);
+
+        let Err(SseConnectionError::HeadersTooLarge { max_headers, .. }) = sut.read() else {
+            panic!("expected HeadersTooLarge");
+        };
+        assert_eq!(max_headers, DEFAULT_MAX_HEADERS);
+    }
+    #[test]
+    fn headerのbyte数が上限を超えた場合はerrorを返す() {
+        let mut fake = FakeTcpConnection::new();
+        fake.set_response("HTTP/1.1 500 Internal Server Error\r\n");
+        fake.set_response(&format!("X-Large: {}\r\n", "a".repeat(32)));
+        fake.set_response("\r\n");
+        let mut sut = SseConnection::new(fake);
+        sut.header_limit.max_header_bytes = 16;
+
+        let Err(SseConnectionError::HeadersTooLarge {
+            max_header_bytes, ..
+        }) = sut.read()
+        else {
+            panic!("expected HeadersTooLarge");
+        };
+        assert_eq!(max_header_bytes, 16);
+    }
+    #[test]
+    fn chunkedのstreamはtrailerを無視して終了する() {
+        let mut fake = FakeTcpConnection::new();
+        fake.set_response("HTTP/1.1 200 OK\r\n");
+        fake.set_response("Content-Type: text/event-stream\r\n");
+        fake.set_response("Transfer-Encoding: chunked\r\n");
+        fake.set_response("\r\n");
+        fake.set_response("f\r\n");
+        fake.set_response("data: Hello\r\n");
+        fake.set_response("\r\n");
+        fake.set_response("\r\n");
+        // the line is split across two chunks
+        fake.set_response("6\r\n");
+        fake.set_response("data: \r\n");
+        fake.set_response("7\r\n");
+        fake.set_response("World\r\n");
+        fake.set_response("\r\n");
+        fake.set_response("0\r\n");
+        fake.set_response("data: trailer\r\n");
+        fake.set_response("\r\n");
+        let mut sut = SseConnection::new(fake);
+
+        assert_eq!(
+            sut.read().unwrap().into_response(),
+            Some(SseResponse::Data("Hello".to_string()))
+        );
+        assert_eq!(
+            sut.read().unwrap().into_response(),
+            Some(SseResponse::Data("World".to_string()))
+        );
+        assert_eq!(sut.read().unwrap(), ConnectedSseResponse::Done);
+    }
+    #[test]
+    fn lossy_utf8の場合は不正なbyteを置換して読み込む() {
+        fn connection(lossy_utf8: bool) -> SseConnection<FakeTcpConnection> {
+            let mut fake = FakeTcpConnection::new();
+            fake.set_response_bytes(b"data: a\xffb\n");
+            let mut sut = SseConnection::new(fake);
+            sut.lossy_utf8 = lossy_utf8;
+            sut
+        }
+
+        let strict = connection(false).read();
+        let lossy = connection(true).read().unwrap();
+
+        assert!(strict.is_err());
+        assert_eq!(
+            lossy.into_response(),
+            Some(SseResponse::Data("a\u{FFFD}b".to_string()))
+        );
+    }
+    #[test]
+    fn lossy_utf8でもchunkの長さは受信したbyte数で数える() {
+        let mut fake = FakeTcpConnection::new();
+        fake.set_response("HTTP/1.1 200 OK\r\n");
+        fake.set_response("Content-Type: text/event-stream\r\n");
+        fake.set_response("Transfer-Encoding: chunked\r\n");
+        fake.set_response("\r\n");
+        // the invalid byte is replaced with 3 bytes of U+FFFD, which must not shift the next chunk
+        fake.set_response("a\r\n");
+        fake.set_response_bytes(b"data: \xffb\n\n\r\n");
+        fake.set_response("c\r\n");
+        fake.set_response("data: World\n\r\n");
+        fake.set_response("0\r\n");
+        fake.set_response("\r\n");
+        let mut sut = SseConnection::new(fake);
+        sut.lossy_utf8 = true;
+
+        assert_eq!(
+            sut.read_all().unwrap(),
+            vec![
+                SseResponse::Data("\u{FFFD}b".to_string()),
+                SseResponse::Data("World".to_string())
+            ]
+        );
+    }
+    #[test]
+    fn sse_connectionのread_timeoutは次のreadから適用される() {
+        let mut fake = FakeTcpConnection::new();
+        fake.set_response("data: first\r\n");
+        fake.set_response("data: second\r\n");
+        let mut sut = SseConnection::new(fake);
+
+        sut.read().unwrap();
+        sut.read_timeout_for(Duration::from_secs(30)).unwrap();
+        sut.read().unwrap();
+
+        assert_eq!(
+            sut.conn.applied_read_timeouts(),
+            &[None, Some(Duration::from_secs(30))]
+        );
+    }
+    #[test]
+    fn sse_connectionは折り返されたheaderを前のheaderの値に連結する() {
+        let mut fake = FakeTcpConnection::new();
+        fake.set_response("HTTP/1.1 200 OK\r\n");
+        fake.set_response("Content-Type: text/event-stream;\r\n");
+        fake.set_response("  charset=utf-8\r\n");
+        fake.set_response("\r\n");
+        fake.set_response("data: Hello\r\n");
+
+        let mut sut = SseConnection::new(fake);
+
+        let result = sut.read().unwrap();
+        assert_eq!(
+            result.into_response(),
+            Some(SseResponse::Data("Hello".to_string()))
+        );
+        assert_eq!(
+            sut.header().get("Content-Type"),
+            Some("text/event-stream; charset=utf-8")
+        );
+    }
+    #[test]
+    fn sse_connectionはbodyの行をそのままsinkに書き込む() {
+        let mut fake = FakeTcpConnection::new();
+        fake.set_response("HTTP/1.1 200 OK\r\n");
+        fake.set_response("Content-Type: text/event-stream\r\n");
+        fake.set_response("\r\n");
+        fake.set_response("event: greeting\r\n");
+        fake.set_response("data: Hello\r\n");
+        fake.set_response("\r\n");
+        let mut sink = Vec::new();
+
+        let mut sut = SseConnection::new(fake);
+        sut.pipe_to(&mut sink).unwrap();
+
+        assert_eq!(
+            String::from_utf8(sink).unwrap(),
+            "event: greeting\r\ndata: Hello\r\n\r\n"
+        );
+    }
+    #[test]
+    fn sse_connectionは未知のfieldを読み飛ばす() {
+        let mut fake = FakeTcpConnection::new();
+        fake.set_response("HTTP/1.1 200 OK\r\n");
+        fake.set_response("Content-Type: text/event-stream\r\n");
+        fake.set_response("\r\n");
+        fake.set_response("foo: bar\r\n");
+        fake.set_response("data: Hello\r\n");
+
+        let mut sut = SseConnection::new(fake);
+
+        let result = sut.read().unwrap();
+        assert_eq!(
+            result.into_response(),
+            Some(SseResponse::Data("Hello".to_string()))
+        );
+    }
+    #[test]
+    fn colonを含む値はheaderではなくsseのfieldとして読み込む() {
+        let mut fake = FakeTcpConnection::new();
+        fake.set_response("HTTP/1.1 200 OK\r\n");
+        fake.set_response("Content-Type: text/event-stream\r\n");
+        fake.set_response("\r\n");
+        fake.set_response("event: time:update\r\n");
+        fake.set_response("data: {\"time\":\"12:00:00\",\"url\":\"https://www.fake\"}\r\n");
+
+        let mut sut = SseConnection::new(fake);
+
+        assert_eq!(
+            sut.read().unwrap().into_response(),
+            Some(SseResponse::Event("time:update".to_string()))
+        );
+        assert_eq!(
+            sut.read().unwrap().into_response(),
+            Some(SseResponse::Data(
+                "{\"time\":\"12:00:00\",\"url\":\"https://www.fake\"}".to_string()
+            ))
+        );
+    }
+    #[test]
+    fn sse_connectionはcommentを読み飛ばす() {
+        let mut fake = FakeTcpConnection::new();
+        fake.set_response("HTTP/1.1 200 OK\n\n");
+        fake.set_response("Content-Type: text/event-stream\n\n");
+        fake.set_response("\n\n");
+        fake.set_response(": ping\n\n");
+        fake.set_response("data: Hello, World!\n\n");
+
+        let mut sut = SseConnection::new(fake);
+
+        let result = sut.read().unwrap();
+        assert_eq!(
+            result.into_response(),
+            Some(SseResponse::Data("Hello, World!".to_string()))
+        );
+    }
+    #[test]
+    fn http_errorのjson_bodyを型付きで取得できる() {
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct OpenAiError {
+            error: OpenAiErrorBody,
+        }
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct OpenAiErrorBody {
+            message: String,
+            r#type: String,
+        }
+        let mut fake = FakeTcpConnection::new();
+        fake.set_response("HTTP/1.1 401 Unauthorized\n");
+        fake.set_response("Content-Type: application/json\n");
+        fake.set_response("\n");
+        fake.set_response("{\"error\":{\"message\":\"Incorrect API key provided\",\n");
+        fake.set_response("\"type\":\"invalid_request_error\"}}\n");
+
+        let mut sut = SseConnection::new(fake);
+        let err = sut.read().unwrap_err();
+
+        assert_eq!(
+            err.json_error::<OpenAiError>(),
+            Some(OpenAiError {
+                error: OpenAiErrorBody {
+                    message: "Incorrect API key provided".to_string(),
+                    r#type: "invalid_request_error".to_string(),
+                }
+            })
+        );
+        assert_eq!(
+            SseConnectionError::CertificatePinMismatch.json_error::<OpenAiError>(),
+            None
+        );
+    }
+    #[test]
+    fn chunkedのhttp_errorはchunkを取り除いたbodyを返す() {
+        let mut fake = FakeTcpConnection::new();
+        fake.set_response("HTTP/1.1 429 Too Many Requests\r\n");
+        fake.set_response("Content-Type: application/json\r\n");
+        fake.set_response("Transfer-Encoding: chunked\r\n");
+        fake.set_response("\r\n");
+        fake.set_response("d\r\n");
+        fake.set_response("{\"error\":\"slo\r\n");
+        fake.set_response("7\r\n");
+        fake.set_response("w down\"\r\n");
+        fake.set_response("2\r\n");
+        fake.set_response("}\n\r\n");
+        fake.set_response("0\r\n");
+        fake.set_response("\r\n");
+        let mut sut = SseConnection::new(fake);
+
+        let err = sut.read().unwrap_err();
+
+        assert_eq!(
+            err.json_error::<serde_json::Value>(),
+            Some(serde_json::json!({"error": "slow down"}))
+        );
+    }
+    #[test]
+    fn http_errorのbodyはcontent_lengthまでしか読まない() {
+        fn read(stream_on_error_status: bool) -> SseConnectionError {
+            let mut fake = FakeTcpConnection::new();
+            fake.set_response("HTTP/1.1 404 Not Found\r\n");
+            fake.set_response("Content-Type: application/json\r\n");
+            fake.set_response("Content-Length: 13\r\n");
+            fake.set_response("\r\n");
+            // the body has no trailing newline, and the keep-alive connection is not closed
+            fake.set_response("{\"a\":\"bcdef\"}");
+            fake.set_read_error(std::io::ErrorKind::WouldBlock);
+            let mut sut = SseConnection::new(fake);
+            sut.set_stream_on_error_status(stream_on_error_status);
+            sut.read().unwrap_err()
+        }
+
+        for stream_on_error_status in [false, true] {
+            let SseConnectionError::HttpError(res) = read(stream_on_error_status) else {
+                panic!("expected HttpError");
+            };
+            assert_eq!(res.body_str(), "{\"a\":\"bcdef\"}");
+        }
+    }
+    #[test]
+    fn http_errorのbodyのread_errorはそのまま返す() {
+        let mut fake = FakeTcpConnection::new();
+        fake.set_response("HTTP/1.1 500 Internal Server Error\r\n");
+        fake.set_response("Content-Type: text/plain\r\n");
+        fake.set_response("\r\n");
+        fake.set_response("partial\n");
+        fake.set_read_error(std::io::ErrorKind::ConnectionReset);
+        let mut sut = SseConnection::new(fake);
+        sut.set_stream_on_error_status(true);
+
+        let err = sut.read().unwrap_err();
+
+        assert!(!matches!(err, SseConnectionError::HttpError(_)));
+        assert_eq!(err.phase(), Some(ConnectionPhase::Read));
+    }
+    #[test]
+    fn http_errorの場合はhttp_responseをそのままerrorに包んで返す() {
+        let mut fake = FakeTcpConnection::new();
+        fake.set_response("HTTP/1.1 404 Not Found\n\n");
+        fake.set_response("Content-Type: text/event-stream\n\n");
+
+        let mut sut = SseConnection::new(fake);
+        let Err(SseConnectionError::HttpError(result)) = sut.read() else {
+            panic!("expected Err, but got Ok");
+        };
+
+        assert_eq!(
+            result,
+            HttpResponse::new(
+                HttpStatusLine::from_str("HTTP/1.1 404 Not Found").unwrap(),
+                HttpHeader::from_line("Content-Type: text/event-stream").unwrap(),
+                HttpBody::from_line("")
+            )
+        );
+    }
+    #[test]
+    fn peerによる切断は専用のerrorになる() {
+        let mut fake = FakeTcpConnection::new();
+        fake.set_response("HTTP/1.1 200 OK\r\n");
+        fake.set_response("Content-Type: text/event-stream\r\n");
+        fake.set_response("\r\n");
+        fake.set_read_error(std::io::ErrorKind::ConnectionReset);
+        let mut sut = SseConnection::new(fake);
+
+        let result = sut.read();
+
+        let Err(err @ SseConnectionError::ConnectionReset(_)) = result else {
+            panic!("expected ConnectionReset, but got {:?}", result);
+        };
+        assert!(err.is_retryable());
+
+        let mut fake = FakeTcpConnection::new();
+        fake.set_read_error(std::io::ErrorKind::UnexpectedEof);
+        let mut sut = SseConnection::new(fake);
+        assert!(matches!(
+            sut.read(),
+            Err(SseConnectionError::UnexpectedEof(_))
+        ));
+        let mut fake = FakeTcpConnection::new();
+        fake.set_read_error(std::io::ErrorKind::InvalidData);
+        let mut sut = SseConnection::new(fake);
+        let err = sut.read().unwrap_err();
+        assert!(matches!(err, SseConnectionError::ConnectionError(_)));
+        assert!(err.is_retryable());
+        assert!(!SseConnectionError::CertificatePinMismatch.is_retryable());
+    }
+    #[test]
+    fn responseはwire上の行をそのまま保持する() {
+        let mut fake = FakeTcpConnection::new();
+        fake.set_response("HTTP/1.1 200 OK\r\n");
+        fake.set_response("Content-Type: text/event-stream\r\n");
+        fake.set_response("\r\n");
+        fake.set_response("data:  {\"text\":\"Hello\"}\r\n");
+        let mut sut = SseConnection::new(fake);
+
+        let result = sut.read().unwrap();
+
+        assert_eq!(
+            result,
+            ConnectedSseResponse::Progress {
+                response: SseResponse::Data(" {\"text\":\"Hello\"}".to_string()),
+                raw: "data:  {\"text\":\"Hello\"}\r\n".to_string()
+            }
+        );
+        assert_eq!(result.raw(), Some("data:  {\"text\":\"Hello\"}\r\n"));
+        assert_eq!(sut.read().unwrap().raw(), None);
+    }
+    #[test]
+    fn requestは一度のwriteで書き込まれる() {
+        #[derive(Debug, Clone, Default)]
+        struct CountingStream {
+            writes: Arc<RefCell<Vec<usize>>>,
+        }
+        impl Read for CountingStream {
+            fn read(&mut self, _buf: &mut [u8]) -> std::result::Result<usize, std::io::Error> {
+                Ok(0)
+            }
+        }
+        impl Write for CountingStream {
+            fn write(&mut self, buf: &[u8]) -> std::result::Result<usize, std::io::Error> {
+                self.writes.borrow_mut().push(buf.len());
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::result::Result<(), std::io::Error> {
+                Ok(())
+            }
+        }
+        impl Stream for CountingStream {
+            fn reader(&self) -> BufReader<Self> {
+                BufReader::new(self.clone())
+            }
+            fn writer(&self) -> BufWriter<Self> {
+                BufWriter::new(self.clone())
+            }
+            fn set_read_timeout(
+                &self,
+                _timeout: Option<Duration>,
+            ) -> std::result::Result<(), std::io::Error> {
+                Ok(())
+            }
+        }
+        let stream = CountingStream::default();
+        let mut sut = TlsSocket::new(stream.clone());
+        // larger than the default capacity of the buffer
+        let req = RequestBuilder::new(&"https://www.fake".try_into().unwrap())
+            .post()
+            .bearer_auth("key")
+            .json(serde_json::json!({ "prompt": "a".repeat(10000) }))
+            .build();
+
+        sut.write_all(req.bytes()).unwrap();
+        let short = RequestBuilder::new(&"https://www.fake".try_into().unwrap())
+            .post()
+            .json(serde_json::json!({ "prompt": "hello" }))
+            .build();
+        sut.write_all(short.bytes()).unwrap();
+
+        assert_eq!(
+            *stream.writes.borrow(),
+            vec![req.bytes().len(), short.bytes().len()]
+        );
+    }
+}
+#[cfg(test)]
+pub(crate) mod fakes {
+    use std::time::Duration;
+
+    use super::{ConnectionPhase, Socket, SseConnection, SseConnectionError};
+
+    pub struct FakeSseConnector {
+        connected_times: usize,
+        connect_timeout: Option<Duration>,
+        // connect timeout which was applied to each reconnect
+        reconnect_timeouts: Vec<Option<Duration>>,
+        pub connection: SseConnection<FakeTcpConnection>,
+    }
+    impl FakeSseConnector {
         pub fn new() -> Self {
             Self {
                 connection: SseConnection::new(FakeTcpConnection::new()),
                 connected_times: 0,
+                connect_timeout: None,
+                reconnect_timeouts: Vec::new(),
             }
         }
+        // like the read timeout given to the builder of a real connector
+        pub fn read_timeout(mut self, timeout: Duration) -> Self {
+            self.connection.read_timeout = Some(timeout);
+            self.connection.conn.read_timeout = Some(timeout);
+            self
+        }
+        pub fn connect_timeout(&self) -> Option<Duration> {
+            self.connect_timeout
+        }
+        pub fn reconnect_timeouts(&self) -> &[Option<Duration>] {
+            &self.reconnect_timeouts
+        }
+        pub fn applied_read_timeouts(&self) -> &[Option<Duration>] {
+            self.connection.conn.applied_read_timeouts()
+        }
+        pub fn written(&self) -> &[u8] {
+            self.connection.conn.written()
+        }
+        pub fn set_read_error(&mut self, kind: std::io::ErrorKind) {
+            self.connection.conn.set_read_error(kind);
+        }
+        // like the server closing the keep-alive connection
+        pub fn close(&mut self) {
+            self.connection.conn.close();
+        }
         pub fn set_response(&mut self, response: &str) {
             self.connection.conn.set_response(response);
         }
@@ -559,36 +2872,114 @@ pub(crate) mod fakes {
         type Socket = FakeTcpConnection;
         fn connect(
             &mut self,
-            _req: &super::Request,
+            req: &super::Request,
         ) -> std::result::Result<&mut SseConnection<FakeTcpConnection>, SseConnectionError>
         {
             self.connected_times += 1;
+            self.connection
+                .write_request(req)
+                .map_err(|e| SseConnectionError::ConnectError(ConnectionPhase::Write, e))?;
             Ok(&mut self.connection)
         }
+        fn set_connect_timeout(&mut self, timeout: Option<Duration>) {
+            self.connect_timeout = timeout;
+        }
+        // the responses are kept, like a server answering on the new connection
+        fn reconnect(&mut self) -> std::result::Result<(), SseConnectionError> {
+            self.reconnect_timeouts.push(self.connect_timeout);
+            Ok(())
+        }
+        fn is_healthy(&mut self) -> bool {
+            self.connection.is_healthy()
+        }
+        fn is_drained(&self) -> bool {
+            self.connection.is_drained()
+        }
     }
     #[derive(Debug, Clone)]
     pub struct FakeTcpConnection {
-        responses: Vec<String>,
+        responses: Vec<Vec<u8>>,
+        // returned after all responses are read
+        read_error: Option<std::io::ErrorKind>,
+        read_timeout: Option<Duration>,
+        // read timeout which was applied to each read
+        applied_read_timeouts: Vec<Option<Duration>>,
+        closed: bool,
+        // bytes of the requests written to the fake
+        written: Vec<u8>,
     }
     impl FakeTcpConnection {
         pub fn new() -> Self {
             Self {
                 responses: Vec::new(),
+                read_error: None,
+                read_timeout: None,
+                applied_read_timeouts: Vec::new(),
+                closed: false,
+                written: Vec::new(),
             }
         }
+        pub fn written(&self) -> &[u8] {
+            &self.written
+        }
+        pub fn close(&mut self) {
+            self.closed = true;
+        }
         pub fn set_response(&mut self, response: &str) {
-            self.responses.push(response.to_string());
+            self.responses.push(response.as_bytes().to_vec());
+        }
+        // like a server sending invalid utf-8
+        pub fn set_response_bytes(&mut self, response: &[u8]) {
+            self.responses.push(response.to_vec());
+        }
+        pub fn set_read_error(&mut self, kind: std::io::ErrorKind) {
+            self.read_error = Some(kind);
+        }
+        pub fn applied_read_timeouts(&self) -> &[Option<Duration>] {
+            &self.applied_read_timeouts
         }
     }
     impl Socket for FakeTcpConnection {
-        fn write_all(&mut self, _buf: &[u8]) -> std::result::Result<(), std::io::Error> {
+        fn write_all(&mut self, buf: &[u8]) -> std::result::Result<(), std::io::Error> {
+            self.written.extend_from_slice(buf);
             Ok(())
         }
+        fn set_read_timeout(
+            &mut self,
+            timeout: Option<Duration>,
+        ) -> std::result::Result<(), std::io::Error> {
+            self.read_timeout = timeout;
+            Ok(())
+        }
+        fn is_open(&mut self) -> bool {
+            !self.closed
+        }
         fn read_line(&mut self) -> std::result::Result<Option<String>, std::io::Error> {
+            self.read_line_bytes(usize::MAX)?
+                .map(|line| {
+                    String::from_utf8(line).map_err(|e| {
+                        std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+                    })
+                })
+                .transpose()
+        }
+        // a response longer than the limit is read in pieces, like a real socket
+        fn read_line_bytes(
+            &mut self,
+            limit: usize,
+        ) -> std::result::Result<Option<Vec<u8>>, std::io::Error> {
+            self.applied_read_timeouts.push(self.read_timeout);
             if self.responses.is_empty() {
-                return Ok(None);
+                return match self.read_error {
+                    Some(kind) => Err(std::io::Error::new(kind, "fake read error")),
+                    None => Ok(None),
+                };
+            }
+            let mut line = self.responses.remove(0);
+            if line.len() > limit {
+                self.responses.insert(0, line.split_off(limit));
             }
-            Ok(Some(self.responses.remove(0)))
+            Ok(Some(line))
         }
     }
 }
@@ -647,7 +3038,7 @@ pub mod chatgpt {
         match res {
             SseResponse::Retry(_) => ChatGptRes::Err,
             SseResponse::Data(data) => {
-                if "[DONE]".contains(data) {
+                if data == "[DONE]" {
                     ChatGptRes::Done
                 } else {
                     ChatGptRes::Data(data.to_string())
@@ -655,13 +3046,18 @@ pub mod chatgpt {
             }
             SseResponse::Id(id) => ChatGptRes::Data(id.to_string()),
             SseResponse::Event(event) => ChatGptRes::Data(event.to_string()),
+            SseResponse::Comment(comment) => ChatGptRes::Data(comment.to_string()),
+            SseResponse::Other { value, .. } => ChatGptRes::Data(value.to_string()),
         }
     }
     pub fn evaluate_chatgpt_response(res: &ConnectedSseResponse) -> ChatGptRes {
         match res {
             ConnectedSseResponse::Done => ChatGptRes::Done,
-            ConnectedSseResponse::Progress(SseResponse::Data(data)) => {
-                if "[DONE]".contains(data) {
+            ConnectedSseResponse::Progress {
+                response: SseResponse::Data(data),
+                ..
+            } => {
+                if data == "[DONE]" {
                     ChatGptRes::Done
                 } else {
                     ChatGptRes::Data(data.to_string())
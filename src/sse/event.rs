@@ -0,0 +1,227 @@
+use super::response::{SseResponse, SseResponseError};
+
+const DEFAULT_EVENT_TYPE: &str = "message";
+
+// an event assembled from the fields between two blank lines
+#[derive(Debug, Clone, PartialEq)]
+pub struct SseEvent {
+    event: Option<String>,
+    data: String,
+    id: Option<String>,
+    retry: Option<u32>,
+}
+impl SseEvent {
+    pub fn event(&self) -> Option<&str> {
+        self.event.as_deref()
+    }
+    pub fn data(&self) -> &str {
+        &self.data
+    }
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+    pub fn retry(&self) -> Option<u32> {
+        self.retry
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SseEventBuilder {
+    event: Option<String>,
+    data: Vec<String>,
+    id: Option<String>,
+    retry: Option<u32>,
+    preserve_missing_event: bool,
+}
+impl SseEventBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    // by default an event without `event:` field has the type `message` like EventSource.
+    // when preserve is true, the event type is left as None
+    pub fn preserve_missing_event(mut self, preserve: bool) -> Self {
+        self.preserve_missing_event = preserve;
+        self
+    }
+    // discards the pending fields, but keeps the allocated buffer and the options
+    pub fn reset(&mut self) {
+        self.event = None;
+        self.data.clear();
+        self.id = None;
+        self.retry = None;
+    }
+    pub fn push(&mut self, res: SseResponse) {
+        match res {
+            SseResponse::Event(event) => self.event = Some(event),
+            SseResponse::Data(data) => self.data.push(data),
+            SseResponse::Id(id) => self.id = Some(id),
+            SseResponse::Retry(retry) => self.retry = Some(retry),
+            SseResponse::Comment(_) | SseResponse::Other { .. } => {}
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.event.is_none() && self.data.is_empty() && self.retry.is_none()
+    }
+    // called on a blank line.
+    // returns None if no data has been pushed, because such a block is not dispatched
+    pub fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        let retry = self.retry.take();
+        if self.data.is_empty() {
+            return None;
+        }
+        let event = match event {
+            Some(event) => Some(event),
+            None if self.preserve_missing_event => None,
+            None => Some(DEFAULT_EVENT_TYPE.to_string()),
+        };
+        Some(SseEvent {
+            event,
+            data: self.data.drain(..).collect::<Vec<_>>().join("\n"),
+            // last event id is kept over events
+            id: self.id.clone(),
+            retry,
+        })
+    }
+}
+
+// accumulates events from raw lines, so that completed events can be consumed at once
+#[derive(Debug, Clone, Default)]
+pub struct SseEventStore {
+    builder: SseEventBuilder,
+    events: Vec<SseEvent>,
+}
+impl SseEventStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    // capacity is a hint of the number of data lines and events, to avoid reallocations on large events
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            builder: SseEventBuilder {
+                data: Vec::with_capacity(capacity),
+                ..SseEventBuilder::default()
+            },
+            events: Vec::with_capacity(capacity),
+        }
+    }
+    // clears everything so that the store can be reused for the next request without allocation
+    pub fn reset(&mut self) {
+        self.builder.reset();
+        self.events.clear();
+    }
+    // a stream without http preamble is expected, so every line is an sse field or a blank line
+    pub fn feed_line(&mut self, line: &str) -> Result<(), SseResponseError> {
+        if line.trim_end_matches(['\r', '\n']).is_empty() {
+            self.events.extend(self.builder.dispatch());
+            return Ok(());
+        }
+        self.builder.push(SseResponse::from_line(line)?);
+        Ok(())
+    }
+    // returns the data of the completed events and clears them
+    pub fn drain_events(&mut self) -> Vec<String> {
+        self.events
+            .drain(..)
+            .map(|event| event.data().to_string())
+            .collect()
+    }
+}
+impl IntoIterator for SseEventStore {
+    type Item = SseEvent;
+    type IntoIter = std::vec::IntoIter<SseEvent>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.events.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn eventの指定がない場合はmessageとなる() {
+        let mut sut = SseEventBuilder::new();
+        sut.push(SseResponse::Data("hello".to_string()));
+
+        let event = sut.dispatch().unwrap();
+
+        assert_eq!(event.event(), Some("message"));
+        assert_eq!(event.data(), "hello");
+    }
+    #[test]
+    #[allow(non_snake_case)]
+    fn eventの指定がないことを保持する場合はNoneとなる() {
+        let mut sut = SseEventBuilder::new().preserve_missing_event(true);
+        sut.push(SseResponse::Data("hello".to_string()));
+
+        let event = sut.dispatch().unwrap();
+
+        assert_eq!(event.event(), None);
+    }
+    #[test]
+    fn 複数行のdataは改行で結合される() {
+        let mut sut = SseEventBuilder::new();
+        sut.push(SseResponse::Event("update".to_string()));
+        sut.push(SseResponse::Id("1".to_string()));
+        sut.push(SseResponse::Data("hello".to_string()));
+        sut.push(SseResponse::Data("world".to_string()));
+
+        let event = sut.dispatch().unwrap();
+
+        assert_eq!(event.event(), Some("update"));
+        assert_eq!(event.id(), Some("1"));
+        assert_eq!(event.data(), "hello\nworld");
+    }
+    #[test]
+    fn 完了したeventのdataをまとめて取り出せる() {
+        let mut sut = SseEventStore::new();
+        for line in [
+            "data: first\n",
+            "\n",
+            "data: second\n",
+            "data: line\n",
+            "\n",
+            "data: pending\n",
+        ] {
+            sut.feed_line(line).unwrap();
+        }
+
+        assert_eq!(
+            sut.drain_events(),
+            vec!["first".to_string(), "second\nline".to_string()]
+        );
+        assert!(sut.drain_events().is_empty());
+
+        sut.feed_line("\n").unwrap();
+        let events = sut.into_iter().collect::<Vec<_>>();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].data(), "pending");
+    }
+    #[test]
+    fn resetしたstoreは確保済みのbufferを再利用する() {
+        let mut sut = SseEventStore::with_capacity(8);
+        let data_ptr = sut.builder.data.as_ptr();
+        let events_ptr = sut.events.as_ptr();
+
+        for _ in 0..3 {
+            sut.feed_line("data: first\n").unwrap();
+            sut.feed_line("\n").unwrap();
+            sut.feed_line("data: pending\n").unwrap();
+            sut.reset();
+        }
+
+        assert!(sut.builder.is_empty());
+        assert!(sut.events.is_empty());
+        assert_eq!(sut.builder.data.as_ptr(), data_ptr);
+        assert_eq!(sut.events.as_ptr(), events_ptr);
+        assert!(sut.events.capacity() >= 8);
+    }
+    #[test]
+    fn dataがない場合はdispatchされない() {
+        let mut sut = SseEventBuilder::new();
+        sut.push(SseResponse::Event("update".to_string()));
+
+        assert!(sut.dispatch().is_none());
+        assert!(sut.is_empty());
+    }
+}
@@ -0,0 +1,174 @@
+use serde::de::DeserializeOwned;
+
+use super::{
+    response::{SseResponse, DONE_SENTINEL},
+    subscriber::{HandleProgress, SseMutHandler, StreamContext},
+};
+
+// composes two handlers.
+// the first handler returns ContinueWith to pass a (transformed) response to the second handler,
+// and Progress to filter the response out
+pub struct ChainHandler<A, B>(pub A, pub B);
+
+impl<A, B> ChainHandler<A, B> {
+    pub fn into_inner(self) -> (A, B) {
+        (self.0, self.1)
+    }
+}
+
+impl<T, E, A, B> SseMutHandler<T, E> for ChainHandler<A, B>
+where
+    A: SseMutHandler<(), E>,
+    B: SseMutHandler<T, E>,
+{
+    fn handle(&mut self, res: SseResponse) -> HandleProgress<E> {
+        match self.0.handle(res) {
+            HandleProgress::ContinueWith(res) => self.1.handle(res),
+            progress => progress,
+        }
+    }
+    fn handle_with_ctx(&mut self, res: SseResponse, ctx: &StreamContext) -> HandleProgress<E> {
+        match self.0.handle_with_ctx(res, ctx) {
+            HandleProgress::ContinueWith(res) => self.1.handle_with_ctx(res, ctx),
+            progress => progress,
+        }
+    }
+    fn result(&self) -> std::result::Result<T, E> {
+        self.0.result()?;
+        self.1.result()
+    }
+}
+
+// assembles a json value split across data events, like the ones fragmented by some proxies.
+// data are buffered until the buffer parses, and an incomplete buffer over max_bytes is an error.
+// the `[DONE]` sentinel of llm apis ends the stream
+pub struct JsonCoalescingHandler<T> {
+    items: Vec<T>,
+    buffer: String,
+    max_bytes: usize,
+}
+
+impl<T> JsonCoalescingHandler<T> {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            items: Vec::new(),
+            buffer: String::new(),
+            max_bytes,
+        }
+    }
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+    pub fn into_items(self) -> Vec<T> {
+        self.items
+    }
+}
+
+impl<T: DeserializeOwned + Clone> SseMutHandler<Vec<T>, serde_json::Error>
+    for JsonCoalescingHandler<T>
+{
+    fn handle(&mut self, res: SseResponse) -> HandleProgress<serde_json::Error> {
+        let SseResponse::Data(data) = res else {
+            return HandleProgress::Progress;
+        };
+        if self.buffer.is_empty() && data == DONE_SENTINEL {
+            return HandleProgress::Done;
+        }
+        self.buffer.push_str(&data);
+        match serde_json::from_str(&self.buffer) {
+            Ok(item) => {
+                self.items.push(item);
+                self.buffer.clear();
+                HandleProgress::Progress
+            }
+            // the rest of the value may arrive in the next data
+            Err(e) if e.is_eof() && self.buffer.len() <= self.max_bytes => HandleProgress::Progress,
+            Err(e) => HandleProgress::Err(e),
+        }
+    }
+    fn result(&self) -> std::result::Result<Vec<T>, serde_json::Error> {
+        Ok(self.items.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sse::subscriber::fakes::MockMutHandler;
+
+    use super::*;
+
+    struct UppercaseDataFilter;
+    impl SseMutHandler<(), ()> for UppercaseDataFilter {
+        fn handle(&mut self, res: SseResponse) -> HandleProgress<()> {
+            match res {
+                SseResponse::Data(data) => {
+                    HandleProgress::ContinueWith(SseResponse::Data(data.to_uppercase()))
+                }
+                _ => HandleProgress::Progress,
+            }
+        }
+        fn result(&self) -> std::result::Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn 前段のhandlerで変換したresponseを後段のhandlerが受け取る() {
+        let mut sut = ChainHandler(UppercaseDataFilter, MockMutHandler::new());
+
+        sut.handle(SseResponse::Event("greeting".to_string()));
+        sut.handle(SseResponse::Data("hello".to_string()));
+        sut.handle(SseResponse::Comment("ping".to_string()));
+        sut.handle(SseResponse::Data("world".to_string()));
+
+        assert!(sut.result().is_ok());
+        let (_, collector) = sut.into_inner();
+        collector.assert_received(&[
+            SseResponse::Data("HELLO".to_string()),
+            SseResponse::Data("WORLD".to_string()),
+        ]);
+    }
+    #[derive(serde::Deserialize, Debug, Clone, PartialEq)]
+    struct Delta {
+        content: String,
+    }
+    #[test]
+    fn 複数のdataに分割されたjsonを組み立てる() {
+        let mut sut = JsonCoalescingHandler::<Delta>::new(1024);
+
+        sut.handle(SseResponse::Data("{\"content\":\"Hel".to_string()));
+        sut.handle(SseResponse::Event("delta".to_string()));
+        sut.handle(SseResponse::Data("lo\"}".to_string()));
+        sut.handle(SseResponse::Data("{\"content\":\"World\"}".to_string()));
+        let done = sut.handle(SseResponse::Data("[DONE]".to_string()));
+
+        assert!(done.is_done());
+        assert_eq!(
+            sut.result().unwrap(),
+            vec![
+                Delta {
+                    content: "Hello".to_string()
+                },
+                Delta {
+                    content: "World".to_string()
+                },
+            ]
+        );
+    }
+    #[test]
+    fn 組み立てられないjsonはerrorになる() {
+        let mut sut = JsonCoalescingHandler::<Delta>::new(16);
+
+        assert!(sut
+            .handle(SseResponse::Data("{\"content\":".to_string()))
+            .is_progress());
+        assert!(sut
+            .handle(SseResponse::Data("\"too long to be buffered".to_string()))
+            .is_err());
+
+        let mut sut = JsonCoalescingHandler::<Delta>::new(1024);
+        assert!(sut
+            .handle(SseResponse::Data("not json".to_string()))
+            .is_err());
+    }
+}
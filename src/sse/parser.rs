@@ -0,0 +1,104 @@
+// field parser which only depends on core, so it can be used without std
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SseField<'a> {
+    Event(&'a str),
+    Data(&'a str),
+    Id(&'a str),
+    Retry(u32),
+    Comment(&'a str),
+    Other { field: &'a str, value: &'a str },
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ParseError {
+    InvalidFormat,
+    InvalidRetry,
+}
+
+pub fn parse_line(line: &str) -> Result<SseField<'_>, ParseError> {
+    // almost every line of llm streams is data, so it is checked before splitting the field
+    if let Some(value) = line.strip_prefix("data:") {
+        return Ok(SseField::Data(trim(value)));
+    }
+    let Some((field, value)) = line.split_once(':') else {
+        return Err(ParseError::InvalidFormat);
+    };
+    parse_field(field, trim(value))
+}
+
+// for fields which are already split into the name and the value, the value is used as is
+pub fn parse_field<'a>(field: &'a str, value: &'a str) -> Result<SseField<'a>, ParseError> {
+    match field {
+        "data" => Ok(SseField::Data(value)),
+        "event" => Ok(SseField::Event(value)),
+        // an id with NULL must be ignored by the spec, so it is treated like an unknown field
+        "id" if value.contains('\0') => Ok(SseField::Other { field, value }),
+        "id" => Ok(SseField::Id(value)),
+        "retry" => value
+            .parse::<u32>()
+            .map(SseField::Retry)
+            .map_err(|_e| ParseError::InvalidRetry),
+        "" => Ok(SseField::Comment(value)),
+        _ => Ok(SseField::Other { field, value }),
+    }
+}
+
+// values are kept verbatim except for a single leading space and the line terminator
+fn trim(value: &str) -> &str {
+    let value = value.trim_end_matches(['\r', '\n']);
+    value.strip_prefix(' ').unwrap_or(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn 各fieldをallocなしでparseできる() {
+        assert_eq!(parse_line("data: hello\r\n"), Ok(SseField::Data("hello")));
+        assert_eq!(
+            parse_line("event:  greeting\n"),
+            Ok(SseField::Event(" greeting"))
+        );
+        assert_eq!(parse_line("id: 1"), Ok(SseField::Id("1")));
+        assert_eq!(parse_line("retry: 3000\n"), Ok(SseField::Retry(3000)));
+        assert_eq!(parse_line(": ping\n"), Ok(SseField::Comment("ping")));
+        assert_eq!(
+            parse_line("foo: bar\n"),
+            Ok(SseField::Other {
+                field: "foo",
+                value: "bar"
+            })
+        );
+    }
+    #[test]
+    fn 分割済みのfieldをparseできる() {
+        assert_eq!(parse_field("data", " hello"), Ok(SseField::Data(" hello")));
+        assert_eq!(parse_field("retry", "10"), Ok(SseField::Retry(10)));
+        assert_eq!(parse_field("", "ping"), Ok(SseField::Comment("ping")));
+        assert_eq!(parse_field("retry", "soon"), Err(ParseError::InvalidRetry));
+    }
+    #[test]
+    fn 値に含まれるfield名はそのまま残る() {
+        assert_eq!(
+            parse_line("data: data: {\"data:\":1}\r\n"),
+            Ok(SseField::Data("data: {\"data:\":1}"))
+        );
+        assert_eq!(parse_line("event: data:\n"), Ok(SseField::Event("data:")));
+    }
+    #[test]
+    fn nullを含むidは無視される() {
+        assert_eq!(
+            parse_line("id: 1\0\n"),
+            Ok(SseField::Other {
+                field: "id",
+                value: "1\0"
+            })
+        );
+    }
+    #[test]
+    fn 不正な行はerrorを返す() {
+        assert_eq!(parse_line("retry: soon\n"), Err(ParseError::InvalidRetry));
+        assert_eq!(parse_line("hello\n"), Err(ParseError::InvalidFormat));
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+
+use crate::{client::SseClient, http::url::Url};
+
+use super::connector::{Result, SseConnector, SseTlsConnector, SseTlsConnectorBuilder};
+
+type Connect<C> = Box<dyn FnMut(&Url) -> Result<C>>;
+
+// keeps connectors per origin, so that short streams to the same host reuse the connection
+pub struct SseConnectionPool<C: SseConnector> {
+    idle: HashMap<Url, Vec<C>>,
+    connect: Connect<C>,
+}
+
+impl<C: SseConnector> SseConnectionPool<C> {
+    pub fn new(connect: impl FnMut(&Url) -> Result<C> + 'static) -> Self {
+        Self {
+            idle: HashMap::new(),
+            connect: Box::new(connect),
+        }
+    }
+    // an idle connector for the host is reused, otherwise a new one is connected
+    pub fn client(&mut self, url: &Url) -> Result<SseClient<C>> {
+        let connector = match self.idle.get_mut(&Self::key(url)).and_then(Vec::pop) {
+            Some(connector) => connector,
+            None => (self.connect)(url)?,
+        };
+        Ok(SseClient::new(connector, url))
+    }
+    // the connector is returned to the pool only when the stream is read to its end and the socket
+    // is still healthy. the socket may be broken after an error, and a stream stopped by the handler
+    // leaves its rest to the next borrower
+    pub fn release<T, E>(&mut self, client: SseClient<C>, result: &std::result::Result<T, E>) {
+        let key = Self::key(client.url());
+        let mut connector = client.into_connector();
+        if result.is_err() || !connector.is_drained() || !connector.is_healthy() {
+            return;
+        }
+        self.idle.entry(key).or_default().push(connector);
+    }
+    pub fn idle_count(&self, url: &Url) -> usize {
+        self.idle.get(&Self::key(url)).map_or(0, Vec::len)
+    }
+    // the path does not matter for the connection
+    fn key(url: &Url) -> Url {
+        url.with_path("/")
+    }
+}
+
+impl SseConnectionPool<SseTlsConnector> {
+    pub fn tls() -> Self {
+        Self::new(|url| SseTlsConnectorBuilder::new(url).build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use crate::sse::{
+        connector::fakes::FakeSseConnector, response::SseResponse, subscriber::HandleProgress,
+    };
+
+    use super::*;
+
+    fn fake_pool(created: Rc<Cell<usize>>) -> SseConnectionPool<FakeSseConnector> {
+        SseConnectionPool::new(move |_url| {
+            created.set(created.get() + 1);
+            let mut connector = FakeSseConnector::new();
+            // chunked, so that each response ends on the keep-alive connection
+            for data in ["first", "second"] {
+                let event = format!("data: {}\r\n", data);
+                connector.set_response("HTTP/1.1 200 OK\r\n");
+                connector.set_response("Content-Type: text/event-stream\r\n");
+                connector.set_response("Transfer-Encoding: chunked\r\n");
+                connector.set_response("\r\n");
+                connector.set_response(&format!("{:x}\r\n", event.len() + 2));
+                connector.set_response(&event);
+                connector.set_response("\r\n");
+                connector.set_response("\r\n");
+                connector.set_response("0\r\n");
+                connector.set_response("\r\n");
+            }
+            Ok(connector)
+        })
+    }
+    // when stop is true, the handler stops the stream at the first data
+    fn first_data(
+        client: &mut SseClient<FakeSseConnector>,
+        stop: bool,
+    ) -> (String, crate::sse::subscriber::Result<(), ()>) {
+        let mut received = String::new();
+        let result = client.send_mut_fn(|res| {
+            let SseResponse::Data(data) = res else {
+                return HandleProgress::Progress;
+            };
+            if received.is_empty() {
+                received = data;
+            }
+            if stop {
+                HandleProgress::Done
+            } else {
+                HandleProgress::Progress
+            }
+        });
+        (received, result)
+    }
+
+    #[test]
+    fn 同じhostへの連続したrequestは一つのconnectionを再利用する() {
+        let created = Rc::new(Cell::new(0));
+        let mut sut = fake_pool(created.clone());
+        let url: Url = "https://www.fake".try_into().unwrap();
+
+        let mut client = sut.client(&url).unwrap();
+        let (received, result) = first_data(&mut client, false);
+        assert_eq!(received, "first");
+        sut.release(client, &result);
+
+        let mut client = sut.client(&url).unwrap();
+        let (received, _) = first_data(&mut client, false);
+        assert_eq!(received, "second");
+
+        assert_eq!(created.get(), 1);
+        assert_eq!(client.into_connector().connected_times(), 2);
+    }
+    #[test]
+    fn errorで終わったconnectionはpoolに戻さない() {
+        let created = Rc::new(Cell::new(0));
+        let mut sut = fake_pool(created.clone());
+        let url: Url = "https://www.fake".try_into().unwrap();
+
+        let client = sut.client(&url).unwrap();
+        sut.release(client, &Err::<(), ()>(()));
+
+        assert_eq!(sut.idle_count(&url), 0);
+        sut.client(&url).unwrap();
+        assert_eq!(created.get(), 2);
+    }
+    #[test]
+    fn handlerが途中で終了したconnectionはpoolに戻さない() {
+        let created = Rc::new(Cell::new(0));
+        let mut sut = fake_pool(created.clone());
+        let url: Url = "https://www.fake".try_into().unwrap();
+
+        let mut client = sut.client(&url).unwrap();
+        let (received, result) = first_data(&mut client, true);
+        assert_eq!(received, "first");
+        assert!(result.is_ok());
+        sut.release(client, &result);
+
+        assert_eq!(sut.idle_count(&url), 0);
+        let mut client = sut.client(&url).unwrap();
+        let (received, _) = first_data(&mut client, false);
+        assert_eq!(received, "first");
+        assert_eq!(created.get(), 2);
+    }
+}
@@ -0,0 +1,153 @@
+use std::io::BufRead;
+
+use thiserror::Error;
+
+use super::{
+    event::{SseEvent, SseEventBuilder},
+    response::{SseResponse, SseResponseError},
+};
+
+// reads a sse body obtained elsewhere, like a file, stdin or a response of another http client.
+// the source has no http preamble, so every line is an sse field or a blank line
+#[derive(Debug)]
+pub struct SseReader<R: BufRead> {
+    reader: R,
+    line: String,
+    builder: SseEventBuilder,
+}
+impl<R: BufRead> SseReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: String::new(),
+            builder: SseEventBuilder::new(),
+        }
+    }
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+    // blank lines are skipped, so only the fields are returned. None at the end of the source
+    pub fn read_response(&mut self) -> Result<Option<SseResponse>, SseReaderError> {
+        loop {
+            match self.read_line()? {
+                Some(Some(res)) => return Ok(Some(res)),
+                Some(None) => continue,
+                None => return Ok(None),
+            }
+        }
+    }
+    // fields are grouped until a blank line like EventSource.
+    // an event not terminated by a blank line at the end of the source is discarded by the spec
+    pub fn read_event(&mut self) -> Result<Option<SseEvent>, SseReaderError> {
+        loop {
+            match self.read_line()? {
+                Some(Some(res)) => self.builder.push(res),
+                Some(None) => {
+                    if let Some(event) = self.builder.dispatch() {
+                        return Ok(Some(event));
+                    }
+                }
+                None => {
+                    self.builder.reset();
+                    return Ok(None);
+                }
+            }
+        }
+    }
+    pub fn events(mut self) -> impl Iterator<Item = Result<SseEvent, SseReaderError>> {
+        std::iter::from_fn(move || self.read_event().transpose())
+    }
+    // Some(None) is a blank line, and None is the end of the source
+    fn read_line(&mut self) -> Result<Option<Option<SseResponse>>, SseReaderError> {
+        self.line.clear();
+        if self.reader.read_line(&mut self.line)? == 0 {
+            return Ok(None);
+        }
+        if self.line.trim_end_matches(['\r', '\n']).is_empty() {
+            return Ok(Some(None));
+        }
+        Ok(Some(Some(SseResponse::from_line(&self.line)?)))
+    }
+}
+impl<R: BufRead> Iterator for SseReader<R> {
+    type Item = Result<SseResponse, SseReaderError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_response().transpose()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SseReaderError {
+    #[error("read io error {0:?}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid sse line {0}")]
+    InvalidLine(#[from] SseResponseError),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::sse::testing::assert_stream_eq;
+
+    use super::*;
+
+    const BODY: &str = concat!(
+        ": keep-alive\r\n",
+        "\r\n",
+        "event: greeting\r\n",
+        "data: Hello\r\n",
+        "data: World\r\n",
+        "id: 1\r\n",
+        "\r\n",
+        "data: second\n",
+        "\n",
+        "data: not terminated\n",
+    );
+
+    #[test]
+    fn cursorから各fieldを読み取れる() {
+        let sut = SseReader::new(Cursor::new(BODY));
+
+        let responses = sut.collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_stream_eq(
+            &responses,
+            &[
+                SseResponse::comment("keep-alive"),
+                SseResponse::event("greeting"),
+                SseResponse::data("Hello"),
+                SseResponse::data("World"),
+                SseResponse::id("1"),
+                SseResponse::data("second"),
+                SseResponse::data("not terminated"),
+            ],
+        );
+    }
+    #[test]
+    fn cursorから複数のeventを読み取れる() {
+        let sut = SseReader::new(Cursor::new(BODY));
+
+        let events = sut.events().collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event(), Some("greeting"));
+        assert_eq!(events[0].data(), "Hello\nWorld");
+        assert_eq!(events[0].id(), Some("1"));
+        assert_eq!(events[1].event(), Some("message"));
+        assert_eq!(events[1].data(), "second");
+        assert_eq!(events[1].id(), Some("1"));
+    }
+    #[test]
+    fn 不正な行はerrorを返す() {
+        let mut sut = SseReader::new(Cursor::new("data: Hello\n\nretry: soon\n\n"));
+
+        assert!(sut.read_event().unwrap().is_some());
+        assert!(matches!(
+            sut.read_event(),
+            Err(SseReaderError::InvalidLine(SseResponseError::InvalidRetry(
+                _
+            )))
+        ));
+    }
+}
@@ -1,61 +1,310 @@
 use std::fmt::Display;
 
+use super::parser::{self, parse_line, ParseError, SseField};
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum SseResponse {
     Event(String),
     Data(String),
     Id(String),
     Retry(u32),
+    Comment(String),
+    // unknown fields, which should be ignored by the spec
+    Other { field: String, value: String },
 }
 
+// the data which ends the stream of llm apis, like openai
+pub const DONE_SENTINEL: &str = "[DONE]";
+
 impl SseResponse {
+    pub fn data(data: impl Into<String>) -> Self {
+        Self::Data(data.into())
+    }
+    pub fn event(event: impl Into<String>) -> Self {
+        Self::Event(event.into())
+    }
+    pub fn id(id: impl Into<String>) -> Self {
+        Self::Id(id.into())
+    }
+    pub fn retry(retry: u32) -> Self {
+        Self::Retry(retry)
+    }
+    pub fn comment(comment: impl Into<String>) -> Self {
+        Self::Comment(comment.into())
+    }
     pub fn from_line(line: &str) -> Result<Self, SseResponseError> {
-        if line.starts_with("data:") {
-            return Ok(Self::Data(Self::trim(line, "data:")));
+        Self::from_parsed(parse_line(line), line)
+    }
+    // for transports which deliver bytes, invalid utf-8 is an error
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SseResponseError> {
+        let line =
+            std::str::from_utf8(bytes).map_err(|e| SseResponseError::InvalidUtf8(e.to_string()))?;
+        Self::from_line(line)
+    }
+    // invalid utf-8 is replaced with U+FFFD, like the lossy_utf8 option of connectors
+    pub fn from_bytes_lossy(bytes: &[u8]) -> Result<Self, SseResponseError> {
+        Self::from_line(&String::from_utf8_lossy(bytes))
+    }
+    // for fields which are already split, like the ones from another sse library
+    pub fn parse_field(field: &str, value: &str) -> Result<Self, SseResponseError> {
+        Self::from_parsed(
+            parser::parse_field(field, value),
+            format_args!("{}:{}", field, value),
+        )
+    }
+    // the line is only formatted for an error, so that a valid field is not allocated twice
+    fn from_parsed(
+        parsed: Result<SseField, ParseError>,
+        line: impl Display,
+    ) -> Result<Self, SseResponseError> {
+        match parsed {
+            Ok(SseField::Data(value)) => Ok(Self::Data(value.to_string())),
+            Ok(SseField::Event(value)) => Ok(Self::Event(value.to_string())),
+            Ok(SseField::Id(value)) => Ok(Self::Id(value.to_string())),
+            Ok(SseField::Retry(retry)) => Ok(Self::Retry(retry)),
+            Ok(SseField::Comment(value)) => Ok(Self::Comment(value.to_string())),
+            Ok(SseField::Other { field, value }) => Ok(Self::Other {
+                field: field.to_string(),
+                value: value.to_string(),
+            }),
+            Err(ParseError::InvalidRetry) => Err(SseResponseError::InvalidRetry(format!(
+                "Invalid retry : {}",
+                line
+            ))),
+            Err(ParseError::InvalidFormat) => Err(SseResponseError::InvalidFormat(format!(
+                "Invalid format: {}",
+                line
+            ))),
         }
-        if line.starts_with("event:") {
-            return Ok(Self::Event(Self::trim(line, "event:")));
+    }
+    pub fn is_data(&self) -> bool {
+        matches!(self, Self::Data(_))
+    }
+    pub fn is_event(&self) -> bool {
+        matches!(self, Self::Event(_))
+    }
+    pub fn is_id(&self) -> bool {
+        matches!(self, Self::Id(_))
+    }
+    pub fn is_retry(&self) -> bool {
+        matches!(self, Self::Retry(_))
+    }
+    pub fn is_comment(&self) -> bool {
+        matches!(self, Self::Comment(_))
+    }
+    pub fn is_other(&self) -> bool {
+        matches!(self, Self::Other { .. })
+    }
+    pub fn is_done_sentinel(&self) -> bool {
+        matches!(self, Self::Data(data) if data == DONE_SENTINEL)
+    }
+    // the inner string regardless of the field kind
+    pub fn into_string(self) -> Option<String> {
+        match self {
+            Self::Data(value) | Self::Event(value) | Self::Id(value) => Some(value),
+            Self::Retry(_) | Self::Comment(_) | Self::Other { .. } => None,
         }
-        if line.starts_with("id:") {
-            return Ok(Self::Id(Self::trim(line, "id:")));
+    }
+}
+
+// parse a whole sse body without network, mainly for tests.
+// data lines of the same event are joined with a newline, and comments and unknown fields are skipped
+pub fn parse_stream(body: &str) -> Result<Vec<SseResponse>, SseResponseError> {
+    let mut responses = Vec::new();
+    // index of the joined data of the current event
+    let mut data_index: Option<usize> = None;
+    for line in body.lines() {
+        if line.is_empty() {
+            data_index = None;
+            continue;
         }
-        if line.starts_with("retry:") {
-            let Ok(retry) = 
-                Self::trim(line, "retry:")
-                    .parse::<u32>() else {
-                return Err(SseResponseError::InvalidRetry(format!("Invalid retry : {}", line)))
-                    };
-            return Ok(Self::Retry(retry));
+        let response = SseResponse::from_line(line)?;
+        match (response, data_index) {
+            (SseResponse::Comment(_) | SseResponse::Other { .. }, _) => {}
+            (SseResponse::Data(data), Some(index)) => {
+                if let SseResponse::Data(joined) = &mut responses[index] {
+                    joined.push('\n');
+                    joined.push_str(&data);
+                }
+            }
+            (SseResponse::Data(data), None) => {
+                data_index = Some(responses.len());
+                responses.push(SseResponse::Data(data));
+            }
+            (response, _) => responses.push(response),
         }
-        Err(SseResponseError::InvalidFormat(format!(
-            "Invalid format: {}",
-            line
-        )))
     }
-    fn trim(line: &str, res_type: &str) -> String {
-        line.replace(res_type, "").trim().to_string()
+    Ok(responses)
+}
+
+impl TryFrom<&[u8]> for SseResponse {
+    type Error = SseResponseError;
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        Self::from_bytes(bytes)
     }
 }
 
 #[derive(Debug, PartialEq)]
 pub enum SseResponseError {
     InvalidFormat(String),
-    InvalidRetry(String)
+    InvalidRetry(String),
+    InvalidUtf8(String),
 }
 impl Display for SseResponseError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             Self::InvalidFormat(message) => write!(f, "InvalidFormat: {}", message),
             Self::InvalidRetry(message) => write!(f, "InvalidRetry: {}", message),
+            Self::InvalidUtf8(message) => write!(f, "InvalidUtf8: {}", message),
         }
     }
 }
 impl std::error::Error for SseResponseError {}
 #[cfg(test)]
 mod tests {
+    use crate::sse::testing::assert_stream_eq;
 
     use super::*;
     #[test]
+    fn 各variantをconstructorで作成できる() {
+        assert_eq!(
+            SseResponse::data("hello"),
+            SseResponse::Data("hello".to_string())
+        );
+        assert_eq!(
+            SseResponse::event(String::from("greeting")),
+            SseResponse::Event("greeting".to_string())
+        );
+        assert_eq!(SseResponse::id("1"), SseResponse::Id("1".to_string()));
+        assert_eq!(SseResponse::retry(3000), SseResponse::Retry(3000));
+        assert_eq!(
+            SseResponse::comment("ping"),
+            SseResponse::Comment("ping".to_string())
+        );
+    }
+    #[test]
+    fn bytesからresponseを作成できる() {
+        let bytes = "data: こんにちは\r\n".as_bytes();
+
+        assert_eq!(
+            SseResponse::from_bytes(bytes),
+            Ok(SseResponse::data("こんにちは"))
+        );
+        assert_eq!(
+            SseResponse::try_from(bytes),
+            Ok(SseResponse::data("こんにちは"))
+        );
+        assert_eq!(
+            SseResponse::from_bytes_lossy(bytes),
+            Ok(SseResponse::data("こんにちは"))
+        );
+    }
+    #[test]
+    fn 不正なutf8はstrictではerrorになりlossyでは置換される() {
+        let bytes = b"data: Hello\xff\r\n";
+
+        assert!(matches!(
+            SseResponse::from_bytes(bytes),
+            Err(SseResponseError::InvalidUtf8(_))
+        ));
+        assert_eq!(
+            SseResponse::from_bytes_lossy(bytes),
+            Ok(SseResponse::data("Hello\u{FFFD}"))
+        );
+    }
+    #[test]
+    fn 分割済みのfieldからresponseを作成できる() {
+        assert_eq!(
+            SseResponse::parse_field("data", "hello"),
+            Ok(SseResponse::data("hello"))
+        );
+        assert_eq!(
+            SseResponse::parse_field("retry", "3000"),
+            Ok(SseResponse::retry(3000))
+        );
+        assert_eq!(
+            SseResponse::parse_field("foo", "bar"),
+            Ok(SseResponse::Other {
+                field: "foo".to_string(),
+                value: "bar".to_string()
+            })
+        );
+        assert!(SseResponse::parse_field("retry", "soon").is_err());
+    }
+    #[test]
+    fn data_event_idは中の文字列を取り出せる() {
+        assert_eq!(
+            SseResponse::Data("data".to_string()).into_string(),
+            Some("data".to_string())
+        );
+        assert_eq!(
+            SseResponse::Event("event".to_string()).into_string(),
+            Some("event".to_string())
+        );
+        assert_eq!(
+            SseResponse::Id("id".to_string()).into_string(),
+            Some("id".to_string())
+        );
+    }
+    #[test]
+    fn retryとcommentと未知のfieldは文字列を取り出せない() {
+        assert_eq!(SseResponse::Retry(3000).into_string(), None);
+        assert_eq!(SseResponse::Comment("ping".to_string()).into_string(), None);
+        assert_eq!(
+            SseResponse::Other {
+                field: "foo".to_string(),
+                value: "bar".to_string()
+            }
+            .into_string(),
+            None
+        );
+    }
+    #[test]
+    fn 複数のeventを含むbodyをparseできる() {
+        let body = concat!(
+            ": keep-alive\r\n",
+            "\r\n",
+            "event: greeting\r\n",
+            "data: Hello\r\n",
+            "data: World\r\n",
+            "id: 1\r\n",
+            "\r\n",
+            "data: second\n",
+            ": ping\n",
+            "data: event\n",
+            "\n",
+            "retry: 3000\n",
+            "data: last\n"
+        );
+
+        let sut = parse_stream(body).unwrap();
+
+        assert_stream_eq(
+            &sut,
+            &[
+                SseResponse::Event("greeting".to_string()),
+                SseResponse::Data("Hello\nWorld".to_string()),
+                SseResponse::Id("1".to_string()),
+                SseResponse::Data("second\nevent".to_string()),
+                SseResponse::Retry(3000),
+                SseResponse::Data("last".to_string()),
+            ],
+        );
+    }
+    #[test]
+    fn 不正な行を含むbodyはerrorを返す() {
+        let body = "data: Hello\n\nretry: soon\n\n";
+
+        let sut = parse_stream(body);
+
+        assert_eq!(
+            sut,
+            Err(SseResponseError::InvalidRetry(
+                "Invalid retry : retry: soon".to_string()
+            ))
+        );
+    }
+    #[test]
     fn sseのdataの場合() {
         let sse_data = "data: hello world\n\n";
 
@@ -64,6 +313,49 @@ mod tests {
         assert_eq!(SseResponse::Data("hello world".to_string()), sut);
     }
     #[test]
+    fn sseのdataの値はデコードせずにそのまま保持する() {
+        let sse_data = "data: hello%20world  with  spaces \r\n";
+
+        let sut = SseResponse::from_line(sse_data).unwrap();
+
+        assert_eq!(
+            SseResponse::Data("hello%20world  with  spaces ".to_string()),
+            sut
+        );
+    }
+    #[test]
+    fn done_sentinelの末尾の改行は取り除かれる() {
+        let sse_data = "data: [DONE]\r\n";
+
+        let sut = SseResponse::from_line(sse_data).unwrap();
+
+        assert_eq!(SseResponse::Data("[DONE]".to_string()), sut);
+    }
+    #[test]
+    fn 先頭の空白は一つだけ取り除く() {
+        let sse_data = "data:  hello\n";
+
+        let sut = SseResponse::from_line(sse_data).unwrap();
+
+        assert_eq!(SseResponse::Data(" hello".to_string()), sut);
+    }
+    #[test]
+    fn 値の中のfield名は取り除かない() {
+        let sse_data = "id:data:id:1\n";
+
+        let sut = SseResponse::from_line(sse_data).unwrap();
+
+        assert_eq!(SseResponse::Id("data:id:1".to_string()), sut);
+    }
+    #[test]
+    fn 先頭のprefixだけを取り除く() {
+        let sse_data = "data: data:inner\n";
+
+        let sut = SseResponse::from_line(sse_data).unwrap();
+
+        assert_eq!(SseResponse::Data("data:inner".to_string()), sut);
+    }
+    #[test]
     fn sseのeventの場合() {
         let sse_data = "event: hello world\n\n";
 
@@ -89,6 +381,70 @@ mod tests {
         assert_eq!(SseResponse::Retry(retry), sut);
     }
     #[test]
+    fn sseのcommentの場合() {
+        let sse_data = ": keep-alive\n\n";
+
+        let sut = SseResponse::from_line(sse_data).unwrap();
+
+        assert_eq!(SseResponse::Comment("keep-alive".to_string()), sut);
+    }
+    #[test]
+    fn 未知のfieldはotherとして扱う() {
+        let sse_data = "foo: bar\r\n";
+
+        let sut = SseResponse::from_line(sse_data).unwrap();
+
+        assert_eq!(
+            SseResponse::Other {
+                field: "foo".to_string(),
+                value: "bar".to_string()
+            },
+            sut
+        );
+        assert!(sut.is_other());
+    }
+    #[test]
+    fn 種類ごとの判定が可能() {
+        let data = SseResponse::Data("data".to_string());
+        let event = SseResponse::Event("event".to_string());
+        let id = SseResponse::Id("id".to_string());
+        let retry = SseResponse::Retry(1000);
+        let comment = SseResponse::Comment("comment".to_string());
+
+        assert!(data.is_data());
+        assert!(!data.is_event());
+        assert!(event.is_event());
+        assert!(!event.is_id());
+        assert!(id.is_id());
+        assert!(!id.is_retry());
+        assert!(retry.is_retry());
+        assert!(!retry.is_comment());
+        assert!(comment.is_comment());
+        assert!(!comment.is_data());
+    }
+    #[test]
+    fn 判定関数でフィルタリング可能() {
+        let responses = vec![
+            SseResponse::Event("message".to_string()),
+            SseResponse::Data("hello".to_string()),
+            SseResponse::Comment("ping".to_string()),
+            SseResponse::Data("world".to_string()),
+        ];
+
+        let data = responses
+            .into_iter()
+            .filter(SseResponse::is_data)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            data,
+            vec![
+                SseResponse::Data("hello".to_string()),
+                SseResponse::Data("world".to_string())
+            ]
+        );
+    }
+    #[test]
     fn sse以外のデータの場合はエラーを返す() {
         let sse_data = "hello world\n\n";
 
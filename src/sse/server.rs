@@ -1,30 +1,106 @@
 use std::{
-    io::{BufRead, Write},
-    net::TcpListener,
+    collections::VecDeque,
+    io::{BufRead, Read, Write},
+    net::{Shutdown, TcpListener},
+    sync::Mutex,
     thread::sleep,
     time::Duration,
 };
 
+const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+const BODY_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+impl LineEnding {
+    #[allow(dead_code)]
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+        }
+    }
+}
+
 pub struct SseServer {
     #[allow(dead_code)]
     addr: String,
     #[allow(dead_code)]
     responses: Vec<String>,
+    #[allow(dead_code)]
+    hang: bool,
+    #[allow(dead_code)]
+    retry_ms: Option<u32>,
+    #[allow(dead_code)]
+    line_ending: LineEnding,
+    #[allow(dead_code)]
+    replay_capacity: Option<usize>,
+    // recently emitted events as (id, data)
+    #[allow(dead_code)]
+    history: Mutex<VecDeque<(usize, String)>>,
+    #[allow(dead_code)]
+    raw_response: Option<String>,
+    #[allow(dead_code)]
+    max_body_size: usize,
 }
 impl SseServer {
     pub fn new(addr: &str) -> Self {
         Self {
             addr: addr.to_string(),
             responses: Vec::new(),
+            hang: false,
+            retry_ms: None,
+            line_ending: LineEnding::CrLf,
+            replay_capacity: None,
+            history: Mutex::new(VecDeque::new()),
+            raw_response: None,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
         }
     }
     #[allow(dead_code)]
     pub fn add_response(&mut self, response: &str) {
         self.responses.push(response.to_string());
     }
+    // when hang is true, the server sends only the status line and headers and never sends events
+    #[allow(dead_code)]
+    pub fn hang(&mut self, hang: bool) {
+        self.hang = hang;
+    }
+    // when retry_ms is set, the server sends `retry: N` once before the data events
+    #[allow(dead_code)]
+    pub fn retry_ms(&mut self, retry_ms: u32) {
+        self.retry_ms = Some(retry_ms);
+    }
+    #[allow(dead_code)]
+    pub fn line_ending(&mut self, line_ending: LineEnding) {
+        self.line_ending = line_ending;
+    }
+    // keep the last `capacity` events with ids, and replay the events after `Last-Event-ID`
+    #[allow(dead_code)]
+    pub fn replay_buffer(&mut self, capacity: usize) {
+        self.replay_capacity = Some(capacity);
+    }
+    // the raw string is sent as the whole response without any framing, like a broken status line
+    #[allow(dead_code)]
+    pub fn raw_response(&mut self, raw: &str) {
+        self.raw_response = Some(raw.to_string());
+    }
+    // a request with a larger body is answered with 413 instead of the stream
+    #[allow(dead_code)]
+    pub fn max_body_size(&mut self, max_body_size: usize) {
+        self.max_body_size = max_body_size;
+    }
     #[allow(dead_code)]
     pub fn start(&self) -> Result<(), std::io::Error> {
         let listener = TcpListener::bind(self.addr.as_str())?;
+        self.serve(listener)
+    }
+    #[allow(dead_code)]
+    pub fn serve(&self, listener: TcpListener) -> Result<(), std::io::Error> {
         for stream in listener.incoming() {
             let stream = stream?;
             self.handle_connection(stream)?;
@@ -34,33 +110,381 @@ impl SseServer {
     #[allow(dead_code)]
     pub fn handle_connection(&self, mut stream: std::net::TcpStream) -> Result<(), std::io::Error> {
         let mut reader = std::io::BufReader::new(&mut stream);
-        let mut line = String::new();
-        while let Ok(size) = reader.read_line(&mut line) {
-            if !size > 0 {
-                break;
-            }
+        let RequestHead {
+            last_event_id,
+            content_length,
+        } = Self::read_request_head(&mut reader)?;
+        if content_length > self.max_body_size {
+            reader.get_mut().write_all(
+                b"HTTP/1.1 413 Payload Too Large\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            )?;
+            // closing with the body unread resets the connection, and the client may lose the 413,
+            // so the rest is discarded until the client closes or stops sending
+            reader.get_ref().shutdown(Shutdown::Write)?;
+            reader
+                .get_ref()
+                .set_read_timeout(Some(BODY_DRAIN_TIMEOUT))?;
+            let _ = std::io::copy(&mut reader, &mut std::io::sink());
+            return Ok(());
         }
+        std::io::copy(
+            &mut reader.by_ref().take(content_length as u64),
+            &mut std::io::sink(),
+        )?;
         let mut writer = std::io::BufWriter::new(&mut stream);
-        writer.write_all(b"HTTP/1.1 200 OK\r\n")?;
-        writer.write_all(b"Content-Type: text/event-stream\r\n")?;
-        writer.write_all(b"\r\n")?;
+        if let Some(raw) = self.raw_response.as_ref() {
+            writer.write_all(raw.as_bytes())?;
+            return writer.flush();
+        }
+        let eol = self.line_ending.as_str();
+        writer.write_all(format!("HTTP/1.1 200 OK{}", eol).as_bytes())?;
+        writer.write_all(
+            format!("Content-Type: text/event-stream; charset=utf-8{}", eol).as_bytes(),
+        )?;
+        writer.write_all(eol.as_bytes())?;
         writer.flush()?;
-        for s in &self.responses {
-            writer.write_all(Self::make_sse_data(s).as_bytes())?;
+        if self.hang {
+            loop {
+                sleep(Duration::from_secs(60));
+            }
+        }
+        if let Some(retry_ms) = self.retry_ms {
+            writer.write_all(format!("retry: {}{}", retry_ms, eol).as_bytes())?;
+            writer.flush()?;
+        }
+        let Some(capacity) = self.replay_capacity else {
+            for s in &self.responses {
+                writer.write_all(self.make_sse_data(s).as_bytes())?;
+                sleep(Duration::from_millis(500));
+                writer.flush()?;
+            }
+            writer.write_all(eol.as_bytes())?;
+            writer.flush()?;
+            return Ok(());
+        };
+        let events: Vec<(usize, String)> = match last_event_id {
+            Some(last_event_id) => self
+                .history
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(id, _)| *id > last_event_id)
+                .cloned()
+                .collect(),
+            None => self
+                .responses
+                .iter()
+                .enumerate()
+                .map(|(i, s)| (i + 1, s.clone()))
+                .collect(),
+        };
+        for (id, s) in events {
+            writer.write_all(format!("id: {}{}", id, eol).as_bytes())?;
+            writer.write_all(self.make_sse_data(&s).as_bytes())?;
+            writer.write_all(eol.as_bytes())?;
             sleep(Duration::from_millis(500));
             writer.flush()?;
+            self.remember(capacity, id, s);
         }
-        writer.write_all(b"\r\n")?;
+        writer.write_all(eol.as_bytes())?;
         writer.flush()?;
         Ok(())
     }
+    // ids only grow, so an event sent again to another connection is already in the history
     #[allow(dead_code)]
-    fn make_sse_data(s: &str) -> String {
-        format!("data: {}\r\n", s)
+    fn remember(&self, capacity: usize, id: usize, data: String) {
+        let mut history = self.history.lock().unwrap();
+        if history.back().is_some_and(|(last_id, _)| *last_id >= id) {
+            return;
+        }
+        if history.len() == capacity {
+            history.pop_front();
+        }
+        history.push_back((id, data));
     }
+    // read the request headers until the blank line
+    #[allow(dead_code)]
+    fn read_request_head(reader: &mut impl BufRead) -> Result<RequestHead, std::io::Error> {
+        let mut head = RequestHead::default();
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 0 {
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some((key, value)) = line.split_once(':') {
+                let key = key.trim();
+                if key.eq_ignore_ascii_case("Last-Event-ID") {
+                    head.last_event_id = value.trim().parse().ok();
+                }
+                if key.eq_ignore_ascii_case("Content-Length") {
+                    head.content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+            line.clear();
+        }
+        Ok(head)
+    }
+    #[allow(dead_code)]
+    fn make_sse_data(&self, s: &str) -> String {
+        format!("data: {}{}", s, self.line_ending.as_str())
+    }
+}
+#[derive(Debug, Default)]
+struct RequestHead {
+    last_event_id: Option<usize>,
+    content_length: usize,
 }
 impl Default for SseServer {
     fn default() -> Self {
         Self::new("localhost:8081")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{io::ErrorKind, thread};
+
+    use crate::{
+        http::{request::RequestBuilder, url::Url},
+        sse::{
+            connector::{SseConnectionError, SseConnector, SseTcpConnectorBuilder},
+            response::SseResponse,
+            subscriber::{HandleProgress, SseSubscribeError, SseSubscriber},
+            testing::assert_stream_eq,
+        },
+    };
+
+    use super::*;
+
+    fn spawn(server: SseServer) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || server.serve(listener));
+        url.as_str().try_into().unwrap()
+    }
+
+    #[test]
+    fn charset付きのcontent_typeでもclientはstreamを読み取れる() {
+        let mut server = SseServer::default();
+        server.add_response("Hello");
+        let url = spawn(server);
+        let mut connector = SseTcpConnectorBuilder::new(&url).build().unwrap();
+        let req = RequestBuilder::new(&url).get().build();
+
+        let connection = connector.connect(&req).unwrap();
+        let result = connection.read().unwrap();
+
+        assert_eq!(
+            result.into_response(),
+            Some(SseResponse::Data("Hello".to_string()))
+        );
+        assert!(connection.header().is_event_stream());
+    }
+    #[test]
+    fn last_event_idを指定して再接続するとそれ以降のeventのみ再送される() {
+        fn subscribe(url: &Url, last_event_id: Option<&str>) -> Vec<(String, String)> {
+            let connector = SseTcpConnectorBuilder::new(url).build().unwrap();
+            let mut subscriber = SseSubscriber::new(connector);
+            let mut req = RequestBuilder::new(url).get();
+            if let Some(last_event_id) = last_event_id {
+                req = req.header("Last-Event-ID", last_event_id);
+            }
+            let mut id = String::new();
+            let mut events = Vec::new();
+            subscriber
+                .subscribe_mut_fn(&req.build(), |res| {
+                    match res {
+                        SseResponse::Id(i) => id = i,
+                        SseResponse::Data(d) => events.push((id.clone(), d)),
+                        _ => {}
+                    }
+                    HandleProgress::<()>::Progress
+                })
+                .unwrap();
+            events
+        }
+        let mut server = SseServer::default();
+        server.add_response("one");
+        server.add_response("two");
+        server.add_response("three");
+        server.replay_buffer(10);
+        let url = spawn(server);
+
+        let first = subscribe(&url, None);
+        // the events sent again to another client are kept once
+        let second = subscribe(&url, None);
+        let replayed = subscribe(&url, Some("2"));
+
+        assert_eq!(
+            first,
+            vec![
+                ("1".to_string(), "one".to_string()),
+                ("2".to_string(), "two".to_string()),
+                ("3".to_string(), "three".to_string()),
+            ]
+        );
+        assert_eq!(second, first);
+        assert_eq!(replayed, vec![("3".to_string(), "three".to_string())]);
+    }
+    #[test]
+    fn lfのみの改行でもclientはstreamを読み取れる() {
+        let mut server = SseServer::default();
+        server.add_response("Hello");
+        server.add_response("World");
+        server.line_ending(LineEnding::Lf);
+        let url = spawn(server);
+        let connector = SseTcpConnectorBuilder::new(&url).build().unwrap();
+        let mut subscriber = SseSubscriber::new(connector);
+        let req = RequestBuilder::new(&url).get().build();
+        let mut events = Vec::new();
+
+        subscriber
+            .subscribe_mut_fn(&req, |res| {
+                events.push(res);
+                HandleProgress::<()>::Progress
+            })
+            .unwrap();
+
+        assert_stream_eq(
+            &events,
+            &[SseResponse::data("Hello"), SseResponse::data("World")],
+        );
+    }
+    #[test]
+    fn bodyを含むpostのrequestにもserverはstreamを返す() {
+        let mut server = SseServer::default();
+        server.add_response("Hello");
+        server.max_body_size(1024);
+        let url = spawn(server);
+        let connector = SseTcpConnectorBuilder::new(&url).build().unwrap();
+        let mut subscriber = SseSubscriber::new(connector);
+        let req = RequestBuilder::new(&url)
+            .post()
+            .json(vec!["a body which is read before the response"; 8])
+            .build();
+        let mut events = Vec::new();
+
+        subscriber
+            .subscribe_mut_fn(&req, |res| {
+                events.push(res);
+                HandleProgress::<()>::Progress
+            })
+            .unwrap();
+
+        assert_stream_eq(&events, &[SseResponse::data("Hello")]);
+    }
+    #[test]
+    fn max_body_sizeより大きなbodyのrequestには413を返す() {
+        let mut server = SseServer::default();
+        server.add_response("Hello");
+        server.max_body_size(1024);
+        let url = spawn(server);
+        let connector = SseTcpConnectorBuilder::new(&url).build().unwrap();
+        let mut subscriber = SseSubscriber::new(connector);
+        // larger than the socket buffers, so that the client blocks unless the body is drained
+        let req = RequestBuilder::new(&url)
+            .post()
+            .json(vec!["a body which is larger than the cap"; 100_000])
+            .build();
+
+        let result = subscriber.subscribe_fn(&req, |_| HandleProgress::<()>::Progress);
+
+        let Err(SseSubscribeError::HttpError(res)) = result else {
+            panic!("expected HttpError");
+        };
+        assert_eq!(res.status_code(), 413);
+    }
+    #[test]
+    fn clientはserverが送信したretryの値を保持する() {
+        let mut server = SseServer::default();
+        server.add_response("Hello");
+        server.retry_ms(3000);
+        let url = spawn(server);
+        let connector = SseTcpConnectorBuilder::new(&url).build().unwrap();
+        let mut subscriber = SseSubscriber::new(connector);
+        let req = RequestBuilder::new(&url).get().build();
+
+        subscriber
+            .subscribe_fn(&req, |_| HandleProgress::<()>::Progress)
+            .unwrap();
+
+        assert_eq!(subscriber.last_retry_ms(), Some(3000));
+    }
+    #[test]
+    fn hangしたserverに対してclientはread_timeoutのエラーを返す() {
+        let mut server = SseServer::default();
+        server.add_response("Hello");
+        server.hang(true);
+        let url = spawn(server);
+        let connector = SseTcpConnectorBuilder::new(&url)
+            .read_timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let mut subscriber = SseSubscriber::new(connector);
+        let req = RequestBuilder::new(&url).get().build();
+
+        let result = subscriber.subscribe_fn(&req, |_| HandleProgress::<()>::Progress);
+
+        let Err(SseSubscribeError::ConnectionError(SseConnectionError::ConnectionError(e))) =
+            result
+        else {
+            panic!("expected read timeout error, but got {:?}", result);
+        };
+        assert!(matches!(
+            e.kind(),
+            ErrorKind::WouldBlock | ErrorKind::TimedOut
+        ));
+    }
+    #[test]
+    fn 不正なstatus_lineを受け取ったclientはparse_errorを返す() {
+        fn subscribe(raw: &str) -> Result<(), SseSubscribeError<()>> {
+            let mut server = SseServer::default();
+            server.raw_response(raw);
+            let url = spawn(server);
+            let connector = SseTcpConnectorBuilder::new(&url).build().unwrap();
+            let mut subscriber = SseSubscriber::new(connector);
+            let req = RequestBuilder::new(&url).get().build();
+            subscriber.subscribe_fn(&req, |_| HandleProgress::<()>::Progress)
+        }
+
+        let garbage = subscribe("garbage\r\n\r\ndata: Hello\r\n\r\n");
+        let invalid_code = subscribe("HTTP/1.1 abc OK\r\nContent-Type: text/event-stream\r\n\r\n");
+
+        assert!(matches!(
+            garbage,
+            Err(SseSubscribeError::ConnectionError(
+                SseConnectionError::InvalidStatusLine(_)
+            ))
+        ));
+        assert!(matches!(
+            invalid_code,
+            Err(SseSubscribeError::ConnectionError(
+                SseConnectionError::InvalidStatusLine(_)
+            ))
+        ));
+    }
+    #[test]
+    fn http_1_0のstatus_lineを返すserverからもeventを受信できる() {
+        let mut server = SseServer::default();
+        server.raw_response(
+            "HTTP/1.0 200 OK\r\nContent-Type: text/event-stream\r\n\r\ndata: Hello\r\n\r\n",
+        );
+        let url = spawn(server);
+        let connector = SseTcpConnectorBuilder::new(&url).build().unwrap();
+        let mut subscriber = SseSubscriber::new(connector);
+        let req = RequestBuilder::new(&url).get().build();
+        let received = std::cell::RefCell::new(Vec::new());
+
+        subscriber
+            .subscribe_fn(&req, |res| {
+                received.borrow_mut().push(res);
+                HandleProgress::<()>::Progress
+            })
+            .unwrap();
+
+        assert_eq!(
+            received.into_inner(),
+            vec![SseResponse::Data("Hello".to_string())]
+        );
+    }
+}
@@ -1,11 +1,18 @@
-use std::fmt::Debug;
+use std::{
+    borrow::Cow,
+    convert::Infallible,
+    fmt::Debug,
+    io::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use thiserror::Error;
 
-use crate::http::{request::Request, response::HttpResponse};
+use crate::http::{header::HttpHeader, request::Request, response::HttpResponse, url::UrlError};
 
 use super::{
-    connector::{ConnectedSseResponse, SseConnectionError, SseConnector},
+    connector::{ConnectedSseResponse, SseConnectionError, SseConnector, StreamRead},
     response::SseResponse,
 };
 pub type Result<T, E> = std::result::Result<T, SseSubscribeError<E>>;
@@ -14,82 +21,276 @@ pub type Result<T, E> = std::result::Result<T, SseSubscribeError<E>>;
 pub enum HandleProgress<E> {
     Done,
     Progress,
+    // discard the rest of the current event and resume from the next one
+    Skip,
+    // pass the transformed response to the next handler of a chain
+    ContinueWith(SseResponse),
     Err(E),
 }
+impl<E> HandleProgress<E> {
+    pub fn is_done(&self) -> bool {
+        matches!(self, Self::Done)
+    }
+    pub fn is_progress(&self) -> bool {
+        matches!(self, Self::Progress)
+    }
+    pub fn is_skip(&self) -> bool {
+        matches!(self, Self::Skip)
+    }
+    pub fn is_continue_with(&self) -> bool {
+        matches!(self, Self::ContinueWith(_))
+    }
+    pub fn is_err(&self) -> bool {
+        matches!(self, Self::Err(_))
+    }
+    pub fn map_err<F>(self, f: impl FnOnce(E) -> F) -> HandleProgress<F> {
+        match self {
+            Self::Done => HandleProgress::Done,
+            Self::Progress => HandleProgress::Progress,
+            Self::Skip => HandleProgress::Skip,
+            Self::ContinueWith(res) => HandleProgress::ContinueWith(res),
+            Self::Err(e) => HandleProgress::Err(f(e)),
+        }
+    }
+}
+
+// stream level metadata passed to handlers
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamContext {
+    event_index: usize,
+    last_id: Option<String>,
+    bytes_read: usize,
+}
+impl StreamContext {
+    // index of the current event in the stream, starting from 0
+    pub fn event_index(&self) -> usize {
+        self.event_index
+    }
+    pub fn last_id(&self) -> Option<&str> {
+        self.last_id.as_deref()
+    }
+    pub fn bytes_read(&self) -> usize {
+        self.bytes_read
+    }
+    pub(crate) fn update(&mut self, res: &SseResponse, bytes_read: usize) {
+        if let SseResponse::Id(id) = res {
+            self.last_id = Some(id.to_string());
+        }
+        self.bytes_read = bytes_read;
+    }
+    pub(crate) fn advance(&mut self) {
+        self.event_index += 1;
+    }
+}
 
 pub trait SseHandler<T, E> {
     fn handle(&self, res: SseResponse) -> HandleProgress<E>;
+    fn handle_with_ctx(&self, res: SseResponse, _ctx: &StreamContext) -> HandleProgress<E> {
+        self.handle(res)
+    }
     fn result(&self) -> std::result::Result<T, E>;
 }
 pub trait SseMutHandler<T, E> {
     fn handle(&mut self, res: SseResponse) -> HandleProgress<E>;
+    fn handle_with_ctx(&mut self, res: SseResponse, _ctx: &StreamContext) -> HandleProgress<E> {
+        self.handle(res)
+    }
     fn result(&self) -> std::result::Result<T, E>;
 }
 
-macro_rules! impl_subscribe_handler {
-    ($self:ident,$req:ident,$handler:ident) => {
-        let connection = $self
-            .connector
-            .connect($req)
-            .map_err(SseSubscribeError::from)?;
-        loop {
-            let res = connection.read().map_err(SseSubscribeError::from)?;
-            match res {
-                ConnectedSseResponse::Progress(sse_response) => {
-                    match $handler.handle(sse_response) {
-                        HandleProgress::Progress => {}
-                        HandleProgress::Done => {
-                            return $handler
-                                .result()
-                                .map_err(|e| SseSubscribeError::HandlerError(e));
-                        }
-                        HandleProgress::Err(_) => {
-                            todo!()
-                        }
-                    };
-                }
-                ConnectedSseResponse::Done => {
-                    return $handler
-                        .result()
-                        .map_err(|e| SseSubscribeError::HandlerError(e));
-                }
-            }
-        }
-    };
-}
-macro_rules! impl_subscribe_fn {
-    ($self:ident,$req:ident,$f:ident) => {
-        let conn = $self
-            .connector
-            .connect($req)
-            .map_err(SseSubscribeError::from)?;
-        loop {
-            let res = conn.read().map_err(SseSubscribeError::from)?;
-            match res {
-                ConnectedSseResponse::Progress(sse_response) => {
-                    match $f(sse_response) {
-                        HandleProgress::Progress => {}
-                        HandleProgress::Done => return Ok(()),
-                        HandleProgress::Err(e) => {
-                            return Err(SseSubscribeError::HandlerError(e));
-                        }
-                    };
-                }
-                ConnectedSseResponse::Done => {
-                    return Ok(());
-                }
-            }
-        }
-    };
-}
-
 #[derive(Debug)]
 pub struct SseSubscriber<C: SseConnector> {
     connector: C,
+    last_retry_ms: Option<u32>,
+    total_deadline: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    on_heartbeat: Option<HeartbeatCallback>,
+    on_open: Option<OpenCallback>,
+    on_progress: Option<ProgressCallback>,
+    reconnect: Option<ReconnectPolicy>,
+    // error statuses which are reconnected like io errors, like 503 from a gateway
+    retry_on_status: Vec<u32>,
+    on_reconnect: Option<ReconnectCallback>,
+    done_when: Option<DoneWhen>,
+    // overrides the timeouts of the connector for the next subscribe only
+    request_timeouts: Option<RequestTimeouts>,
+    // builds the request of each reconnect of the next subscribe again
+    rebuild_request: Option<RebuildRequest>,
+}
+type DonePredicate = dyn FnMut(&SseResponse) -> bool + Send;
+// the stream is terminated when the predicate returns true, for apis with their own end marker
+#[derive(Clone)]
+pub(crate) struct DoneWhen(Arc<Mutex<DonePredicate>>);
+impl DoneWhen {
+    pub(crate) fn new(f: impl FnMut(&SseResponse) -> bool + Send + 'static) -> Self {
+        Self(Arc::new(Mutex::new(f)))
+    }
+}
+impl Debug for DoneWhen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("DoneWhen")
+    }
+}
+// reconnect when the connection is failed, up to max_attempts times.
+// the retry value sent by the server takes precedence over the delay
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    pub max_attempts: u32,
+    pub delay: Duration,
+}
+// timeouts of a single request, like a slow generation on a client used for quick polls too
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestTimeouts {
+    pub connect: Duration,
+    pub read: Duration,
+}
+type OnReconnect = dyn FnMut(u32, &SseConnectionError, Duration) + Send;
+// shared, so that builders holding the callback can be cloned
+#[derive(Clone)]
+pub(crate) struct ReconnectCallback(Arc<Mutex<OnReconnect>>);
+impl ReconnectCallback {
+    pub(crate) fn new(f: impl FnMut(u32, &SseConnectionError, Duration) + Send + 'static) -> Self {
+        Self(Arc::new(Mutex::new(f)))
+    }
+    pub(crate) fn call(&self, attempt: u32, err: &SseConnectionError, delay: Duration) {
+        (self.0.lock().unwrap())(attempt, err, delay)
+    }
+}
+impl Debug for ReconnectCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ReconnectCallback")
+    }
+}
+struct HeartbeatCallback(Box<dyn FnMut() + Send>);
+impl Debug for HeartbeatCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("HeartbeatCallback")
+    }
+}
+type OnOpen = dyn FnMut(u32, &HttpHeader) + Send;
+struct OpenCallback(Box<OnOpen>);
+impl Debug for OpenCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("OpenCallback")
+    }
+}
+type Rebuild = dyn FnMut() -> std::result::Result<Request, SseConnectionError> + Send;
+struct RebuildRequest(Box<Rebuild>);
+impl Debug for RebuildRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RebuildRequest")
+    }
+}
+struct ProgressCallback(Box<dyn FnMut(usize, usize) + Send>);
+impl Debug for ProgressCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ProgressCallback")
+    }
 }
 impl<C: SseConnector> SseSubscriber<C> {
     pub fn new(connector: C) -> Self {
-        Self { connector }
+        Self {
+            connector,
+            last_retry_ms: None,
+            total_deadline: None,
+            idle_timeout: None,
+            on_heartbeat: None,
+            on_open: None,
+            on_progress: None,
+            reconnect: None,
+            retry_on_status: Vec::new(),
+            on_reconnect: None,
+            done_when: None,
+            request_timeouts: None,
+            rebuild_request: None,
+        }
+    }
+    // the read timeout of the connection is shortened to the remaining time of the deadline
+    pub fn total_deadline(mut self, deadline: Option<Duration>) -> Self {
+        self.total_deadline = deadline;
+        self
+    }
+    // fail when no event is dispatched within the timeout, even if comments or fields arrive
+    pub fn idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+    // called on a blank line without any field, like a stream of keep-alive comments,
+    // so that the caller can reset its own idle timer
+    pub fn on_heartbeat(&mut self, f: impl FnMut() + Send + 'static) {
+        self.on_heartbeat = Some(HeartbeatCallback(Box::new(f)));
+    }
+    // called with the status code and headers when the headers of a response are read
+    pub fn on_open(&mut self, f: impl FnMut(u32, &HttpHeader) + Send + 'static) {
+        self.on_open = Some(OpenCallback(Box::new(f)));
+    }
+    // called with the number of events and bytes read so far each time a blank line
+    // dispatches an event, which is lighter than the handler for a progress bar
+    pub fn on_progress(&mut self, f: impl FnMut(usize, usize) + Send + 'static) {
+        self.on_progress = Some(ProgressCallback(Box::new(f)));
+    }
+    pub fn reconnect(mut self, policy: Option<ReconnectPolicy>) -> Self {
+        self.reconnect = policy;
+        self
+    }
+    // the statuses are reconnected under the reconnect policy, and the other statuses fail fast
+    pub fn retry_on_status(mut self, statuses: &[u32]) -> Self {
+        self.retry_on_status = statuses.to_vec();
+        self
+    }
+    // called with the attempt number, the error and the delay before each reconnection
+    pub fn on_reconnect(
+        &mut self,
+        f: impl FnMut(u32, &SseConnectionError, Duration) + Send + 'static,
+    ) {
+        self.on_reconnect = Some(ReconnectCallback::new(f));
+    }
+    // the response matched by the predicate is not passed to handlers
+    pub fn done_when(&mut self, f: impl FnMut(&SseResponse) -> bool + Send + 'static) {
+        self.done_when = Some(DoneWhen::new(f));
+    }
+    pub(crate) fn has_done_when(&self) -> bool {
+        self.done_when.is_some()
+    }
+    pub(crate) fn set_done_when(&mut self, done_when: Option<DoneWhen>) {
+        self.done_when = done_when;
+    }
+    pub(crate) fn set_reconnect_callback(&mut self, callback: Option<ReconnectCallback>) {
+        self.on_reconnect = callback;
+    }
+    // applied to the next subscribe, and the timeouts of the connector are restored after it
+    pub fn request_timeouts(&mut self, timeouts: RequestTimeouts) {
+        self.request_timeouts = Some(timeouts);
+    }
+    // the request of the next subscribe is built again for each reconnect,
+    // like one with a token which may have expired while the previous connection was streaming
+    pub(crate) fn rebuild_request_on_reconnect(
+        &mut self,
+        f: impl FnMut() -> std::result::Result<Request, SseConnectionError> + Send + 'static,
+    ) {
+        self.rebuild_request = Some(RebuildRequest(Box::new(f)));
+    }
+    // the timeouts of the next request, if they are overridden.
+    // the connectors have connected with the connect timeout of the build,
+    // so the request opens its connection again to apply its own one
+    fn take_request_timeouts(&mut self) -> Option<RequestTimeouts> {
+        let timeouts = self.request_timeouts.take();
+        self.connector
+            .set_connect_timeout(timeouts.map(|timeouts| timeouts.connect));
+        timeouts
+    }
+    pub fn is_healthy(&mut self) -> bool {
+        self.connector.is_healthy()
+    }
+    pub fn connector(&self) -> &C {
+        &self.connector
+    }
+    pub fn into_connector(self) -> C {
+        self.connector
+    }
+    // the latest retry value sent by the server
+    pub fn last_retry_ms(&self) -> Option<u32> {
+        self.last_retry_ms
     }
 
     pub fn subscribe_fn<E, F: Fn(SseResponse) -> HandleProgress<E>>(
@@ -97,21 +298,56 @@ impl<C: SseConnector> SseSubscriber<C> {
         req: &Request,
         f: F,
     ) -> Result<(), E> {
-        impl_subscribe_fn!(self, req, f);
+        self.subscribe_with_ctx(req, |res, _ctx| f(res))
     }
     pub fn subscribe_mut_fn<E, F: FnMut(SseResponse) -> HandleProgress<E>>(
         &mut self,
         req: &Request,
         mut f: F,
     ) -> Result<(), E> {
-        impl_subscribe_fn!(self, req, f);
+        self.subscribe_with_ctx(req, |res, _ctx| f(res))
+    }
+    pub fn pipe_to<W: Write>(
+        &mut self,
+        req: &Request,
+        sink: W,
+    ) -> std::result::Result<(), SseConnectionError> {
+        // without reconnects the request is never built again
+        self.rebuild_request = None;
+        let timeouts = self.take_request_timeouts();
+        if timeouts.is_some() {
+            self.connector.reconnect()?;
+        }
+        let connection = self.connector.connect(req)?;
+        if let Some(timeouts) = timeouts {
+            connection.read_timeout_for(timeouts.read)?;
+        }
+        connection.pipe_to(sink)
+    }
+    pub fn data_to_writer<W: Write>(
+        &mut self,
+        req: &Request,
+        sink: W,
+    ) -> std::result::Result<(), SseConnectionError> {
+        // without reconnects the request is never built again
+        self.rebuild_request = None;
+        let timeouts = self.take_request_timeouts();
+        if timeouts.is_some() {
+            self.connector.reconnect()?;
+        }
+        let connection = self.connector.connect(req)?;
+        if let Some(timeouts) = timeouts {
+            connection.read_timeout_for(timeouts.read)?;
+        }
+        connection.data_to_writer(sink)
     }
     pub fn subscribe<T, E>(
         &mut self,
         req: &Request,
         handler: &impl SseHandler<T, E>,
     ) -> Result<T, E> {
-        impl_subscribe_handler!(self, req, handler);
+        self.subscribe_with_ctx(req, |res, ctx| handler.handle_with_ctx(res, ctx))?;
+        handler.result().map_err(SseSubscribeError::HandlerError)
     }
 
     pub fn subscribe_mut<T, E>(
@@ -119,10 +355,290 @@ impl<C: SseConnector> SseSubscriber<C> {
         req: &Request,
         handler: &mut impl SseMutHandler<T, E>,
     ) -> Result<T, E> {
-        impl_subscribe_handler!(self, req, handler);
+        self.subscribe_with_ctx(req, |res, ctx| handler.handle_with_ctx(res, ctx))?;
+        handler.result().map_err(SseSubscribeError::HandlerError)
+    }
+
+    fn subscribe_with_ctx<E>(
+        &mut self,
+        req: &Request,
+        mut f: impl FnMut(SseResponse, &StreamContext) -> HandleProgress<E>,
+    ) -> Result<(), E> {
+        let mut ctx = StreamContext::default();
+        let started = Instant::now();
+        let timeouts = self.take_request_timeouts();
+        let read_timeout = timeouts.map(|timeouts| timeouts.read);
+        let mut rebuild_request = self.rebuild_request.take();
+        let mut req = Cow::Borrowed(req);
+        let mut attempt = 0;
+        #[cfg(feature = "tracing")]
+        let mut total_bytes = 0;
+        let result = loop {
+            // the dropped connection is replaced by a new one before retrying
+            let reconnected = match attempt {
+                0 if timeouts.is_none() => Ok(()),
+                _ => self.connector.reconnect(),
+            };
+            let reconnected = match (attempt, rebuild_request.as_mut()) {
+                (1.., Some(RebuildRequest(rebuild))) => reconnected
+                    .and_then(|()| rebuild())
+                    .map(|rebuilt| req = Cow::Owned(rebuilt)),
+                _ => reconnected,
+            };
+            let result = match reconnected {
+                Ok(()) => self.stream(&req, &mut f, &mut ctx, started, read_timeout),
+                Err(e) => Err(e.into()),
+            };
+            // bytes are counted per connection, so they are summed over reconnects
+            #[cfg(feature = "tracing")]
+            {
+                total_bytes += std::mem::take(&mut ctx.bytes_read);
+            }
+            let err = match result {
+                Err(SseSubscribeError::ConnectionError(err)) => err,
+                Err(SseSubscribeError::HttpError(res)) => SseConnectionError::HttpError(res),
+                result => break result,
+            };
+            let Some(delay) = reconnect_delay(
+                self.reconnect,
+                &self.retry_on_status,
+                self.last_retry_ms,
+                attempt,
+                &err,
+            ) else {
+                break Err(err.into());
+            };
+            if self
+                .total_deadline
+                .is_some_and(|deadline| started.elapsed() + delay >= deadline)
+            {
+                break Err(SseSubscribeError::DeadlineExceeded);
+            }
+            attempt += 1;
+            #[cfg(feature = "tracing")]
+            tracing::warn!(attempt, reason = %err, ?delay, "reconnecting sse stream");
+            if let Some(on_reconnect) = self.on_reconnect.as_ref() {
+                on_reconnect.call(attempt, &err, delay);
+            }
+            std::thread::sleep(delay);
+        };
+        #[cfg(feature = "tracing")]
+        tracing::info!(
+            events = ctx.event_index(),
+            reconnects = attempt,
+            total_bytes,
+            duration = ?started.elapsed(),
+            outcome = outcome_of(&result),
+            "sse stream ended"
+        );
+        result
+    }
+    fn stream<E>(
+        &mut self,
+        req: &Request,
+        f: &mut impl FnMut(SseResponse, &StreamContext) -> HandleProgress<E>,
+        ctx: &mut StreamContext,
+        started: Instant,
+        read_timeout: Option<Duration>,
+    ) -> Result<(), E> {
+        let connection = self
+            .connector
+            .connect(req)
+            .map_err(SseSubscribeError::from)?;
+        // the read timeout of the request overrides the one of the connection
+        let configured_read_timeout = read_timeout.or(connection.read_timeout());
+        let deadline = self.total_deadline;
+        let is_exceeded = || deadline.is_some_and(|deadline| started.elapsed() >= deadline);
+        let mut state = StreamState::new(ctx, &mut self.last_retry_ms, self.done_when.as_ref());
+        state.on_progress = self.on_progress.as_mut();
+        loop {
+            let idle_deadline = state.idle_deadline(self.idle_timeout);
+            connection.set_idle_deadline(idle_deadline);
+            let total_remaining = deadline
+                .map(|deadline| {
+                    deadline
+                        .checked_sub(started.elapsed())
+                        .filter(|remaining| !remaining.is_zero())
+                        .ok_or(SseSubscribeError::DeadlineExceeded)
+                })
+                .transpose()?;
+            let idle_remaining = idle_deadline
+                .map(|idle_deadline| {
+                    idle_deadline
+                        .checked_duration_since(Instant::now())
+                        .filter(|remaining| !remaining.is_zero())
+                        .ok_or(SseSubscribeError::IdleTimeout)
+                })
+                .transpose()?;
+            // the read is bounded by the shortest one.
+            // without deadlines the configured read timeout of the socket is left as it is
+            if let Some(remaining) = total_remaining
+                .into_iter()
+                .chain(idle_remaining)
+                .chain(read_timeout)
+                .min()
+                .map(|remaining| remaining.min(configured_read_timeout.unwrap_or(remaining)))
+            {
+                connection
+                    .read_timeout_for(remaining)
+                    .map_err(SseSubscribeError::from)?;
+            }
+            let opened = connection.opened();
+            let is_idle = || idle_deadline.is_some_and(|deadline| Instant::now() >= deadline);
+            let on_heartbeat = &mut self.on_heartbeat;
+            let read = match connection.read_until_dispatch(|| {
+                if let Some(HeartbeatCallback(on_heartbeat)) = on_heartbeat.as_mut() {
+                    on_heartbeat();
+                }
+            }) {
+                Err(_) if is_exceeded() => return Err(SseSubscribeError::DeadlineExceeded),
+                Err(_) if is_idle() => return Err(SseSubscribeError::IdleTimeout),
+                read => read.map_err(SseSubscribeError::from)?,
+            };
+            if let Some(OpenCallback(on_open)) = self.on_open.as_mut() {
+                if let (true, Some(status_code)) =
+                    (opened < connection.opened(), connection.status_code())
+                {
+                    on_open(status_code, connection.header());
+                }
+            }
+            if let Some(result) = state.handle(read, connection.bytes_read(), f) {
+                return result;
+            }
+        }
+    }
+}
+
+// only io errors and the configured statuses are recovered by reconnecting
+pub(crate) fn reconnect_delay(
+    policy: Option<ReconnectPolicy>,
+    retry_on_status: &[u32],
+    last_retry_ms: Option<u32>,
+    attempt: u32,
+    err: &SseConnectionError,
+) -> Option<Duration> {
+    let policy = policy.filter(|policy| attempt < policy.max_attempts)?;
+    let retryable = match err {
+        SseConnectionError::HttpError(res) => retry_on_status.contains(&res.status_code()),
+        _ => err.is_retryable(),
+    };
+    if !retryable {
+        return None;
+    }
+    Some(
+        last_retry_ms
+            .map(|retry| Duration::from_millis(retry as u64))
+            .unwrap_or(policy.delay),
+    )
+}
+// what the sync and async streams do with each read, so that only the io differs between them
+pub(crate) struct StreamState<'a> {
+    ctx: &'a mut StreamContext,
+    last_retry_ms: &'a mut Option<u32>,
+    done_when: Option<&'a DoneWhen>,
+    on_progress: Option<&'a mut ProgressCallback>,
+    // the rest of an event skipped by the handler is read, but not passed to it
+    skipping: bool,
+    last_event: Instant,
+}
+impl<'a> StreamState<'a> {
+    pub(crate) fn new(
+        ctx: &'a mut StreamContext,
+        last_retry_ms: &'a mut Option<u32>,
+        done_when: Option<&'a DoneWhen>,
+    ) -> Self {
+        Self {
+            ctx,
+            last_retry_ms,
+            done_when,
+            on_progress: None,
+            skipping: false,
+            last_event: Instant::now(),
+        }
+    }
+    pub(crate) fn idle_deadline(&self, idle_timeout: Option<Duration>) -> Option<Instant> {
+        idle_timeout.map(|timeout| self.last_event + timeout)
+    }
+    // None continues the stream with the next read
+    pub(crate) fn handle<E>(
+        &mut self,
+        read: StreamRead,
+        bytes_read: usize,
+        f: &mut impl FnMut(SseResponse, &StreamContext) -> HandleProgress<E>,
+    ) -> Option<Result<(), E>> {
+        let res = match read {
+            StreamRead::Response(res) => res,
+            StreamRead::Dispatched => {
+                self.skipping = false;
+                self.last_event = Instant::now();
+                self.ctx.advance();
+                if let Some(ProgressCallback(on_progress)) = self.on_progress.as_mut() {
+                    on_progress(self.ctx.event_index(), bytes_read);
+                }
+                return None;
+            }
+        };
+        let ConnectedSseResponse::Progress {
+            response: sse_response,
+            ..
+        } = res
+        else {
+            return Some(Ok(()));
+        };
+        if self.skipping {
+            return None;
+        }
+        if let SseResponse::Retry(retry) = &sse_response {
+            *self.last_retry_ms = Some(*retry);
+        }
+        if let Some(DoneWhen(done_when)) = self.done_when {
+            if (done_when.lock().unwrap())(&sse_response) {
+                return Some(Ok(()));
+            }
+        }
+        self.ctx.update(&sse_response, bytes_read);
+        match f(sse_response, self.ctx) {
+            HandleProgress::Progress | HandleProgress::ContinueWith(_) => None,
+            HandleProgress::Skip => {
+                self.skipping = true;
+                None
+            }
+            HandleProgress::Done => Some(Ok(())),
+            HandleProgress::Err(e) => Some(Err(SseSubscribeError::HandlerError(e))),
+        }
+    }
+}
+
+// one word for the summary log of a stream
+#[cfg(feature = "tracing")]
+fn outcome_of<E>(result: &Result<(), E>) -> &'static str {
+    match result {
+        Ok(()) => "completed",
+        Err(SseSubscribeError::InvalidUrl(_)) => "invalid_url",
+        Err(SseSubscribeError::ConnectionError(_)) => "connection_error",
+        Err(SseSubscribeError::HttpError(_)) => "http_error",
+        Err(SseSubscribeError::HandlerError(_)) => "handler_error",
+        Err(SseSubscribeError::DeadlineExceeded) => "deadline_exceeded",
+        Err(SseSubscribeError::IdleTimeout) => "idle_timeout",
+        Err(SseSubscribeError::Cancelled) => "cancelled",
     }
 }
 
+// the result of the handler is kept even when the stream is terminated by an error
+#[derive(Debug)]
+pub struct StreamOutcome<T, E> {
+    pub result: Option<T>,
+    pub reason: TerminationReason<E>,
+}
+#[derive(Debug)]
+pub enum TerminationReason<E> {
+    // the server closed the stream, or the end marker of done_when is received
+    Completed,
+    HandlerStopped,
+    Error(SseSubscribeError<E>),
+    Timeout,
+}
+
 #[derive(Debug, Error)]
 pub enum SseSubscribeError<E> {
     #[error("SseSubscribeError invalid url: {0}")]
@@ -133,29 +649,98 @@ pub enum SseSubscribeError<E> {
     HttpError(HttpResponse),
     #[error("SseSubscribeError handler error: {0:?}")]
     HandlerError(E),
+    #[error("SseSubscribeError deadline exceeded")]
+    DeadlineExceeded,
+    #[error("SseSubscribeError idle timeout")]
+    IdleTimeout,
+    // the partial result is kept by the handler
+    #[error("SseSubscribeError cancelled")]
+    Cancelled,
+}
+impl<E> From<UrlError> for SseSubscribeError<E> {
+    fn from(err: UrlError) -> Self {
+        Self::InvalidUrl(err.to_string())
+    }
 }
 impl<E> From<SseConnectionError> for SseSubscribeError<E> {
     fn from(err: SseConnectionError) -> Self {
         match err {
             SseConnectionError::HttpError(err) => Self::HttpError(err),
+            SseConnectionError::IdleTimeout => Self::IdleTimeout,
+            SseConnectionError::DeadlineExceeded => Self::DeadlineExceeded,
             _ => Self::ConnectionError(err),
         }
     }
 }
+// for the apis without handler errors, which report only the errors of the stream
+impl From<SseSubscribeError<Infallible>> for SseConnectionError {
+    fn from(err: SseSubscribeError<Infallible>) -> Self {
+        match err {
+            SseSubscribeError::InvalidUrl(url) => Self::InvalidUrl(url),
+            SseSubscribeError::ConnectionError(err) => err,
+            SseSubscribeError::HttpError(res) => Self::HttpError(res),
+            SseSubscribeError::HandlerError(never) => match never {},
+            SseSubscribeError::DeadlineExceeded => Self::DeadlineExceeded,
+            SseSubscribeError::IdleTimeout => Self::IdleTimeout,
+            SseSubscribeError::Cancelled => Self::ConnectionError(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                "cancelled",
+            )),
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
 
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Arc,
+        },
+        thread,
+    };
+
     use crate::{
-        http::request::RequestBuilder,
+        http::{request::RequestBuilder, url::Url},
         sse::{
-            connector::fakes::FakeSseConnector,
+            connector::{
+                fakes::{FakeSseConnector, FakeTcpConnection},
+                ConnectionPhase, SseConnection, SseTcpConnectorBuilder,
+            },
             subscriber::fakes::{MockHandler, MockMutHandler},
         },
     };
 
     use super::*;
     #[test]
+    fn handle_progressの種類を判定できる() {
+        let done = HandleProgress::<()>::Done;
+        let progress = HandleProgress::<()>::Progress;
+        let err = HandleProgress::Err(());
+
+        assert!(done.is_done());
+        assert!(!done.is_progress());
+        assert!(progress.is_progress());
+        assert!(!progress.is_err());
+        assert!(err.is_err());
+        assert!(!err.is_done());
+    }
+    #[test]
+    fn handle_progressのエラーを変換できる() {
+        let err = HandleProgress::Err(404);
+
+        let sut = err.map_err(|code| format!("status {}", code));
+
+        assert_eq!(sut, HandleProgress::Err("status 404".to_string()));
+        assert_eq!(
+            HandleProgress::<i32>::Done.map_err(|code| code.to_string()),
+            HandleProgress::Done
+        );
+    }
+    #[test]
     fn fnをsubscribeの処理に使うことが可能() {
         let mut connector = FakeSseConnector::new();
         connector.set_response("HTTP/1.1 200 OK\r\n");
@@ -205,6 +790,41 @@ mod tests {
         assert_eq!(store, vec!["Hello", "World!"]);
     }
     #[test]
+    fn fnとfn_mutが返したerrorはhandler_errorとして返る() {
+        fn connector() -> FakeSseConnector {
+            let mut connector = FakeSseConnector::new();
+            connector.set_response("HTTP/1.1 200 OK\r\n");
+            connector.set_response("Content-Type: text/event-stream\r\n");
+            connector.set_response("\r\n");
+            connector.set_response("data: Hello\r\n");
+            connector.set_response("data: World!\r\n");
+            connector
+        }
+        let request = RequestBuilder::new(&"https://www.fake".try_into().unwrap())
+            .get()
+            .build();
+
+        let mut sut = SseSubscriber::new(connector());
+        let result = sut.subscribe_fn(&request, |res| match res {
+            SseResponse::Data(s) => HandleProgress::Err(s),
+            _ => HandleProgress::Progress,
+        });
+        assert!(matches!(result, Err(SseSubscribeError::HandlerError(s)) if s == "Hello"));
+
+        let mut sut = SseSubscriber::new(connector());
+        let mut store = Vec::new();
+        let result = sut.subscribe_mut_fn(&request, |res| match res {
+            SseResponse::Data(s) if s == "World!" => HandleProgress::Err(s),
+            SseResponse::Data(s) => {
+                store.push(s);
+                HandleProgress::Progress
+            }
+            _ => HandleProgress::Progress,
+        });
+        assert!(matches!(result, Err(SseSubscribeError::HandlerError(s)) if s == "World!"));
+        assert_eq!(store, vec!["Hello"]);
+    }
+    #[test]
     fn handlerの最終結果を取得可能() {
         let mut connector = FakeSseConnector::new();
         connector.set_response("HTTP/1.1 200 OK\r\n");
@@ -315,6 +935,595 @@ mod tests {
         ])
     }
     #[test]
+    fn handlerはstreamのcontextを受け取ることが可能() {
+        let mut connector = FakeSseConnector::new();
+        connector.set_response("HTTP/1.1 200 OK\r\n");
+        connector.set_response("Content-Type: text/event-stream\r\n");
+        connector.set_response("\r\n\r\n");
+        connector.set_response("id: 1\r\n");
+        connector.set_response("data: Hello\r\n");
+        connector.set_response("data: World!\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: Bye\r\n");
+        connector.set_response("\r\n");
+
+        struct ContextHandler {
+            contexts: Vec<StreamContext>,
+        }
+        impl SseMutHandler<(), ()> for ContextHandler {
+            fn handle(&mut self, _res: SseResponse) -> HandleProgress<()> {
+                unreachable!("handle_with_ctx is overridden")
+            }
+            fn handle_with_ctx(
+                &mut self,
+                _res: SseResponse,
+                ctx: &StreamContext,
+            ) -> HandleProgress<()> {
+                self.contexts.push(ctx.clone());
+                HandleProgress::Progress
+            }
+            fn result(&self) -> std::result::Result<(), ()> {
+                Ok(())
+            }
+        }
+        let mut handler = ContextHandler { contexts: vec![] };
+        let mut sut = SseSubscriber::new(connector);
+        let request = RequestBuilder::new(&"https://www.fake".try_into().unwrap())
+            .get()
+            .build();
+
+        sut.subscribe_mut(&request, &mut handler).unwrap();
+
+        let indexes = handler
+            .contexts
+            .iter()
+            .map(|ctx| ctx.event_index())
+            .collect::<Vec<_>>();
+        assert_eq!(indexes, vec![0, 0, 0, 1]);
+        assert_eq!(handler.contexts[2].last_id(), Some("1"));
+        assert!(handler.contexts[1].bytes_read() < handler.contexts[2].bytes_read());
+    }
+    #[test]
+    fn nullを含むidはlast_idを更新しない() {
+        let mut connector = FakeSseConnector::new();
+        connector.set_response("HTTP/1.1 200 OK\r\n");
+        connector.set_response("Content-Type: text/event-stream\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("id: 1\r\n");
+        connector.set_response("data: Hello\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("id: 2\0\r\n");
+        connector.set_response("data: World!\r\n");
+        let mut sut = SseSubscriber::new(connector);
+        let request = RequestBuilder::new(&"https://www.fake".try_into().unwrap())
+            .get()
+            .build();
+
+        let mut received = Vec::new();
+        sut.subscribe_with_ctx(&request, |res, ctx| {
+            received.push((res, ctx.last_id().map(str::to_string)));
+            HandleProgress::<()>::Progress
+        })
+        .unwrap();
+
+        assert_eq!(
+            received.last(),
+            Some(&(SseResponse::data("World!"), Some("1".to_string())))
+        );
+        assert!(!received
+            .iter()
+            .any(|(res, _)| matches!(res, SseResponse::Id(id) if id.contains('\0'))));
+    }
+    #[test]
+    fn url_errorとconnection_errorは疑問符演算子でsubscribe_errorに変換できる() {
+        fn parse_url(url: &str) -> Result<Url, ()> {
+            Ok(Url::from_str(url)?)
+        }
+        fn connect(err: SseConnectionError) -> Result<(), ()> {
+            Err(err)?
+        }
+
+        assert!(matches!(
+            parse_url("ftp://localhost"),
+            Err(SseSubscribeError::InvalidUrl(_))
+        ));
+        assert!(matches!(
+            connect(SseConnectionError::InvalidUrl("".to_string())),
+            Err(SseSubscribeError::ConnectionError(
+                SseConnectionError::InvalidUrl(_)
+            ))
+        ));
+    }
+    #[test]
+    fn total_deadlineより短いread_timeoutはreadに適用される() {
+        let mut connector = FakeSseConnector::new().read_timeout(Duration::from_secs(1));
+        connector.set_response("HTTP/1.1 200 OK\r\n");
+        connector.set_response("Content-Type: text/event-stream\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: Hello\r\n");
+        let mut sut = SseSubscriber::new(connector).total_deadline(Some(Duration::from_secs(10)));
+        let request = RequestBuilder::new(&"https://www.fake".try_into().unwrap())
+            .get()
+            .build();
+
+        sut.subscribe_fn(&request, |_| HandleProgress::<()>::Progress)
+            .unwrap();
+
+        let applied = sut.connector().applied_read_timeouts();
+        assert!(!applied.is_empty());
+        assert!(
+            applied
+                .iter()
+                .all(|timeout| *timeout == Some(Duration::from_secs(1))),
+            "{:?}",
+            applied
+        );
+    }
+    #[test]
+    fn total_deadlineを過ぎた場合はエラーを返す() {
+        let mut connector = FakeSseConnector::new();
+        connector.set_response("HTTP/1.1 200 OK\r\n");
+        connector.set_response("Content-Type: text/event-stream\r\n");
+        connector.set_response("\r\n");
+        for _ in 0..100 {
+            connector.set_response("data: Hello\r\n");
+        }
+        let mut sut = SseSubscriber::new(connector).total_deadline(Some(Duration::from_millis(50)));
+        let request = RequestBuilder::new(&"https://www.fake".try_into().unwrap())
+            .get()
+            .build();
+
+        let result = sut.subscribe_fn(&request, |_| {
+            std::thread::sleep(Duration::from_millis(10));
+            HandleProgress::<()>::Progress
+        });
+
+        let Err(SseSubscribeError::DeadlineExceeded) = result else {
+            panic!("expected DeadlineExceeded, but got {:?}", result);
+        };
+    }
+    #[test]
+    fn commentだけが届き続ける場合はidle_timeoutでエラーを返す() {
+        use std::{
+            io::{BufRead, BufReader, Write},
+            net::TcpListener,
+        };
+
+        use crate::sse::connector::SseTcpConnectorBuilder;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url: Url = format!("http://{}", listener.local_addr().unwrap())
+            .as_str()
+            .try_into()
+            .unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+                line.clear();
+            }
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\n");
+            for _ in 0..100 {
+                if stream.write_all(b": ping\r\n\r\n").is_err() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(20));
+            }
+        });
+        // each read finishes within the read timeout, because the comments keep arriving
+        let connector = SseTcpConnectorBuilder::new(&url)
+            .read_timeout(Duration::from_secs(1))
+            .build()
+            .unwrap();
+        let mut sut = SseSubscriber::new(connector).idle_timeout(Some(Duration::from_millis(100)));
+        let request = RequestBuilder::new(&url).get().build();
+        let started = Instant::now();
+
+        let result = sut.subscribe_fn(&request, |_| HandleProgress::<()>::Progress);
+
+        let Err(SseSubscribeError::IdleTimeout) = result else {
+            panic!("expected IdleTimeout, but got {:?}", result);
+        };
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+    #[test]
+    fn eventが完結しないfieldだけが届き続ける場合はidle_timeoutでエラーを返す() {
+        use std::{
+            io::{BufRead, BufReader, Write},
+            net::TcpListener,
+        };
+
+        use crate::sse::connector::SseTcpConnectorBuilder;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url: Url = format!("http://{}", listener.local_addr().unwrap())
+            .as_str()
+            .try_into()
+            .unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+                line.clear();
+            }
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\n");
+            for _ in 0..100 {
+                if stream.write_all(b"id: 1\r\n").is_err() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(20));
+            }
+        });
+        // fields without a blank line never dispatch an event, so they do not reset the idle timer
+        let connector = SseTcpConnectorBuilder::new(&url)
+            .read_timeout(Duration::from_secs(1))
+            .build()
+            .unwrap();
+        let mut sut = SseSubscriber::new(connector).idle_timeout(Some(Duration::from_millis(100)));
+        let request = RequestBuilder::new(&url).get().build();
+        let started = Instant::now();
+
+        let result = sut.subscribe_fn(&request, |_| HandleProgress::<()>::Progress);
+
+        let Err(SseSubscribeError::IdleTimeout) = result else {
+            panic!("expected IdleTimeout, but got {:?}", result);
+        };
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+    #[test]
+    fn fieldのない空行でheartbeatが呼ばれる() {
+        let mut connector = FakeSseConnector::new();
+        connector.set_response("HTTP/1.1 200 OK\r\n");
+        connector.set_response("Content-Type: text/event-stream\r\n");
+        connector.set_response("\r\n");
+        connector.set_response(": ping\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: Hello\r\n");
+        connector.set_response("\r\n");
+        let heartbeats = Arc::new(AtomicUsize::new(0));
+        let counter = heartbeats.clone();
+        let mut sut = SseSubscriber::new(connector);
+        sut.on_heartbeat(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let request = RequestBuilder::new(&"https://www.fake".try_into().unwrap())
+            .get()
+            .build();
+        let mut handler = MockMutHandler::new();
+
+        sut.subscribe_mut(&request, &mut handler).unwrap();
+
+        assert_eq!(heartbeats.load(Ordering::SeqCst), 2);
+        handler.assert_received(&[SseResponse::Data("Hello".to_string())]);
+    }
+    #[test]
+    fn heartbeatのみのstreamでも終了を待たずにheartbeatが呼ばれる() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url: Url = format!("http://{}", listener.local_addr().unwrap())
+            .as_str()
+            .try_into()
+            .unwrap();
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 && !line.trim().is_empty() {
+                line.clear();
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\n")
+                .unwrap();
+            // the stream is kept open until the heartbeats are observed
+            while stop_rx.try_recv().is_err() {
+                stream.write_all(b": ping\r\n\r\n").unwrap();
+                thread::sleep(Duration::from_millis(50));
+            }
+        });
+        let (heartbeat_tx, heartbeat_rx) = mpsc::channel();
+        let subscriber = thread::spawn(move || {
+            let connector = SseTcpConnectorBuilder::new(&url).build().unwrap();
+            let mut sut = SseSubscriber::new(connector);
+            sut.on_heartbeat(move || {
+                let _ = heartbeat_tx.send(());
+            });
+            let request = RequestBuilder::new(&url).get().build();
+            sut.subscribe_fn(&request, |_| HandleProgress::<()>::Progress)
+        });
+
+        for _ in 0..2 {
+            heartbeat_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        stop_tx.send(()).unwrap();
+        assert!(subscriber.join().unwrap().is_ok());
+    }
+    #[test]
+    fn eventごとにprogressが呼ばれる() {
+        let mut connector = FakeSseConnector::new();
+        connector.set_response("HTTP/1.1 200 OK\r\n");
+        connector.set_response("Content-Type: text/event-stream\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: Hello\r\n");
+        connector.set_response("data: Again\r\n");
+        connector.set_response("\r\n");
+        connector.set_response(": ping\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: World!\r\n");
+        connector.set_response("\r\n");
+        // not dispatched, because the stream ends before the blank line
+        connector.set_response("data: Incomplete\r\n");
+        let progresses = Arc::new(Mutex::new(Vec::new()));
+        let recorded = progresses.clone();
+        let mut sut = SseSubscriber::new(connector);
+        sut.on_progress(move |events, bytes| recorded.lock().unwrap().push((events, bytes)));
+        let request = RequestBuilder::new(&"https://www.fake".try_into().unwrap())
+            .get()
+            .build();
+
+        sut.subscribe_mut(&request, &mut MockMutHandler::new())
+            .unwrap();
+
+        let progresses = progresses.lock().unwrap();
+        let events = progresses
+            .iter()
+            .map(|(events, _)| *events)
+            .collect::<Vec<_>>();
+        assert_eq!(events, vec![1, 2]);
+        assert!(progresses[0].1 < progresses[1].1);
+    }
+    #[test]
+    fn stream_on_error_statusの場合はerror_statusでもevent_streamを読み込む() {
+        let mut connector = FakeSseConnector::new();
+        connector.set_response("HTTP/1.1 400 Bad Request\r\n");
+        connector.set_response("Content-Type: text/event-stream\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("event: error\r\n");
+        connector.set_response("data: invalid model\r\n");
+        connector.set_response("\r\n");
+        connector.connection.set_stream_on_error_status(true);
+        let status_codes = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let opened = status_codes.clone();
+        let mut sut = SseSubscriber::new(connector);
+        sut.on_open(move |status_code, header| {
+            assert!(header.is_event_stream());
+            opened.lock().unwrap().push(status_code);
+        });
+        let request = RequestBuilder::new(&"https://www.fake".try_into().unwrap())
+            .get()
+            .build();
+        let mut handler = MockMutHandler::new();
+
+        sut.subscribe_mut(&request, &mut handler).unwrap();
+
+        assert_eq!(*status_codes.lock().unwrap(), vec![400]);
+        handler.assert_received(&[
+            SseResponse::Event("error".to_string()),
+            SseResponse::Data("invalid model".to_string()),
+        ]);
+    }
+    #[test]
+    fn stream_on_error_statusでもevent_streamでなければhttp_errorを返す() {
+        let mut connector = FakeSseConnector::new();
+        connector.set_response("HTTP/1.1 400 Bad Request\r\n");
+        connector.set_response("Content-Type: application/json\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("{\"error\":\"invalid model\"}");
+        connector.connection.set_stream_on_error_status(true);
+        let mut sut = SseSubscriber::new(connector);
+        let request = RequestBuilder::new(&"https://www.fake".try_into().unwrap())
+            .get()
+            .build();
+
+        let Err(SseSubscribeError::HttpError(res)) =
+            sut.subscribe_fn(&request, |_res| HandleProgress::<()>::Progress)
+        else {
+            panic!("expected http error");
+        };
+
+        assert_eq!(res.status_code(), 400);
+        assert_eq!(res.body_str(), "{\"error\":\"invalid model\"}");
+    }
+    #[test]
+    fn 接続に失敗した場合はreconnectしてhookを呼ぶ() {
+        struct FlakyConnector {
+            fail_times: usize,
+            inner: FakeSseConnector,
+        }
+        impl SseConnector for FlakyConnector {
+            type Socket = FakeTcpConnection;
+            fn connect(
+                &mut self,
+                req: &Request,
+            ) -> std::result::Result<&mut SseConnection<FakeTcpConnection>, SseConnectionError>
+            {
+                if self.fail_times > 0 {
+                    self.fail_times -= 1;
+                    return Err(SseConnectionError::ConnectError(
+                        ConnectionPhase::Connect,
+                        std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused"),
+                    ));
+                }
+                self.inner.connect(req)
+            }
+        }
+        let mut inner = FakeSseConnector::new();
+        inner.set_response("HTTP/1.1 200 OK\r\n");
+        inner.set_response("Content-Type: text/event-stream\r\n");
+        inner.set_response("\r\n");
+        inner.set_response("data: Hello\r\n");
+        inner.set_response("\r\n");
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        let recorded = attempts.clone();
+        let mut sut = SseSubscriber::new(FlakyConnector {
+            fail_times: 2,
+            inner,
+        })
+        .reconnect(Some(ReconnectPolicy {
+            max_attempts: 3,
+            delay: Duration::ZERO,
+        }));
+        sut.on_reconnect(move |attempt, err, delay| {
+            assert!(matches!(err, SseConnectionError::ConnectError(..)));
+            recorded.lock().unwrap().push((attempt, delay));
+        });
+        let request = RequestBuilder::new(&"https://www.fake".try_into().unwrap())
+            .get()
+            .build();
+        let mut handler = MockMutHandler::new();
+
+        sut.subscribe_mut(&request, &mut handler).unwrap();
+
+        assert_eq!(
+            *attempts.lock().unwrap(),
+            vec![(1, Duration::ZERO), (2, Duration::ZERO)]
+        );
+        handler.assert_received(&[SseResponse::Data("Hello".to_string())]);
+    }
+    #[test]
+    fn 切断されたconnectionの代わりに新しいconnectionで再接続する() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url: Url = format!("http://{}", listener.local_addr().unwrap())
+            .as_str()
+            .try_into()
+            .unwrap();
+        thread::spawn(move || {
+            // closing with the request unread resets the first connection
+            let (first, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_millis(100));
+            drop(first);
+            let (mut second, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(second.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 && !line.trim().is_empty() {
+                line.clear();
+            }
+            second
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\n")
+                .unwrap();
+            second.write_all(b"data: Hello\r\n\r\n").unwrap();
+        });
+        let connector = SseTcpConnectorBuilder::new(&url).build().unwrap();
+        let mut sut = SseSubscriber::new(connector).reconnect(Some(ReconnectPolicy {
+            max_attempts: 1,
+            delay: Duration::ZERO,
+        }));
+        let request = RequestBuilder::new(&url).get().build();
+        let mut handler = MockMutHandler::new();
+
+        sut.subscribe_mut(&request, &mut handler).unwrap();
+
+        handler.assert_received(&[SseResponse::Data("Hello".to_string())]);
+    }
+    #[test]
+    fn retry_on_statusに含まれるstatusの場合は再接続する() {
+        // a new connection for each connect, because an error response is read until the end
+        struct SequenceConnector {
+            pending: Vec<FakeSseConnector>,
+            current: Option<FakeSseConnector>,
+        }
+        impl SseConnector for SequenceConnector {
+            type Socket = FakeTcpConnection;
+            fn connect(
+                &mut self,
+                req: &Request,
+            ) -> std::result::Result<&mut SseConnection<FakeTcpConnection>, SseConnectionError>
+            {
+                let current = self.current.insert(self.pending.remove(0));
+                current.connect(req)
+            }
+        }
+        fn connector() -> SequenceConnector {
+            let mut unavailable = FakeSseConnector::new();
+            unavailable.set_response("HTTP/1.1 503 Service Unavailable\r\n");
+            unavailable.set_response("\r\n");
+            let mut ok = FakeSseConnector::new();
+            ok.set_response("HTTP/1.1 200 OK\r\n");
+            ok.set_response("Content-Type: text/event-stream\r\n");
+            ok.set_response("\r\n");
+            ok.set_response("data: Hello\r\n");
+            SequenceConnector {
+                pending: vec![unavailable, ok],
+                current: None,
+            }
+        }
+        let policy = Some(ReconnectPolicy {
+            max_attempts: 1,
+            delay: Duration::ZERO,
+        });
+        let request = RequestBuilder::new(&"https://www.fake".try_into().unwrap())
+            .get()
+            .build();
+
+        let mut sut = SseSubscriber::new(connector())
+            .reconnect(policy)
+            .retry_on_status(&[502, 503]);
+        let mut handler = MockMutHandler::new();
+        sut.subscribe_mut(&request, &mut handler).unwrap();
+        handler.assert_received(&[SseResponse::Data("Hello".to_string())]);
+
+        let mut sut = SseSubscriber::new(connector())
+            .reconnect(policy)
+            .retry_on_status(&[502]);
+        let result = sut.subscribe_mut(&request, &mut MockMutHandler::new());
+        assert!(
+            matches!(result, Err(SseSubscribeError::HttpError(res)) if res.status_code() == 503)
+        );
+    }
+    #[test]
+    fn done_whenに一致したresponseでstreamを終了する() {
+        let mut connector = FakeSseConnector::new();
+        connector.set_response("HTTP/1.1 200 OK\r\n");
+        connector.set_response("Content-Type: text/event-stream\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: Hello\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("event: end\r\n");
+        connector.set_response("data: bye\r\n");
+        connector.set_response("\r\n");
+        let mut sut = SseSubscriber::new(connector);
+        sut.done_when(|res| matches!(res, SseResponse::Event(event) if event == "end"));
+        let request = RequestBuilder::new(&"https://www.fake".try_into().unwrap())
+            .get()
+            .build();
+        let mut handler = MockMutHandler::new();
+
+        sut.subscribe_mut(&request, &mut handler).unwrap();
+
+        handler.assert_received(&[SseResponse::Data("Hello".to_string())]);
+    }
+    #[test]
+    fn skipした場合は次のeventまで読み飛ばす() {
+        let mut connector = FakeSseConnector::new();
+        connector.set_response("HTTP/1.1 200 OK\r\n");
+        connector.set_response("Content-Type: text/event-stream\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: broken\r\n");
+        connector.set_response("data: rest of broken\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: valid\r\n");
+        connector.set_response("\r\n");
+        let mut sut = SseSubscriber::new(connector);
+        let request = RequestBuilder::new(&"https://www.fake".try_into().unwrap())
+            .get()
+            .build();
+        let mut data = Vec::new();
+
+        sut.subscribe_mut_fn(&request, |res| match res {
+            SseResponse::Data(d) if d == "broken" => HandleProgress::<()>::Skip,
+            SseResponse::Data(d) => {
+                data.push(d);
+                HandleProgress::Progress
+            }
+            _ => HandleProgress::Progress,
+        })
+        .unwrap();
+
+        assert_eq!(data, vec!["valid".to_string()]);
+    }
+    #[test]
     fn sseのhttp接続エラーの場合はhttpのレスポンスをエラーに包んで返す() {
         let mut connector = FakeSseConnector::new();
         connector.set_response("HTTP/1.1 400 Bad Request\r\n");
@@ -335,6 +1544,76 @@ mod tests {
         assert_eq!(err.status_code(), 400);
         assert_eq!(err.get_header("Content-Type"), Some("text/event-stream"));
     }
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn streamの終了時にsummaryをtracingに出力する() {
+        use std::collections::HashMap;
+        type Fields = HashMap<String, String>;
+        // records the fields of every event
+        struct Capture(Arc<Mutex<Vec<Fields>>>);
+        struct Visitor<'a>(&'a mut Fields);
+        impl tracing::field::Visit for Visitor<'_> {
+            fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+                self.0.insert(field.name().to_string(), value.to_string());
+            }
+            fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn Debug) {
+                self.0
+                    .insert(field.name().to_string(), format!("{:?}", value));
+            }
+        }
+        impl tracing::Subscriber for Capture {
+            fn enabled(&self, _metadata: &tracing::Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, _span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+                tracing::span::Id::from_u64(1)
+            }
+            fn record(&self, _span: &tracing::span::Id, _values: &tracing::span::Record<'_>) {}
+            fn record_follows_from(&self, _span: &tracing::span::Id, _follows: &tracing::span::Id) {
+            }
+            fn event(&self, event: &tracing::Event<'_>) {
+                let mut fields = Fields::new();
+                event.record(&mut Visitor(&mut fields));
+                self.0.lock().unwrap().push(fields);
+            }
+            fn enter(&self, _span: &tracing::span::Id) {}
+            fn exit(&self, _span: &tracing::span::Id) {}
+        }
+        let mut connector = FakeSseConnector::new();
+        connector.set_response("HTTP/1.1 200 OK\r\n");
+        connector.set_response("Content-Type: text/event-stream\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: Hello\r\n");
+        connector.set_response("data: World!\r\n");
+        connector.set_response("\r\n");
+        connector.set_response("data: Bye\r\n");
+        connector.set_response("\r\n");
+        let mut sut = SseSubscriber::new(connector);
+        let request = RequestBuilder::new(&"https://www.fake".try_into().unwrap())
+            .get()
+            .build();
+        let events = Arc::new(Mutex::new(Vec::new()));
+
+        tracing::subscriber::with_default(Capture(events.clone()), || {
+            sut.subscribe_fn(&request, |_| HandleProgress::<()>::Progress)
+                .unwrap();
+        });
+
+        let events = events.lock().unwrap();
+        let summary = events
+            .iter()
+            .find(|fields| {
+                fields
+                    .get("message")
+                    .is_some_and(|m| m == "sse stream ended")
+            })
+            .unwrap();
+        assert_eq!(summary["events"], "2");
+        assert_eq!(summary["reconnects"], "0");
+        assert_eq!(summary["outcome"], "completed");
+        assert_ne!(summary["total_bytes"], "0");
+        assert!(summary.contains_key("duration"));
+    }
 }
 #[cfg(test)]
 pub(crate) mod fakes {
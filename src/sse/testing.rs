@@ -0,0 +1,72 @@
+use super::response::SseResponse;
+
+// panics with a per-event diff instead of the whole vectors, which are unreadable for long streams
+#[track_caller]
+pub fn assert_stream_eq(actual: &[SseResponse], expected: &[SseResponse]) {
+    if let Some(diff) = stream_diff(actual, expected) {
+        panic!("{}", diff);
+    }
+}
+
+// lines of the diff are prefixed by `-` for the expected event and `+` for the actual one,
+// and the events which are equal are shown without prefix
+pub fn stream_diff(actual: &[SseResponse], expected: &[SseResponse]) -> Option<String> {
+    if actual == expected {
+        return None;
+    }
+    let mut diff = format!(
+        "streams are not equal (- expected {} events, + actual {} events)\n",
+        expected.len(),
+        actual.len()
+    );
+    for i in 0..actual.len().max(expected.len()) {
+        match (expected.get(i), actual.get(i)) {
+            (Some(expected), Some(actual)) if expected == actual => {
+                diff.push_str(&format!("  [{}] {:?}\n", i, actual));
+            }
+            (expected, actual) => {
+                if let Some(expected) = expected {
+                    diff.push_str(&format!("- [{}] {:?}\n", i, expected));
+                }
+                if let Some(actual) = actual {
+                    diff.push_str(&format!("+ [{}] {:?}\n", i, actual));
+                }
+            }
+        }
+    }
+    Some(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn 異なるeventのみが差分として表示される() {
+        let actual = [
+            SseResponse::data("Hello"),
+            SseResponse::data("Wrold"),
+            SseResponse::id("1"),
+        ];
+        let expected = [SseResponse::data("Hello"), SseResponse::data("World")];
+
+        let diff = stream_diff(&actual, &expected).unwrap();
+
+        assert_eq!(
+            diff,
+            concat!(
+                "streams are not equal (- expected 2 events, + actual 3 events)\n",
+                "  [0] Data(\"Hello\")\n",
+                "- [1] Data(\"World\")\n",
+                "+ [1] Data(\"Wrold\")\n",
+                "+ [2] Id(\"1\")\n",
+            )
+        );
+        assert_eq!(stream_diff(&expected, &expected), None);
+    }
+    #[test]
+    #[should_panic(expected = "- [0] Data(\"World\")\n+ [0] Data(\"Hello\")")]
+    fn 一致しないstreamはdiffを表示してpanicする() {
+        assert_stream_eq(&[SseResponse::data("Hello")], &[SseResponse::data("World")]);
+    }
+}